@@ -0,0 +1,410 @@
+//! Assembles SAP into 704 words, in two passes as the Symbolic Assembly
+//! Program did: the first gives every symbol a location and the second
+//! builds the words.
+//!
+//! Numbers in the variable field are decimal and `*` is the location of the
+//! instruction itself. The fields are the address, the tag and the
+//! decrement, `Y,T,D`.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::cpu::*;
+use crate::diagnostics::{Diagnostic, Span, STATEMENT_END};
+use crate::sap::{Line, Listing};
+use crate::word::SIGN;
+
+/// Operations whose code fills the sign and bits 1 to 11.
+const OPERATIONS: &[(&str, u64)] = &[
+    ("HTR", HTR),
+    ("TRA", TRA),
+    ("TSX", TSX),
+    ("TZE", TZE),
+    ("TNZ", TNZ),
+    ("TPL", TPL),
+    ("TMI", TMI),
+    ("TOV", TOV),
+    ("TNO", TNO),
+    ("TQP", TQP),
+    ("XCA", XCA),
+    ("MPY", MPY),
+    ("DVH", DVH),
+    ("DVP", DVP),
+    ("FDP", FDP),
+    ("FMP", FMP),
+    ("FAD", FAD),
+    ("FSB", FSB),
+    ("ANS", ANS),
+    ("ANA", ANA),
+    ("ACL", ACL),
+    ("ADD", ADD),
+    ("ADM", ADM),
+    ("SUB", SUB),
+    ("SBM", SBM),
+    ("HPR", HPR),
+    ("CLA", CLA),
+    ("CAL", CAL),
+    ("ORA", ORA),
+    ("CLS", CLS),
+    ("LXA", LXA),
+    ("LXD", LXD),
+    ("LDQ", LDQ),
+    ("STZ", STZ),
+    ("STQ", STQ),
+    ("STO", STO),
+    ("SLW", SLW),
+    ("ORS", ORS),
+    ("STA", STA),
+    ("STD", STD),
+    ("SXA", SXA),
+    ("SXD", SXD),
+    ("PAX", PAX),
+    ("PDX", PDX),
+    ("PXA", PXA),
+    ("PXD", PXD),
+    ("NOP", NOP),
+    ("CPY", CPY),
+    ("CAD", CAD),
+    ("RDS", RDS),
+    ("LLS", LLS),
+    ("LRS", LRS),
+    ("ALS", ALS),
+    ("ARS", ARS),
+    ("RQL", RQL),
+];
+
+/// Operations with a prefix in the sign and bits 1 and 2 and a decrement.
+const PREFIXED: &[(&str, u64)] = &[
+    ("PZE", 0),
+    ("TXI", TXI),
+    ("TIX", TIX),
+    ("TXH", TXH),
+    ("MZE", 4),
+    ("TNX", TNX),
+    ("TXL", TXL),
+];
+
+/// Operations that share a code and are told apart by the address, which
+/// the variable field is added to: `SLN 2` is 0760 with an address of 142.
+const SENSE: &[(&str, u64, u64)] = &[
+    ("CLM", PLUS_SENSE, 0),
+    ("CHS", PLUS_SENSE, 0o2),
+    ("SSP", PLUS_SENSE, 0o3),
+    ("COM", PLUS_SENSE, 0o6),
+    ("RND", PLUS_SENSE, 0o10),
+    ("DCT", PLUS_SENSE, 0o12),
+    ("SLF", PLUS_SENSE, 0o140),
+    ("SLN", PLUS_SENSE, 0o140),
+    ("SWT", PLUS_SENSE, 0o160),
+    ("SSM", MINUS_SENSE, 0o3),
+    ("SLT", MINUS_SENSE, 0o140),
+];
+
+/// The library routines a punched program can call. There are no printing
+/// routines, since the 716 printer is not driven, and no EXP3 for a floating
+/// point power, so a program that needs them cannot be punched.
+const LIBRARY: &str = "
+* FLOATF, THE DECREMENT OF THE AC TO FLOATING POINT
+FLOATF ARS 18
+       ORA 0FLTC
+       FAD 0FLTZ
+       TRA 1,4
+0FLTC  OCT 233000000000
+0FLTZ  OCT 000000000000
+* XFIXF, FLOATING POINT TO THE DECREMENT, DROPPING THE FRACTION
+XFIXF  STO 0FIXS
+       ARS 27
+       SSP
+       SUB 0FIXC
+       CHS
+       STA 0FIXH
+       CLA 0FIXS
+       ANA 0FIXF
+0FIXH  ARS 0
+       ALS 18
+       LDQ 0FIXS
+       LLS 0
+       TRA 1,4
+0FIXS  BSS 1
+0FIXC  OCT 000000000233
+0FIXF  OCT 000777777777
+* EXP1, A FIXED POINT AC TO THE FIXED POINT POWER IN THE MQ
+EXP1   STO 0E1B
+       STQ 0E1N
+       CLA 0E1N
+       TPL 0E1P
+* ONLY 1 AND -1 HAVE A NEGATIVE POWER THAT IS NOT 0
+       CLA 0E1B
+       SSP
+       SUB 0E1C
+       TZE 0E1M
+       PXD 0,0
+       TRA 1,4
+0E1M   CLA 0E1N
+       SSP
+       STO 0E1N
+0E1P   CLA 0E1C
+       STO 0E1R
+0E1L   CLA 0E1N
+       TZE 0E1D
+       SUB 0E1C
+       STO 0E1N
+       LDQ 0E1R
+       MPY 0E1B
+       ALS 17
+       STO 0E1R
+       TRA 0E1L
+0E1D   CLA 0E1R
+       TRA 1,4
+0E1B   BSS 1
+0E1N   BSS 1
+0E1R   BSS 1
+0E1C   OCT 000001000000
+* EXP2, A FLOATING POINT AC TO THE FIXED POINT POWER IN THE MQ
+EXP2   STO 0E2B
+       STQ 0E2N
+       CLA 0E2O
+       STO 0E2R
+       CLA 0E2N
+       SSP
+       STO 0E2K
+0E2L   CLA 0E2K
+       TZE 0E2D
+       SUB 0E2I
+       STO 0E2K
+       LDQ 0E2R
+       FMP 0E2B
+       STO 0E2R
+       TRA 0E2L
+0E2D   CLA 0E2N
+       TMI 0E2V
+       CLA 0E2R
+       TRA 1,4
+* A NEGATIVE POWER IS THE RECIPROCAL OF THE POSITIVE ONE
+0E2V   CLA 0E2O
+       FDP 0E2R
+       XCA
+       TRA 1,4
+0E2B   BSS 1
+0E2N   BSS 1
+0E2K   BSS 1
+0E2R   BSS 1
+0E2O   OCT 201400000000
+0E2I   OCT 000001000000
+";
+
+/// A program as words in core.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Assembly {
+    /// words by location; words set aside with BSS have none
+    pub words: BTreeMap<usize, u64>,
+    pub symbols: BTreeMap<String, usize>,
+    /// the location END names, where the program starts
+    pub entry: usize,
+    /// the location of each line of the listing, up to END
+    pub locations: Vec<usize>,
+}
+
+/// The library's routines by name.
+fn routines() -> BTreeMap<String, Vec<Line>> {
+    let mut routines = BTreeMap::new();
+    let mut name = None;
+    for line in LIBRARY
+        .lines()
+        .filter(|x| !x.is_empty() && !x.starts_with('*'))
+    {
+        let line = Line::parse(line);
+        // a routine runs from its name to the next one
+        if !line.location.is_empty() && !line.location.starts_with(|x: char| x.is_ascii_digit()) {
+            name = Some(line.location.clone());
+        }
+        if let Some(name) = &name {
+            routines.entry(name.clone()).or_insert(vec![]).push(line);
+        }
+    }
+    routines
+}
+
+/// The library routines the listing calls, put in between its code and its
+/// data. Routines the library does not have are left in `externals`, at
+/// location 0 so the program still assembles.
+pub fn link(listing: &Listing) -> Listing {
+    let routines = routines();
+    let (found, missing): (BTreeSet<String>, BTreeSet<String>) = listing
+        .externals
+        .iter()
+        .cloned()
+        .partition(|x| routines.contains_key(x));
+    let mut library = found.iter().flat_map(|x| routines[x].clone()).collect::<Vec<_>>();
+    library.extend(missing.iter().map(|x| Line {
+        location: x.clone(),
+        operation: "EQU".to_string(),
+        address: "0".to_string(),
+        card: None,
+    }));
+    let mut lines = listing.lines.clone();
+    let data = listing.data.min(lines.len());
+    lines.splice(data..data, library.iter().cloned());
+    Listing {
+        lines,
+        externals: missing,
+        data: data + library.len(),
+    }
+}
+
+/// An error on each statement that calls a routine the library does not
+/// have, which a punched deck cannot run without.
+pub fn unsupported(linked: &Listing) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = vec![];
+    for line in linked.lines.iter().filter(|x| x.operation == "TSX") {
+        let routine = line.address.split(',').next().unwrap_or("");
+        let (Some(card), true) = (line.card, linked.externals.contains(routine)) else {
+            continue;
+        };
+        let message = match routine {
+            "PRINT" | "PRFIX" | "PRFLT" | "PREND" => {
+                "PRINT is not supported in a punched deck, the 716 printer is not driven"
+                    .to_string()
+            }
+            "EXP3" => "a floating point power is not supported in a punched deck, \
+                       the library has no EXP3"
+                .to_string(),
+            a => format!(
+                "{} is not supported in a punched deck, the library has no {}",
+                a, a
+            ),
+        };
+        let diagnostic = Diagnostic::error(Span::new(card, 7..STATEMENT_END + 1), message);
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    diagnostics
+}
+
+pub fn assemble(listing: &Listing) -> Result<Assembly, String> {
+    let mut assembly = Assembly::default();
+
+    let mut location = 0;
+    for (number, line) in listing.lines.iter().enumerate() {
+        let error = |message: String| format!("line {}: {}", number + 1, message);
+        let symbols = &assembly.symbols;
+        let value = |location| evaluate(&line.address, symbols, location).map_err(error);
+        if line.operation == "ORG" {
+            location = value(location)?;
+        }
+        // EQU names a value rather than a location, and BSS takes as many
+        // words as its variable field says
+        let (symbol, size) = match line.operation.as_str() {
+            "EQU" => (value(location)?, 0),
+            "BSS" => (location, value(location)?),
+            "ORG" | "END" => (location, 0),
+            _ => (location, 1),
+        };
+        assembly.locations.push(location);
+        if !line.location.is_empty()
+            && assembly
+                .symbols
+                .insert(line.location.clone(), symbol)
+                .is_some()
+        {
+            return Err(error(format!("{} is defined twice", line.location)));
+        }
+        if line.operation == "END" {
+            break;
+        }
+        location += size;
+    }
+
+    let mut location = 0;
+    for (number, line) in listing.lines.iter().enumerate() {
+        let error = |message: String| format!("line {}: {}", number + 1, message);
+        let symbols = &assembly.symbols;
+        let field = |i: usize| -> Result<u64, String> {
+            match line.address.split(',').nth(i) {
+                Some(text) if !text.trim().is_empty() => {
+                    Ok(evaluate(text, symbols, location)? as u64 & 0o77777)
+                }
+                _ => Ok(0),
+            }
+        };
+        let operation = line.operation.as_str();
+        let word = if let Some((_, code)) = OPERATIONS.iter().find(|x| x.0 == operation) {
+            if field(2).map_err(error)? != 0 {
+                return Err(error(format!("{} has no decrement", operation)));
+            }
+            code << 24 | (field(1).map_err(error)? & 7) << 15 | field(0).map_err(error)?
+        } else if let Some((_, prefix)) = PREFIXED.iter().find(|x| x.0 == operation) {
+            prefix << 33
+                | field(2).map_err(error)? << 18
+                | (field(1).map_err(error)? & 7) << 15
+                | field(0).map_err(error)?
+        } else if let Some((_, code, base)) = SENSE.iter().find(|x| x.0 == operation) {
+            code << 24 | (base + field(0).map_err(error)?)
+        } else {
+            match operation {
+                "ORG" => {
+                    location = evaluate(&line.address, symbols, location).map_err(error)?;
+                    continue;
+                }
+                "BSS" => {
+                    location += evaluate(&line.address, symbols, location).map_err(error)?;
+                    continue;
+                }
+                "EQU" => continue,
+                "END" => {
+                    assembly.entry = evaluate(&line.address, symbols, location).map_err(error)?;
+                    break;
+                }
+                "OCT" => {
+                    let (sign, digits) = match line.address.strip_prefix('-') {
+                        Some(a) => (SIGN, a),
+                        None => (0, line.address.as_str()),
+                    };
+                    match u64::from_str_radix(digits, 8) {
+                        Ok(a) if a < SIGN => sign | a,
+                        _ => return Err(error(format!("{} is not an octal word", line.address))),
+                    }
+                }
+                _ => return Err(error(format!("{} is not a 704 operation", operation))),
+            }
+        };
+        assembly.words.insert(location, word);
+        location += 1;
+    }
+    Ok(assembly)
+}
+
+/// The value of a variable field expression, numbers, symbols and `*`
+/// added and subtracted.
+fn evaluate(
+    text: &str,
+    symbols: &BTreeMap<String, usize>,
+    location: usize,
+) -> Result<usize, String> {
+    let mut total: i64 = 0;
+    let mut sign = 1;
+    let mut term = String::new();
+    for c in text.trim().chars().chain(std::iter::once('+')) {
+        if c != '+' && c != '-' {
+            term.push(c);
+            continue;
+        }
+        let value = match term.trim() {
+            // a blank term is 0, as in `-1`
+            "" => 0,
+            "*" => location as i64,
+            a if a.chars().all(|x| x.is_ascii_digit()) => {
+                a.parse::<i64>().map_err(|_| format!("{} is too big", a))?
+            }
+            a => match symbols.get(a) {
+                Some(b) => *b as i64,
+                None => return Err(format!("{} is not defined", a)),
+            },
+        };
+        total += sign * value;
+        sign = if c == '-' { -1 } else { 1 };
+        term.clear();
+    }
+    // addresses wrap round, so A-1 of an array at 0 is 77777
+    Ok(total.rem_euclid(1 << 15) as usize)
+}
@@ -0,0 +1,125 @@
+// the card, tape and printer devices share these, not all of them use every helper yet
+#![allow(dead_code)]
+
+/// The 48 characters of the 704 and their six bit BCD codes (octal).
+///
+/// The same codes are used in core, on tape, by the card reader and by the
+/// printer, so every device converts through this table.
+pub const CHARACTERS: [(char, u8); 48] = [
+    ('0', 0o00),
+    ('1', 0o01),
+    ('2', 0o02),
+    ('3', 0o03),
+    ('4', 0o04),
+    ('5', 0o05),
+    ('6', 0o06),
+    ('7', 0o07),
+    ('8', 0o10),
+    ('9', 0o11),
+    ('=', 0o13),
+    ('\'', 0o14),
+    ('+', 0o20),
+    ('A', 0o21),
+    ('B', 0o22),
+    ('C', 0o23),
+    ('D', 0o24),
+    ('E', 0o25),
+    ('F', 0o26),
+    ('G', 0o27),
+    ('H', 0o30),
+    ('I', 0o31),
+    ('.', 0o33),
+    (')', 0o34),
+    ('-', 0o40),
+    ('J', 0o41),
+    ('K', 0o42),
+    ('L', 0o43),
+    ('M', 0o44),
+    ('N', 0o45),
+    ('O', 0o46),
+    ('P', 0o47),
+    ('Q', 0o50),
+    ('R', 0o51),
+    ('$', 0o53),
+    ('*', 0o54),
+    (' ', 0o60),
+    ('/', 0o61),
+    ('S', 0o62),
+    ('T', 0o63),
+    ('U', 0o64),
+    ('V', 0o65),
+    ('W', 0o66),
+    ('X', 0o67),
+    ('Y', 0o70),
+    ('Z', 0o71),
+    (',', 0o73),
+    ('(', 0o74),
+];
+
+pub const BLANK: u8 = 0o60;
+
+/// Characters in a 36 bit word.
+pub const WORD_CHARACTERS: usize = 6;
+
+pub fn encode(c: char) -> Option<u8> {
+    CHARACTERS.iter().find(|(x, _)| *x == c).map(|(_, code)| *code)
+}
+
+pub fn decode(code: u8) -> Option<char> {
+    CHARACTERS.iter().find(|(_, x)| *x == code).map(|(c, _)| *c)
+}
+
+pub fn is_bcd(c: char) -> bool {
+    encode(c).is_some()
+}
+
+/// # Returns the codes, or the position and character of the first one outside the set
+pub fn encode_str(text: &str) -> Result<Vec<u8>, (usize, char)> {
+    text.chars()
+        .enumerate()
+        .map(|(i, c)| encode(c).ok_or((i, c)))
+        .collect()
+}
+
+/// Codes with no character print as blanks, as they did on the 716 printer.
+pub fn decode_str(codes: &[u8]) -> String {
+    codes.iter().map(|code| decode(*code).unwrap_or(' ')).collect()
+}
+
+/// Packs up to six codes into the low 36 bits of a word, padding with blanks.
+pub fn pack_word(codes: &[u8]) -> u64 {
+    (0..WORD_CHARACTERS).fold(0, |word, i| {
+        (word << 6) | (*codes.get(i).unwrap_or(&BLANK) & 0o77) as u64
+    })
+}
+
+pub fn unpack_word(word: u64) -> [u8; WORD_CHARACTERS] {
+    let mut codes = [0; WORD_CHARACTERS];
+    for (i, code) in codes.iter_mut().enumerate() {
+        *code = ((word >> (6 * (WORD_CHARACTERS - 1 - i))) & 0o77) as u8;
+    }
+    codes
+}
+
+/// The holes punched in one card column, as a bit per row with row 12 in
+/// bit 11 down to row 9 in bit 0.
+pub fn punches(code: u8) -> u16 {
+    let zone = match code >> 4 {
+        0 => 0,
+        1 => 1 << 11,
+        2 => 1 << 10,
+        _ => 1 << 9,
+    };
+    let digit = code & 0o17;
+    match (code, digit) {
+        (BLANK, _) => 0,
+        // zero is a single punch in the zone 0 row
+        (0o00, _) => 1 << 9,
+        (_, 0) => zone,
+        (_, 1..=9) => zone | (1 << (9 - digit)),
+        // 8-3 and 8-4 combinations
+        (_, 0o13) => zone | (1 << 1) | (1 << 6),
+        (_, 0o14) => zone | (1 << 1) | (1 << 5),
+        _ => zone,
+    }
+}
@@ -0,0 +1,138 @@
+use crate::diagnostics::{Diagnostic, Span};
+use crate::LineData;
+
+/// Width of a card image; shorter lines are padded with blanks.
+pub const CARD_COLUMNS: usize = 80;
+
+/// Reads a deck of fixed-form card images.
+///
+/// * column 1 `C` marks a comment card
+/// * columns 1-5 hold the statement number
+/// * column 6 marks a continuation when it is not blank or `0`
+/// * columns 7-72 hold the statement
+/// * columns 73-80 are the sequence field and are dropped
+///
+/// # Returns (statement text, line data, diagnostics)
+pub fn import_cards(deck: &str) -> (String, Vec<LineData>, Vec<Diagnostic>) {
+    let mut text = vec![];
+    let mut line_data = vec![];
+    let mut diagnostics = vec![];
+
+    for (number, card) in deck.replace("\r\n", "\n").split('\n').enumerate() {
+        let columns = card_columns(card);
+
+        let comment = columns[0] == 'C' || columns[0] == 'c';
+        let statement: String = if comment {
+            columns[1..72].iter().collect()
+        } else {
+            columns[6..72].iter().collect()
+        };
+
+        let mut data = LineData {
+            number: number as i32,
+            continuation: false,
+            comment,
+            label: 0,
+        };
+        if !comment {
+            match read_label(&columns[0..5]) {
+                Some(label) => data.label = label,
+                None => diagnostics.push(Diagnostic::error(
+                    Span::new(number, 1..6),
+                    format!(
+                        "{} is not a statement number",
+                        columns[0..5].iter().collect::<String>().trim()
+                    ),
+                )),
+            }
+            data.continuation = columns[5] != ' ' && columns[5] != '0';
+        }
+
+        text.push(statement.trim_end().to_string());
+        line_data.push(data);
+    }
+
+    // a trailing newline at the end of the deck is not a blank card
+    if text.len() > 1 && deck.ends_with('\n') {
+        text.pop();
+        line_data.pop();
+    }
+
+    (text.join("\n"), line_data, diagnostics)
+}
+
+/// Splits a card into its 80 columns, expanding tabs to the statement field.
+fn card_columns(card: &str) -> Vec<char> {
+    let mut columns = vec![];
+    for c in card.chars() {
+        if c == '\t' {
+            // a leading tab is the usual shorthand for "skip to column 7"
+            while columns.len() < 6 {
+                columns.push(' ');
+            }
+            if columns.len() > 6 {
+                columns.push(' ');
+            }
+        } else {
+            columns.push(c);
+        }
+    }
+    columns.resize(CARD_COLUMNS.max(columns.len()), ' ');
+    columns
+}
+
+/// Statement numbers may be punched anywhere in columns 1-5; blanks are ignored.
+/// A blank field is no statement number, and anything but digits is none at all.
+fn read_label(columns: &[char]) -> Option<i32> {
+    let digits: String = columns.iter().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() {
+        return Some(0);
+    }
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse::<i32>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_fields_of_a_card() {
+        let deck = format!("C     A COMMENT CARD\n{:<72}SEQ00010\n", "   10 X = 1.0");
+        let (text, cards, diagnostics) = import_cards(&deck);
+        assert!(diagnostics.is_empty());
+        assert_eq!(text, "     A COMMENT CARD\nX = 1.0");
+        assert!(cards[0].comment);
+        assert!(!cards[1].comment);
+        assert_eq!(cards[1].label, 10);
+        assert_eq!(cards[1].number, 1);
+    }
+
+    #[test]
+    fn a_leading_tab_skips_to_column_7() {
+        let (text, cards, _) = import_cards("\tI = 2\n10\tJ = 3");
+        assert_eq!(text, "I = 2\nJ = 3");
+        assert_eq!(cards[0].label, 0);
+        assert_eq!(cards[1].label, 10);
+        assert!(!cards[1].continuation);
+    }
+
+    #[test]
+    fn column_6_marks_a_continuation_unless_blank_or_zero() {
+        let (_, cards, _) = import_cards("      A = 1.0 +\n     1    2.0\n     0B = 3.0");
+        assert!(!cards[0].continuation);
+        assert!(cards[1].continuation);
+        assert!(!cards[2].continuation);
+    }
+
+    #[test]
+    fn a_statement_number_is_digits_anywhere_in_columns_1_to_5() {
+        let (_, cards, diagnostics) = import_cards(" 1 2 X = 1.0\n1A    Y = 2.0\n");
+        assert_eq!(cards[0].label, 12);
+        assert_eq!(cards[1].label, 0);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span, Span::new(1, 1..6));
+    }
+}
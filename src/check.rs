@@ -0,0 +1,302 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::compile::array_words;
+use crate::compute::LIBRARY_FUNCTIONS;
+use crate::cpu::CoreSize;
+use crate::diagnostics::{Diagnostic, Span};
+use crate::parse::{Expr, Statement, StatementKind};
+
+/// Checks the whole program before anything runs.
+///
+/// * every statement number referred to is defined, and only once
+/// * branches and DO statements end on executable statements, PRINT on a FORMAT
+/// * no transfer goes into the range of a DO from outside of it
+/// * DO ranges are either nested or separate
+/// * statement functions come before the first executable statement
+/// * only dimensioned names have subscripts
+/// * every array has sizes of at least 1 and fits in the largest core
+///
+/// and warns about statements that can never be reached.
+pub fn check(program: &[Statement]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    let mut labels: HashMap<i32, usize> = HashMap::new();
+    for (i, statement) in program.iter().enumerate() {
+        if statement.label == 0 {
+            continue;
+        }
+        if let Some(first) = labels.get(&statement.label) {
+            diagnostics.push(Diagnostic::error(
+                Span::new(statement.span.card, 1..6),
+                format!(
+                    "statement number {} is already used on card {}",
+                    statement.label,
+                    program[*first].span.card + 1
+                ),
+            ));
+        } else {
+            labels.insert(statement.label, i);
+        }
+    }
+
+    // each DO with the index of the last statement of its range
+    let mut ranges = vec![];
+    let mut executable = false;
+    let mut arrays = HashSet::new();
+    let mut functions = HashSet::new();
+    for statement in program {
+        match &statement.kind {
+            StatementKind::Dimension(a) => {
+                for (name, sizes) in a {
+                    arrays.insert(name.to_owned());
+                    if array_words(sizes).is_some() {
+                        continue;
+                    }
+                    let message = if sizes.iter().any(|x| *x < 1) {
+                        format!("the sizes of {} must be at least 1", name)
+                    } else {
+                        let words = sizes.iter().fold(1u64, |a, x| a.saturating_mul(*x as u64));
+                        format!(
+                            "{} takes {} words, more than the {} of the largest core",
+                            name,
+                            words,
+                            CoreSize::K32.words()
+                        )
+                    };
+                    diagnostics.push(Diagnostic::error(statement.span.clone(), message));
+                }
+            }
+            StatementKind::Function { name, .. } => {
+                functions.insert(name.to_owned());
+            }
+            _ => {}
+        }
+    }
+
+    for (i, statement) in program.iter().enumerate() {
+        let span = &statement.span;
+        for label in statement.kind.branch_targets() {
+            if let Some(target) = lookup(&labels, label, span, &mut diagnostics) {
+                if !program[target].kind.is_executable() {
+                    diagnostics.push(Diagnostic::error(
+                        span.clone(),
+                        format!("statement {} cannot be branched to, it is not executable", label),
+                    ));
+                }
+            }
+        }
+
+        match &statement.kind {
+            StatementKind::Do { end, .. } => {
+                if let Some(target) = lookup(&labels, *end, span, &mut diagnostics) {
+                    if target <= i {
+                        diagnostics.push(Diagnostic::error(
+                            span.clone(),
+                            format!("statement {} is before the DO that ends on it", end),
+                        ));
+                    } else if !program[target].kind.is_executable() {
+                        diagnostics.push(Diagnostic::error(
+                            span.clone(),
+                            format!("a DO cannot end on statement {}, it is not executable", end),
+                        ));
+                    } else {
+                        ranges.push((i, target));
+                    }
+                }
+            }
+            StatementKind::Print { format, .. } => {
+                if let Some(target) = lookup(&labels, *format, span, &mut diagnostics) {
+                    if !matches!(program[target].kind, StatementKind::Format(_)) {
+                        diagnostics.push(Diagnostic::error(
+                            span.clone(),
+                            format!("statement {} is not a FORMAT statement", format),
+                        ));
+                    }
+                }
+            }
+            StatementKind::Function { name, .. } if executable => {
+                diagnostics.push(Diagnostic::error(
+                    span.clone(),
+                    format!(
+                        "statement function {} must be defined before the first executable statement",
+                        name
+                    ),
+                ));
+            }
+            _ => {}
+        }
+        if statement.kind.is_executable() {
+            executable = true;
+        }
+
+        let mut subscripted = vec![];
+        match &statement.kind {
+            StatementKind::Assign {
+                variable,
+                subscripts,
+                value,
+            } => {
+                if !subscripts.is_empty() {
+                    subscripted.push((variable.to_owned(), false));
+                }
+                subscripts.iter().for_each(|x| calls(x, &mut subscripted));
+                calls(value, &mut subscripted);
+            }
+            StatementKind::Function { value, .. } => calls(value, &mut subscripted),
+            StatementKind::If { value, .. } => calls(value, &mut subscripted),
+            StatementKind::Do {
+                start, limit, step, ..
+            } => {
+                calls(start, &mut subscripted);
+                calls(limit, &mut subscripted);
+                calls(step, &mut subscripted);
+            }
+            StatementKind::Print { items, .. } => {
+                items.iter().for_each(|x| calls(x, &mut subscripted))
+            }
+            _ => {}
+        }
+        for (name, call) in subscripted {
+            if arrays.contains(&name)
+                || functions.contains(&name)
+                || LIBRARY_FUNCTIONS.contains(&name.as_str())
+            {
+                continue;
+            }
+            let message = if call && name.ends_with('F') {
+                format!("{} is not a statement function or a library function", name)
+            } else {
+                format!("{} has subscripts but is not in a DIMENSION statement", name)
+            };
+            diagnostics.push(Diagnostic::error(span.clone(), message));
+        }
+    }
+
+    // a DO that starts inside another's range has to end inside it as well
+    for (i, (start, end)) in ranges.iter().enumerate() {
+        for (inner_start, inner_end) in &ranges[i + 1..] {
+            if inner_start < end && inner_end > end {
+                diagnostics.push(Diagnostic::error(
+                    program[*inner_start].span.clone(),
+                    format!(
+                        "this DO range overlaps the range of the DO on card {}",
+                        program[*start].span.card + 1
+                    ),
+                ));
+            }
+        }
+    }
+
+    for (i, statement) in program.iter().enumerate() {
+        for label in statement.kind.branch_targets() {
+            let Some(target) = labels.get(&label) else {
+                continue;
+            };
+            for (start, end) in &ranges {
+                let inside = |x: usize| x > *start && x <= *end;
+                if inside(*target) && !inside(i) {
+                    diagnostics.push(Diagnostic::error(
+                        statement.span.clone(),
+                        format!(
+                            "GO TO {} goes into the range of the DO on card {}",
+                            label,
+                            program[*start].span.card + 1
+                        ),
+                    ));
+                    break;
+                }
+            }
+        }
+    }
+
+    // a statement after one that always transfers can only be reached by its number
+    let executable: Vec<&Statement> = program.iter().filter(|x| x.kind.is_executable()).collect();
+    for pair in executable.windows(2) {
+        let transfers = !pair[0].kind.branch_targets().is_empty()
+            || matches!(pair[0].kind, StatementKind::Stop(_));
+        if transfers && pair[1].label == 0 {
+            diagnostics.push(Diagnostic::warning(
+                pair[1].span.clone(),
+                "this statement can never be reached, it has no statement number".to_string(),
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+/// Where a statement number is, reporting it when it is not defined.
+fn lookup(
+    labels: &HashMap<i32, usize>,
+    label: i32,
+    span: &Span,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<usize> {
+    let found = labels.get(&label).copied();
+    if found.is_none() {
+        diagnostics.push(Diagnostic::error(
+            span.clone(),
+            format!("there is no statement {}", label),
+        ));
+    }
+    found
+}
+
+/// Collects the names used with subscripts in an expression, and whether each
+/// was read as a function call.
+fn calls(expr: &Expr, names: &mut Vec<(String, bool)>) {
+    match expr {
+        Expr::Element(_, args) => args.iter().for_each(|x| calls(x, names)),
+        Expr::Call(name, args) => {
+            names.push((name.to_owned(), true));
+            args.iter().for_each(|x| calls(x, names));
+        }
+        Expr::Negate(a) => calls(a, names),
+        Expr::Binary(_, a, b) => {
+            calls(a, names);
+            calls(b, names);
+        }
+        Expr::Int(_) | Expr::Float(_) | Expr::Variable(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::card::import_cards;
+    use crate::compute::process;
+    use crate::diagnostics::{has_errors, Severity};
+
+    #[test]
+    fn warns_about_a_statement_after_a_transfer() {
+        let (text, line_data, _) = import_cards("      GO TO 10\n      I = 2\n10    STOP\n");
+        let (_, diagnostics) = process(text, line_data);
+        assert!(!has_errors(&diagnostics));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].span.card, 1);
+    }
+
+    #[test]
+    fn rejects_an_array_with_no_elements_or_too_many() {
+        let errors = |deck: &str| {
+            let (text, line_data, _) = import_cards(deck);
+            let (_, diagnostics) = process(text, line_data);
+            diagnostics
+                .into_iter()
+                .map(|x| x.message)
+                .collect::<Vec<_>>()
+        };
+        assert!(errors("      DIMENSION A(32767), B(128, 256)\n      STOP\n").is_empty());
+        assert_eq!(
+            errors("      DIMENSION A(2, 0)\n      STOP\n"),
+            vec!["the sizes of A must be at least 1"]
+        );
+        assert_eq!(
+            errors("      DIMENSION A(1000, 1000, 1000), B(2, 16385)\n      STOP\n"),
+            vec![
+                "A takes 1000000000 words, more than the 32768 of the largest core",
+                "B takes 32770 words, more than the 32768 of the largest core",
+            ]
+        );
+    }
+}
@@ -0,0 +1,541 @@
+use std::process::exit;
+
+use crate::card::import_cards;
+use crate::compile::{compile, Program};
+use crate::assembler::unsupported;
+use crate::compute::process;
+use crate::cpu::{CoreSize, Cpu, CpuStep};
+use crate::diagnostics::has_errors;
+use crate::machine::{Machine, RuntimeError, Step};
+use crate::object;
+use crate::optimize::Passes;
+use crate::sap;
+use crate::snapshot::Snapshot;
+use crate::storage::allocate;
+use crate::timing::{format_time, not_counted, CYCLE_MICROSECONDS};
+use crate::trace;
+use crate::watch::Watchpoint;
+use crate::word::octal;
+
+/// Statements run before a program is taken to be in a loop it will not leave.
+const DEFAULT_BUDGET: u64 = 100_000_000;
+
+/// Statements run between checks of the budget.
+const SLICE: usize = 10_000;
+
+const USAGE: &str = "usage: fortran [--budget statements] [--watch 'X > 100.0']... \
+[--trace trace.json] [--save snapshot.json] [--time] [--profile] [--coverage lcov.info] [--optimize fold,cse,hoist,index|all] [--sap] [--core 4K|8K|32K] [--map] [--punch deck.cbn] <deck> | --resume snapshot.json | \
+[--core 4K|8K|32K] --octal program.oct | [--core 4K|8K|32K] [--boot] --load deck.cbn";
+
+/// Compiles and runs a deck from the command line, `fortran deck.f`.
+pub fn main() {
+    let mut path = None;
+    let mut resume = false;
+    let mut octal = false;
+    let mut binary = false;
+    let mut boot = false;
+    let mut punch_path = None;
+    let mut storage_map = false;
+    let mut passes = Passes::default();
+    let mut core_size = CoreSize::K32;
+    let mut time = false;
+    let mut assembly = false;
+    let mut profile = false;
+    let mut coverage_path = None;
+    let mut save_path = None;
+    let mut budget = DEFAULT_BUDGET;
+    let mut watches = vec![];
+    let mut trace_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--budget" {
+            match args.next().and_then(|x| x.parse::<u64>().ok()) {
+                Some(a) => budget = a,
+                None => {
+                    eprintln!("{}", USAGE);
+                    exit(2);
+                }
+            }
+        } else if arg == "--trace" {
+            match args.next() {
+                Some(a) => trace_path = Some(a),
+                None => {
+                    eprintln!("{}", USAGE);
+                    exit(2);
+                }
+            }
+        } else if arg == "--save" {
+            match args.next() {
+                Some(a) => save_path = Some(a),
+                None => {
+                    eprintln!("{}", USAGE);
+                    exit(2);
+                }
+            }
+        } else if arg == "--sap" {
+            assembly = true;
+        } else if arg == "--optimize" {
+            match args.next().and_then(|x| Passes::parse(&x)) {
+                Some(a) => passes = a,
+                None => {
+                    eprintln!("{}", USAGE);
+                    exit(2);
+                }
+            }
+        } else if arg == "--map" {
+            storage_map = true;
+        } else if arg == "--time" {
+            time = true;
+        } else if arg == "--profile" {
+            profile = true;
+        } else if arg == "--coverage" {
+            match args.next() {
+                Some(a) => coverage_path = Some(a),
+                None => {
+                    eprintln!("{}", USAGE);
+                    exit(2);
+                }
+            }
+        } else if arg == "--octal" {
+            octal = true;
+        } else if arg == "--load" {
+            binary = true;
+        } else if arg == "--boot" {
+            boot = true;
+        } else if arg == "--punch" {
+            match args.next() {
+                Some(a) => punch_path = Some(a),
+                None => {
+                    eprintln!("{}", USAGE);
+                    exit(2);
+                }
+            }
+        } else if arg == "--core" {
+            match args.next().and_then(|x| CoreSize::parse(&x)) {
+                Some(a) => core_size = a,
+                None => {
+                    eprintln!("{}", USAGE);
+                    exit(2);
+                }
+            }
+        } else if arg == "--resume" {
+            resume = true;
+        } else if arg == "--watch" {
+            match args.next() {
+                Some(a) => watches.push(a),
+                None => {
+                    eprintln!("{}", USAGE);
+                    exit(2);
+                }
+            }
+        } else {
+            path = Some(arg);
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{}", USAGE);
+        exit(2);
+    };
+    if binary {
+        run_object_deck(&path, core_size, boot, budget);
+    }
+    let file = match std::fs::read_to_string(&path) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            exit(2);
+        }
+    };
+    if octal {
+        run_octal(&path, &file, core_size, budget);
+    }
+
+    // a snapshot has no card images to show under a runtime error
+    let (deck, mut machine) = if resume {
+        match Snapshot::from_json(&file) {
+            Ok(snapshot) => (
+                (snapshot.text.clone(), snapshot.line_data.clone()),
+                Machine::restore(snapshot),
+            ),
+            Err(message) => {
+                eprintln!("{}: {}", path, message);
+                exit(2);
+            }
+        }
+    } else {
+        let cards: Vec<&str> = file.lines().collect();
+        let (text, line_data, mut diagnostics) = import_cards(&file);
+        let (statements, mut found) = process(text.clone(), line_data.clone());
+        diagnostics.append(&mut found);
+        for diagnostic in &diagnostics {
+            eprintln!("{}", diagnostic.render(&path, &cards));
+        }
+        if has_errors(&diagnostics) {
+            exit(1);
+        }
+        match compile(&statements) {
+            Ok(program) => ((text, line_data), Machine::new(program)),
+            Err(diagnostic) => {
+                eprintln!("{}", diagnostic.render(&path, &cards));
+                exit(1);
+            }
+        }
+    };
+    let cards: Vec<&str> = if resume { vec![] } else { file.lines().collect() };
+    // the program is listed in SAP instead of being run
+    if assembly {
+        print_assembly(&machine.program, passes, &cards);
+        return;
+    }
+    // or given its place in core, and punched as an object deck
+    if storage_map || punch_path.is_some() {
+        punch(
+            &path,
+            punch_path.as_deref(),
+            storage_map,
+            core_size,
+            passes,
+            &machine,
+            &cards,
+        );
+        return;
+    }
+    machine.tracing = trace_path.is_some();
+    for watch in &watches {
+        match Watchpoint::parse(watch, &machine.program.arrays) {
+            Ok(a) => machine.watchpoints.push(a),
+            Err(message) => {
+                eprintln!("{}: {}", path, message);
+                exit(2);
+            }
+        }
+    }
+    let start = machine.executed;
+    // a budget of 0 runs for as long as the program does
+    if budget == 0 && machine.watchpoints.is_empty() {
+        if let Err(error) = machine.run_until_halt() {
+            save_trace(&machine, &trace_path);
+            runtime_error(&path, &cards, error);
+        }
+    }
+    while !machine.halted {
+        // stop exactly at the budget, so a saved run picks up where it was cut off
+        let slice = match budget {
+            0 => SLICE,
+            _ => SLICE.min((budget - (machine.executed - start)) as usize),
+        };
+        match machine.run_for(slice) {
+            Ok(Step::Halted) => break,
+            // there is no one to pause for, so hits are reported as they happen
+            Ok(Step::Watch) => {
+                for hit in &machine.hits {
+                    eprintln!("{}:{}: {}", path, hit.card + 1, hit);
+                }
+            }
+            Ok(_) => {}
+            Err(error) => {
+                save_trace(&machine, &trace_path);
+                runtime_error(&path, &cards, error);
+            }
+        }
+        if budget != 0 && machine.executed - start >= budget {
+            if let Some(save_path) = &save_path {
+                let snapshot = machine.snapshot(deck.0.clone(), deck.1.clone());
+                if let Err(e) = std::fs::write(save_path, snapshot.to_json()) {
+                    eprintln!("{}: {}", save_path, e);
+                }
+            }
+            machine.interrupt();
+            save_trace(&machine, &trace_path);
+            print!("{}", machine.io.print);
+            let card = machine.card().map_or(0, |x| x + 1);
+            eprintln!(
+                "{}:{}: interrupted after {} statements",
+                path, card, machine.executed
+            );
+            exit(3);
+        }
+    }
+    save_trace(&machine, &trace_path);
+    print!("{}", machine.io.print);
+    if time {
+        print_time(&machine);
+    }
+    if profile {
+        print_profile(&machine);
+    }
+    if let Some(coverage_path) = coverage_path {
+        if let Err(e) = std::fs::write(&coverage_path, coverage(&machine, &path)) {
+            eprintln!("{}: {}", coverage_path, e);
+        }
+    }
+}
+
+/// Runs a hand assembled program on the 704 itself and shows the registers
+/// it stopped with, and every word of core the program changed.
+fn run_octal(path: &str, text: &str, core_size: CoreSize, budget: u64) -> ! {
+    let mut cpu = Cpu::new(core_size);
+    match cpu.load_octal(text) {
+        Ok(start) => cpu.ic = start,
+        Err(message) => {
+            eprintln!("{}: {}", path, message);
+            exit(2);
+        }
+    }
+    let loaded = cpu.memory.clone();
+    run_cpu(path, cpu, &loaded, budget);
+}
+
+/// Lists a compiled program in SAP. With optimizations the listing without
+/// them is shown beside it, each card's code under the card.
+fn print_assembly(program: &Program, passes: Passes, cards: &[&str]) {
+    let before = sap::generate(program);
+    if !passes.any() {
+        print!("{}", before);
+        return;
+    }
+    let after = sap::generate_with(program, passes);
+    println!("{:<32}* AFTER", "* BEFORE");
+    for (card, before, after) in sap::compare(&before, &after) {
+        if let Some(card) = card {
+            println!("* {}", cards.get(card).unwrap_or(&"").trim_end());
+        }
+        let (before, after) = (before.lines().collect::<Vec<_>>(), after.lines().collect::<Vec<_>>());
+        for i in 0..before.len().max(after.len()) {
+            let line = format!("{:<32}{}", before.get(i).unwrap_or(&""), after.get(i).unwrap_or(&""));
+            println!("{}", line.trim_end());
+        }
+    }
+}
+
+/// Allocates a compiled program's storage in core, printing the storage map
+/// if asked, and punches it as a binary deck with its library routines.
+fn punch(
+    path: &str,
+    punch_path: Option<&str>,
+    storage_map: bool,
+    core_size: CoreSize,
+    passes: Passes,
+    machine: &Machine,
+    cards: &[&str],
+) {
+    let (listing, assembly, map) = match allocate(&machine.program, core_size, passes) {
+        Ok(a) => a,
+        Err(message) => {
+            eprintln!("{}: {}", path, message);
+            exit(1);
+        }
+    };
+    let diagnostics = match punch_path {
+        Some(_) => unsupported(&listing),
+        None => vec![],
+    };
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic.render(path, cards));
+    }
+    if !diagnostics.is_empty() {
+        exit(1);
+    }
+    if storage_map {
+        print!("{}", map);
+    }
+    let Some(punch_path) = punch_path else {
+        return;
+    };
+    let deck = object::column_binary(&object::punch(&assembly));
+    if let Err(e) = std::fs::write(punch_path, deck) {
+        eprintln!("{}: {}", punch_path, e);
+        exit(2);
+    }
+}
+
+/// Loads an object deck and runs it on the 704, either loaded straight into
+/// core or by LOAD CARDS and the deck's own loader.
+fn run_object_deck(path: &str, core_size: CoreSize, boot: bool, budget: u64) -> ! {
+    let bytes = match std::fs::read(path) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            exit(2);
+        }
+    };
+    let mut cpu = Cpu::new(core_size);
+    let loaded = object::read_column_binary(&bytes).and_then(|cards| {
+        let entry = object::load(&mut cpu, &cards)?;
+        Ok((cards, entry))
+    });
+    let (cards, entry) = match loaded {
+        Ok(a) => a,
+        Err(message) => {
+            eprintln!("{}: {}", path, message);
+            exit(2);
+        }
+    };
+    // what the program changes is shown against the deck as loaded either way
+    let loaded = cpu.memory.clone();
+    if boot {
+        cpu = Cpu::new(core_size);
+        cpu.load_cards(cards);
+    } else {
+        cpu.ic = entry;
+    }
+    run_cpu(path, cpu, &loaded, budget);
+}
+
+/// Runs the 704 until it halts and shows the registers it stopped with, and
+/// every word of core that differs from `loaded`.
+fn run_cpu(path: &str, mut cpu: Cpu, loaded: &[u64], budget: u64) -> ! {
+    let budget = match budget {
+        0 => usize::MAX,
+        a => a as usize,
+    };
+    let result = cpu.run(budget);
+    print!("{}", cpu);
+    for (location, (word, before)) in cpu.memory.iter().zip(loaded).enumerate() {
+        if word != before {
+            println!("{:05o} {}", location, octal(*word));
+        }
+    }
+    match result {
+        Ok(CpuStep::Halted) => exit(0),
+        Ok(CpuStep::Ran) => {
+            eprintln!("{}: interrupted after {} instructions", path, cpu.executed);
+            exit(3);
+        }
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            exit(1);
+        }
+    }
+}
+
+/// Every statement that can run, how often it ran and the time it took, with
+/// `#####` for those that never ran as gcov marks them.
+fn print_profile(machine: &Machine) {
+    eprintln!("{:>5} {:>6} {:>10} {:>10} {:>6}", "card", "label", "count", "cycles", "share");
+    let statements = machine
+        .program
+        .instructions
+        .iter()
+        .zip(machine.statement_counts.iter().zip(&machine.statement_cycles));
+    for (instruction, (count, cycles)) in statements {
+        if !instruction.executable {
+            continue;
+        }
+        let label = match instruction.label {
+            0 => "".to_string(),
+            a => a.to_string(),
+        };
+        let count = match count {
+            0 => "#####".to_string(),
+            a => a.to_string(),
+        };
+        eprintln!(
+            "{:>5} {:>6} {:>10} {:>10} {:>5.1}%",
+            instruction.span.card + 1,
+            label,
+            count,
+            cycles,
+            *cycles as f64 * 100.0 / machine.cycles.max(1) as f64
+        );
+    }
+}
+
+/// Statement counts as an lcov tracefile, one line per card a statement starts on.
+fn coverage(machine: &Machine, path: &str) -> String {
+    let mut text = format!("TN:\nSF:{}\n", path);
+    let mut found = 0;
+    let mut hit = 0;
+    for (instruction, count) in machine.program.instructions.iter().zip(&machine.statement_counts) {
+        if !instruction.executable {
+            continue;
+        }
+        found += 1;
+        if *count > 0 {
+            hit += 1;
+        }
+        text.push_str(&format!("DA:{},{}\n", instruction.span.card + 1, count));
+    }
+    text.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", found, hit));
+    text
+}
+
+/// The simulated 704 time of the run, and of each statement that took any.
+fn print_time(machine: &Machine) {
+    eprintln!("simulated time {}", format_time(machine.cycles));
+    eprintln!("{}", not_counted());
+    eprintln!("{:>5} {:>6} {:>10} {:>12} {:>6}", "card", "label", "cycles", "ms", "share");
+    for (instruction, cycles) in machine.program.instructions.iter().zip(&machine.statement_cycles) {
+        if *cycles == 0 {
+            continue;
+        }
+        let label = match instruction.label {
+            0 => "".to_string(),
+            a => a.to_string(),
+        };
+        eprintln!(
+            "{:>5} {:>6} {:>10} {:>12.3} {:>5.1}%",
+            instruction.span.card + 1,
+            label,
+            cycles,
+            (*cycles * CYCLE_MICROSECONDS) as f64 / 1000.0,
+            *cycles as f64 * 100.0 / machine.cycles.max(1) as f64
+        );
+    }
+}
+
+/// Writes the statements the trace still holds, the last `TRACE_LIMIT` run.
+fn save_trace(machine: &Machine, path: &Option<String>) {
+    let Some(path) = path else { return };
+    if let Err(e) = std::fs::write(path, trace::export(&machine.trace)) {
+        eprintln!("{}: {}", path, e);
+    }
+}
+
+/// Prints what the program printed before it failed, then where it failed.
+fn runtime_error(path: &str, cards: &[&str], error: RuntimeError) -> ! {
+    print!("{}", error.state.io.print);
+    eprintln!("{}", error.diagnostic().render(path, cards));
+    exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coverage_lists_the_statements_that_can_run() {
+        let deck = "      I = 2
+      IF (I - 2) 10, 20, 10
+   10 I = 0
+    1 FORMAT(I5)
+   20 DO 30 J = 1, 4
+   30 I = I + J
+      STOP
+";
+        let (text, line_data, _) = import_cards(deck);
+        let (statements, _) = process(text, line_data);
+        let mut machine = Machine::new(compile(&statements).unwrap());
+        machine.run_until_halt().unwrap();
+        assert_eq!(machine.statement_counts, vec![1, 1, 0, 0, 1, 4, 1]);
+        // the FORMAT is not a line that can be covered
+        assert_eq!(
+            coverage(&machine, "deck.f"),
+            "TN:
+SF:deck.f
+DA:1,1
+DA:2,1
+DA:3,0
+DA:5,1
+DA:6,4
+DA:7,1
+LF:6
+LH:5
+end_of_record
+"
+        );
+        machine.reset();
+        assert_eq!(machine.statement_counts, vec![0; 7]);
+        assert_eq!(machine.statement_cycles, vec![0; 7]);
+    }
+}
@@ -0,0 +1,250 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::compute::{declarations, Functions};
+use crate::cpu::CoreSize;
+use crate::diagnostics::{Diagnostic, Span};
+use crate::parse::{Expr, Statement, StatementKind};
+use crate::timing;
+
+/// A statement ready to run, with its statement numbers resolved to
+/// positions in the program.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Op {
+    Assign {
+        variable: String,
+        subscripts: Vec<Expr>,
+        value: Expr,
+    },
+    GoTo(usize),
+    ComputedGoTo {
+        targets: Vec<usize>,
+        index: String,
+    },
+    If {
+        value: Expr,
+        targets: [usize; 3],
+    },
+    IfSenseLight {
+        light: i32,
+        targets: [usize; 2],
+    },
+    IfSenseSwitch {
+        switch: i32,
+        targets: [usize; 2],
+    },
+    SenseLight(i32),
+    Do {
+        /// the last statement of the range
+        end: usize,
+        variable: String,
+        start: Expr,
+        limit: Expr,
+        step: Expr,
+    },
+    Print(Vec<Expr>),
+    Pause(Option<i32>),
+    Stop(Option<i32>),
+    /// CONTINUE, and the statements that are not executed
+    Continue,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Instruction {
+    pub label: i32,
+    pub span: Span,
+    pub op: Op,
+    /// 704 cycles to run it once, see `timing`
+    pub cycles: u64,
+    /// false for FORMAT, DIMENSION and statement functions, which are never run
+    pub executable: bool,
+}
+
+/// A program compiled once and run as many times as needed.
+///
+/// There is one instruction for every statement, so positions in the program
+/// are positions in the deck.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    pub arrays: HashMap<String, Vec<i32>>,
+    pub functions: Functions,
+}
+
+/// The words an array with these sizes takes, or `None` when a size is not
+/// positive or the array would not fit in the largest 704 core.
+pub fn array_words(sizes: &[i32]) -> Option<usize> {
+    let mut words = 1usize;
+    for &size in sizes {
+        if size < 1 {
+            return None;
+        }
+        words = words.checked_mul(size as usize)?;
+        if words > CoreSize::K32.words() {
+            return None;
+        }
+    }
+    Some(words)
+}
+
+/// Resolves statement numbers and collects the declarations of a checked program.
+pub fn compile(statements: &[Statement]) -> Result<Program, Diagnostic> {
+    let mut labels: HashMap<i32, usize> = HashMap::new();
+    for (i, statement) in statements.iter().enumerate() {
+        if statement.label != 0 {
+            labels.entry(statement.label).or_insert(i);
+        }
+    }
+    let (arrays, functions) = declarations(statements);
+
+    let mut instructions = vec![];
+    for statement in statements {
+        let resolve = |label: &i32| {
+            labels.get(label).copied().ok_or(Diagnostic::error(
+                statement.span.clone(),
+                format!("there is no statement {}", label),
+            ))
+        };
+        let op = match &statement.kind {
+            StatementKind::Assign {
+                variable,
+                subscripts,
+                value,
+            } => Op::Assign {
+                variable: variable.to_owned(),
+                subscripts: subscripts.clone(),
+                value: value.clone(),
+            },
+            StatementKind::GoTo(a) => Op::GoTo(resolve(a)?),
+            StatementKind::ComputedGoTo { targets, index } => Op::ComputedGoTo {
+                targets: targets.iter().map(resolve).collect::<Result<_, _>>()?,
+                index: index.to_owned(),
+            },
+            StatementKind::If { value, targets } => Op::If {
+                value: value.clone(),
+                targets: [
+                    resolve(&targets[0])?,
+                    resolve(&targets[1])?,
+                    resolve(&targets[2])?,
+                ],
+            },
+            StatementKind::IfSenseLight { light, targets } => Op::IfSenseLight {
+                light: *light,
+                targets: [resolve(&targets[0])?, resolve(&targets[1])?],
+            },
+            StatementKind::IfSenseSwitch { switch, targets } => Op::IfSenseSwitch {
+                switch: *switch,
+                targets: [resolve(&targets[0])?, resolve(&targets[1])?],
+            },
+            StatementKind::SenseLight(a) => Op::SenseLight(*a),
+            StatementKind::Do {
+                end,
+                variable,
+                start,
+                limit,
+                step,
+            } => Op::Do {
+                end: resolve(end)?,
+                variable: variable.to_owned(),
+                start: start.clone(),
+                limit: limit.clone(),
+                step: step.clone(),
+            },
+            StatementKind::Print { items, .. } => Op::Print(items.clone()),
+            StatementKind::Pause(a) => Op::Pause(*a),
+            StatementKind::Stop(a) => Op::Stop(*a),
+            StatementKind::Continue
+            | StatementKind::Format(_)
+            | StatementKind::Dimension(_)
+            | StatementKind::Function { .. } => Op::Continue,
+        };
+        instructions.push(Instruction {
+            label: statement.label,
+            span: statement.span.clone(),
+            cycles: timing::cycles(&op),
+            executable: statement.kind.is_executable(),
+            op,
+        });
+    }
+
+    Ok(Program {
+        instructions,
+        arrays,
+        functions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::import_cards;
+    use crate::compute::{process, Value};
+    use crate::machine::Machine;
+    use crate::optimize::transfers;
+
+    fn statements(deck: &str) -> Vec<Statement> {
+        let (text, line_data, _) = import_cards(deck);
+        process(text, line_data).0
+    }
+
+    #[test]
+    fn statement_numbers_become_positions_in_the_program() {
+        let program = compile(&statements(
+            "      DIMENSION A(2, 3)
+    5 I = 1
+      IF (I) 5, 20, 30
+   20 GO TO (5, 30), I
+   30 DO 40 J = 1, 2
+   40 A(J, 1) = 1.0
+      STOP
+",
+        ))
+        .unwrap();
+        let ops: Vec<&Op> = program.instructions.iter().map(|x| &x.op).collect();
+        assert_eq!(transfers(ops[2]), vec![1, 3, 4]);
+        assert_eq!(transfers(ops[3]), vec![1, 4]);
+        assert!(matches!(ops[4], Op::Do { end: 5, .. }));
+        assert_eq!(program.instructions[3].label, 20);
+        assert!(!program.instructions[0].executable);
+        assert_eq!(program.arrays["A"], vec![2, 3]);
+    }
+
+    #[test]
+    fn a_branch_to_a_missing_statement_is_an_error() {
+        let error = compile(&statements("      I = 1\n      GO TO 10\n")).unwrap_err();
+        assert_eq!(error.message, "there is no statement 10");
+        assert_eq!(error.span.card, 1);
+    }
+
+    #[test]
+    fn a_compiled_program_runs_the_same_each_time() {
+        let program = compile(&statements(
+            "      DO 10 I = 1, 5
+   10 K = K + I
+      STOP
+",
+        ))
+        .unwrap();
+        let mut machine = Machine::new(program);
+        machine.run_until_halt().unwrap();
+        let first = (machine.variables.clone(), machine.executed, machine.cycles);
+        machine.reset();
+        machine.run_until_halt().unwrap();
+        assert_eq!(
+            (machine.variables.clone(), machine.executed, machine.cycles),
+            first
+        );
+        assert_eq!(machine.variables["K"], vec![Value::Fixed(15)]);
+    }
+
+    #[test]
+    fn an_array_takes_the_product_of_its_sizes() {
+        assert_eq!(array_words(&[]), Some(1));
+        assert_eq!(array_words(&[2, 3, 4]), Some(24));
+        assert_eq!(array_words(&[32768]), Some(32768));
+        assert_eq!(array_words(&[32769]), None);
+        assert_eq!(array_words(&[2, 0]), None);
+        assert_eq!(array_words(&[-1, -1]), None);
+        assert_eq!(array_words(&[i32::MAX, i32::MAX, i32::MAX]), None);
+    }
+}
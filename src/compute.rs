@@ -1,35 +1,23 @@
-use log::info;
-use log::Level;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use sycamore::prelude::ReadSignal;
-use sycamore::web::html::li;
 
+use crate::bcd;
+use crate::word;
+use crate::check::check;
+use crate::diagnostics::{Diagnostic, Span};
+use crate::parse::{parse, Expr, Operator, Statement, StatementKind};
 use crate::LineData;
-use crate::IO704;
+
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Identifier(String),
-    Variable {
-        id: String,
-    },
-    Function {
-        id: String,
-        args: Vec<String>,
-        definition: bool,
-    },
     Int(i32),
     Float(f32),
-    UnknownToken(String),
+    Unknown(String),
     Newline,
-    OpenParen {
-        id: Option<i64>,
-        function: bool,
-    },
-    CloseParen {
-        id: Option<i64>,
-        function: bool,
-    },
+    OpenParen,
+    CloseParen,
     Print,
     Format,
     GoTo,
@@ -42,71 +30,136 @@ pub enum Token {
     Add,
     Subtract,
     Equals,
-    Space,
     Stop,
+    Pause,
+    Continue,
     Do,
     Comma,
     Dimension,
     SenseSwitch,
-    E,
+    Hollerith(String),
+    /// A field of a format specification, such as `2E14.5`: a repeat count,
+    /// the conversion `E`, `F` or `I`, a width and the digits after the point.
+    Field {
+        repeat: u32,
+        conversion: char,
+        width: u32,
+        decimals: Option<u32>,
+    },
 }
 
 impl Token {
-    fn identifier_builtin(str: String) -> Token {
-        match str.as_str() {
-            "\n" => Token::Newline,
-            "DO" => Token::Do,
-            "IF" => Token::If,
-            "GoTo" => Token::GoTo,
-            "DIMENSION" => Token::Dimension,
-            "SenseLight" => Token::SenseLight,
-            "E" => Token::E,
-            "PRINT" => Token::Print,
-            "STOP" => Token::Stop,
-            "SenseSwitch" => Token::SenseSwitch,
-            _ => Token::Identifier(str),
-        }
-    }
-
-    fn number(str: String) -> Token {
-        if str.contains(".") {
-            let float = str.parse::<f32>();
-            match float {
-                Ok(f) => Token::Float(f),
-                Err(_) => Token::UnknownToken(str),
-            }
-        } else {
-            let int = str.parse::<i32>();
-            match int {
-                Ok(i) => Token::Int(i),
-                Err(_) => Token::UnknownToken(str),
+    /// Reads a fixed or floating point constant, checked against the 704 word formats.
+    ///
+    /// `mantissa` is the digits and decimal point; `exponent` is the signed
+    /// decimal exponent that followed an `E`, if there was one.
+    fn number(mantissa: &str, exponent: Option<&str>) -> Result<Token, String> {
+        if mantissa.matches('.').count() > 1 {
+            return Err(format!("{} has more than one decimal point", mantissa));
+        }
+        if !mantissa.contains(|c: char| c.is_ascii_digit()) {
+            return Err(format!("{} is not a number", mantissa));
+        }
+        if !mantissa.contains('.') {
+            if exponent.is_some() {
+                return Err(format!(
+                    "{}E{} needs a decimal point to be a floating point constant",
+                    mantissa,
+                    exponent.unwrap_or_default()
+                ));
             }
+            return match mantissa.parse::<i32>() {
+                Ok(i) if i <= MAX_FIXED => Ok(Token::Int(i)),
+                _ => Err(format!(
+                    "fixed point constant {} is larger than {}",
+                    mantissa, MAX_FIXED
+                )),
+            };
+        }
+
+        let text = format!("{}e{}", mantissa, exponent.unwrap_or("0"));
+        match text.parse::<f64>() {
+            Ok(f) if f == 0.0 || (MIN_FLOAT..=MAX_FLOAT).contains(&f) => Ok(Token::Float(f as f32)),
+            Ok(_) => Err(format!(
+                "{} is outside the 704 floating point range",
+                text.replace('e', "E")
+            )),
+            Err(_) => Err(format!("{} is not a number", text.replace('e', "E"))),
         }
     }
 
     fn tokenize_symbols(str: String) -> Token {
         match str.as_str() {
-            "(" => Token::OpenParen {
-                id: None,
-                function: false,
-            },
-            ")" => Token::CloseParen {
-                id: None,
-                function: false,
-            },
+            "(" => Token::OpenParen,
+            ")" => Token::CloseParen,
             "*" => Token::Multiply,
-            "\n" => Token::Newline,
             "/" => Token::Divide,
             "+" => Token::Add,
             "," => Token::Comma,
-            " " => Token::Space,
             "-" => Token::Subtract,
             "=" => Token::Equals,
-            _ => Token::UnknownToken(str),
+            _ => Token::Unknown(str),
+        }
+    }
+
+    /// How the token reads in a diagnostic.
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Identifier(a) => a.to_owned(),
+            Token::Int(a) => a.to_string(),
+            Token::Float(a) => a.to_string(),
+            Token::Unknown(a) => a.to_owned(),
+            Token::Hollerith(a) => format!("{}H{}", a.len(), a),
+            Token::Field {
+                repeat,
+                conversion,
+                width,
+                decimals,
+            } => {
+                let repeat = if *repeat == 1 { "".to_string() } else { repeat.to_string() };
+                match decimals {
+                    Some(d) => format!("{}{}{}.{}", repeat, conversion, width, d),
+                    None => format!("{}{}{}", repeat, conversion, width),
+                }
+            }
+            Token::Label(a) => format!("statement number {}", a),
+            Token::Newline => "the end of the statement".to_string(),
+            Token::OpenParen => "(".to_string(),
+            Token::CloseParen => ")".to_string(),
+            Token::Power => "**".to_string(),
+            Token::Multiply => "*".to_string(),
+            Token::Divide => "/".to_string(),
+            Token::Add => "+".to_string(),
+            Token::Subtract => "-".to_string(),
+            Token::Equals => "=".to_string(),
+            Token::Comma => ",".to_string(),
+            Token::Print => "PRINT".to_string(),
+            Token::Format => "FORMAT".to_string(),
+            Token::GoTo => "GO TO".to_string(),
+            Token::SenseLight => "SENSE LIGHT".to_string(),
+            Token::SenseSwitch => "SENSE SWITCH".to_string(),
+            Token::If => "IF".to_string(),
+            Token::Stop => "STOP".to_string(),
+            Token::Pause => "PAUSE".to_string(),
+            Token::Continue => "CONTINUE".to_string(),
+            Token::Do => "DO".to_string(),
+            Token::Dimension => "DIMENSION".to_string(),
         }
     }
 }
 
+/// Names are one to six letters and digits, starting with a letter.
+const MAX_NAME_LENGTH: usize = 6;
+/// Function names may have a seventh character as long as it is the final `F`.
+const MAX_FUNCTION_NAME_LENGTH: usize = 7;
+/// A statement is one initial card and up to nine continuation cards.
+const MAX_CONTINUATIONS: usize = 9;
+/// Fixed point words hold a sign and 15 bits of magnitude.
+const MAX_FIXED: i32 = 32767;
+/// Floating point words have an 8 bit characteristic and a 27 bit fraction.
+const MAX_FLOAT: f64 = 1.7014118e38;
+const MIN_FLOAT: f64 = 1.4693679e-39;
+
 #[derive(PartialEq, Copy, Clone, Debug)]
 enum CharType {
     Letter,
@@ -114,627 +167,1020 @@ enum CharType {
     Operator,
 }
 
-fn combine_and_expand(tokens: &mut Vec<Token>) {
-    if tokens.len() == 0 {
-        return;
+fn get_char_type(c: char) -> CharType {
+    if c.is_alphabetic() {
+        CharType::Letter
+    } else if c.is_numeric() || c == '.' {
+        CharType::Digit
+    } else {
+        CharType::Operator
     }
-    let mut i = 0;
-    let mut bedmas = 0;
-    while i < tokens.len() {
-        let mut del = vec![];
-        let mut changed = true;
-        if i < tokens.len() - 1 && i > 0 {
-            match (&tokens[i - 1], &tokens[i], &tokens[i + 1], bedmas) {
-                (Token::Float(x), Token::E, Token::Int(y), 0) => {
-                    tokens[i] = Token::Float(x * (10.0_f32).powi(y.clone()));
-                }
-                (Token::Int(x), Token::Power, Token::Int(y), 0) => {
-                    tokens[i] = Token::Int(x.pow(y.clone() as u32));
-                }
-                (Token::Int(x), Token::Multiply, Token::Int(y), 1) => {
-                    tokens[i] = Token::Int(x * y);
-                }
-                (Token::Float(x), Token::Multiply, Token::Float(y), 1) => {
-                    tokens[i] = Token::Float(x * y);
-                }
-                (Token::Float(x), Token::Add, Token::Float(y), 2) => {
-                    tokens[i] = Token::Float(x + y);
-                }
-                (Token::Float(x), Token::Subtract, Token::Float(y), 2) => {
-                    tokens[i] = Token::Float(x - y);
-                }
-                (Token::Float(x), Token::Divide, Token::Float(y), 1) => {
-                    tokens[i] = Token::Float(x / y);
-                }
-                (Token::Int(x), Token::Add, Token::Int(y), 2) => {
-                    tokens[i] = Token::Int(x + y);
-                }
-                (Token::Int(x), Token::Subtract, Token::Int(y), 2) => {
-                    tokens[i] = Token::Int(x - y);
-                }
-                (Token::Int(x), Token::Divide, Token::Int(y), 1) => {
-                    tokens[i] = Token::Int(x / y);
-                }
-                // must be last option
-                (
-                    Token::OpenParen {
-                        id: a,
-                        function: false,
-                    },
-                    _,
-                    Token::CloseParen {
-                        id: b,
-                        function: false,
-                    },
-                    _,
-                ) if a == b => {
-                    bedmas = 0;
-                }
-                _ => {
-                    changed = false;
-                }
-            }
-            if changed {
-                del.push(i - 1);
-                del.push(i + 1);
-                i = 0;
+}
+
+/// A fixed or floating point word.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    Fixed(i32),
+    Floating(f32),
+}
+
+impl Value {
+    /// Variables starting with I, J, K, L, M or N are fixed point.
+    pub fn zero(name: &str) -> Value {
+        if is_fixed_name(name) {
+            Value::Fixed(0)
+        } else {
+            Value::Floating(0.0)
+        }
+    }
+
+    pub fn as_fixed(&self) -> i32 {
+        match self {
+            Value::Fixed(a) => *a,
+            Value::Floating(a) => wrap_fixed(*a as i64),
+        }
+    }
+
+    pub fn as_floating(&self) -> f32 {
+        match self {
+            Value::Fixed(a) => *a as f32,
+            Value::Floating(a) => *a,
+        }
+    }
+
+    /// The word this value is stored as.
+    pub fn word(&self) -> u64 {
+        match self {
+            Value::Fixed(a) => word::fixed_word(*a),
+            Value::Floating(a) => word::floating_word(*a),
+        }
+    }
+
+    /// Reads a word back in the mode of the named variable.
+    pub fn from_word(name: &str, word: u64) -> Value {
+        if is_fixed_name(name) {
+            Value::Fixed(word::word_fixed(word))
+        } else {
+            Value::Floating(word::word_floating(word))
+        }
+    }
+
+    /// Reads a value typed in for the named variable.
+    pub fn parse_for(name: &str, text: &str) -> Option<Value> {
+        let text = text.trim();
+        if is_fixed_name(name) {
+            text.parse::<i32>()
+                .ok()
+                .filter(|x| x.abs() <= MAX_FIXED)
+                .map(Value::Fixed)
+        } else {
+            text.parse::<f32>().ok().filter(|x| x.is_finite()).map(Value::Floating)
+        }
+    }
+
+    /// Converts to the mode of the named variable, as storing into it would.
+    pub fn convert_for(&self, name: &str) -> Value {
+        if is_fixed_name(name) {
+            Value::Fixed(self.as_fixed())
+        } else {
+            Value::Floating(self.as_floating())
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Fixed(a) => write!(f, "{}", a),
+            Value::Floating(a) => write!(f, "{:E}", a),
+        }
+    }
+}
+
+pub fn is_fixed_name(name: &str) -> bool {
+    name.starts_with(['I', 'J', 'K', 'L', 'M', 'N'])
+}
+
+/// Fixed point results keep their sign and the low 15 bits of magnitude.
+fn wrap_fixed(value: i64) -> i32 {
+    (value.signum() * (value.abs() % (MAX_FIXED as i64 + 1))) as i32
+}
+
+/// Why a run stopped short of a STOP statement.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ErrorKind {
+    DivideByZero,
+    SubscriptOutOfRange {
+        name: String,
+        subscript: usize,
+        value: i32,
+        size: i32,
+    },
+    BranchOutOfRange {
+        index: String,
+        value: i32,
+        branches: usize,
+    },
+    UnknownFunction(String),
+    MissingArgument(String),
+    NoSuchSenseLight(i32),
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::DivideByZero => write!(f, "divide check, division by zero"),
+            ErrorKind::SubscriptOutOfRange {
+                name,
+                subscript,
+                value,
+                size,
+            } => write!(
+                f,
+                "subscript {} of {} is {}, outside 1 to {}",
+                subscript, name, value, size
+            ),
+            ErrorKind::BranchOutOfRange {
+                index,
+                value,
+                branches,
+            } => write!(f, "{} is {}, but there are {} branches", index, value, branches),
+            ErrorKind::UnknownFunction(a) => {
+                write!(f, "{} is not a statement function or a library function", a)
             }
+            ErrorKind::MissingArgument(a) => write!(f, "{} is missing an argument", a),
+            ErrorKind::NoSuchSenseLight(a) => write!(f, "there is no sense light {}", a),
         }
-        if i > 0 {
-            changed = true;
-            match (&tokens[i - 1], &tokens[i]) {
-                (Token::Multiply, Token::Multiply) => {
-                    tokens[i] = Token::Power;
-                }
-                _ => {
-                    changed = false;
+    }
+}
+
+/// Statement functions by name: their argument names and body.
+pub type Functions = HashMap<String, (Vec<String>, Expr)>;
+
+/// Reads the array sizes and statement functions the program declares.
+pub fn declarations(program: &[Statement]) -> (HashMap<String, Vec<i32>>, Functions) {
+    let mut arrays = HashMap::new();
+    let mut functions = HashMap::new();
+    for statement in program {
+        match &statement.kind {
+            StatementKind::Dimension(a) => {
+                for (name, sizes) in a {
+                    arrays.insert(name.to_owned(), sizes.clone());
                 }
             }
-            if changed {
-                del.push(i - 1);
-                i = 0;
+            StatementKind::Function { name, args, value } => {
+                functions.insert(name.to_owned(), (args.clone(), value.clone()));
             }
+            _ => {}
         }
-        if del == vec![] {
-            i += 1;
-            if i == tokens.len() {
-                bedmas += 1;
-                i = 0;
+    }
+    (arrays, functions)
+}
+
+/// Evaluates expressions against the variables of a run.
+pub struct Evaluator<'a> {
+    pub variables: &'a HashMap<String, Vec<Value>>,
+    pub arrays: &'a HashMap<String, Vec<i32>>,
+    pub functions: &'a Functions,
+    /// the arguments of the statement function being evaluated
+    pub arguments: HashMap<String, Value>,
+}
+
+impl Evaluator<'_> {
+    pub fn evaluate(&self, expr: &Expr) -> Result<Value, ErrorKind> {
+        match expr {
+            Expr::Int(a) => Ok(Value::Fixed(*a)),
+            Expr::Float(a) => Ok(Value::Floating(*a)),
+            Expr::Variable(a) => Ok(match self.arguments.get(a) {
+                Some(value) => *value,
+                None => self
+                    .variables
+                    .get(a)
+                    .and_then(|x| x.first().copied())
+                    .unwrap_or(Value::zero(a)),
+            }),
+            Expr::Element(name, subscripts) => {
+                let index = self.element(name, subscripts)?;
+                Ok(self
+                    .variables
+                    .get(name)
+                    .and_then(|x| x.get(index).copied())
+                    .unwrap_or(Value::zero(name)))
+            }
+            Expr::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|x| self.evaluate(x))
+                    .collect::<Result<Vec<Value>, ErrorKind>>()?;
+                self.call(name, args)
+            }
+            Expr::Negate(a) => Ok(match self.evaluate(a)? {
+                Value::Fixed(a) => Value::Fixed(-a),
+                Value::Floating(a) => Value::Floating(-a),
+            }),
+            Expr::Binary(operator, a, b) => {
+                let a = self.evaluate(a)?;
+                let b = self.evaluate(b)?;
+                arithmetic(*operator, a, b)
             }
-            if bedmas == 5 {
-                break;
+        }
+    }
+
+    /// The position of an array element in storage; arrays are stored by columns.
+    pub fn element(&self, name: &str, subscripts: &[Expr]) -> Result<usize, ErrorKind> {
+        let sizes = self.arrays.get(name).cloned().unwrap_or_default();
+        let mut index = 0;
+        let mut stride = 1;
+        for (i, subscript) in subscripts.iter().enumerate() {
+            let value = self.evaluate(subscript)?.as_fixed();
+            let size = sizes.get(i).copied().unwrap_or(1);
+            if value < 1 || value > size {
+                return Err(ErrorKind::SubscriptOutOfRange {
+                    name: name.to_owned(),
+                    subscript: i + 1,
+                    value,
+                    size,
+                });
             }
+            index += (value - 1) as usize * stride;
+            stride *= size as usize;
         }
-        del.sort();
-        for i in del.iter().rev() {
-            tokens.remove(*i);
+        Ok(index)
+    }
+
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<Value, ErrorKind> {
+        if let Some((params, body)) = self.functions.get(name) {
+            let evaluator = Evaluator {
+                arguments: params
+                    .iter()
+                    .cloned()
+                    .zip(args.iter().zip(params).map(|(x, p)| x.convert_for(p)))
+                    .collect(),
+                ..*self
+            };
+            return Ok(evaluator.evaluate(body)?.convert_for(name));
         }
+        library_function(name, &args)
     }
 }
 
-fn id_brackets(tokens: &mut Vec<Token>) {
-    let mut id_1 = 0;
-    let mut id_2 = 0;
-    let mut functions = vec![];
-    for i in 0..tokens.len() {
-        match tokens[i] {
-            Token::OpenParen {
-                id: None,
-                function: _,
-            } => {
-                id_1 += 1;
-                if id_1 == 1 {
-                    id_2 += 10000000;
+fn arithmetic(operator: Operator, a: Value, b: Value) -> Result<Value, ErrorKind> {
+    Ok(match (a, b) {
+        (Value::Fixed(_), Value::Fixed(0)) if operator == Operator::Divide => {
+            return Err(ErrorKind::DivideByZero)
+        }
+        (Value::Fixed(a), Value::Fixed(b)) => Value::Fixed(wrap_fixed(match operator {
+            Operator::Add => a as i64 + b as i64,
+            Operator::Subtract => a as i64 - b as i64,
+            Operator::Multiply => a as i64 * b as i64,
+            Operator::Divide => (a / b) as i64,
+            Operator::Power => fixed_power(a, b)?,
+        })),
+        (_, b) if operator == Operator::Divide && b.as_floating() == 0.0 => {
+            return Err(ErrorKind::DivideByZero)
+        }
+        (Value::Floating(a), Value::Fixed(b)) if operator == Operator::Power => {
+            Value::Floating(a.powi(b))
+        }
+        (a, b) => {
+            let (a, b) = (a.as_floating(), b.as_floating());
+            Value::Floating(match operator {
+                Operator::Add => a + b,
+                Operator::Subtract => a - b,
+                Operator::Multiply => a * b,
+                Operator::Divide => a / b,
+                Operator::Power => a.powf(b),
+            })
+        }
+    })
+}
+
+/// `I**J` in fixed point; a negative exponent is the truncated reciprocal.
+fn fixed_power(a: i32, b: i32) -> Result<i64, ErrorKind> {
+    if b < 0 {
+        return match a {
+            0 => Err(ErrorKind::DivideByZero),
+            1 => Ok(1),
+            -1 => Ok(if b % 2 == 0 { 1 } else { -1 }),
+            _ => Ok(0),
+        };
+    }
+    // only the low 15 bits are kept, so the wrapped power is exact enough
+    let magnitude = (a.unsigned_abs() as u64).wrapping_pow(b as u32) % (MAX_FIXED as u64 + 1);
+    let sign = if a < 0 && b % 2 == 1 { -1 } else { 1 };
+    Ok(sign * magnitude as i64)
+}
+
+/// The functions supplied with the FORTRAN library tape.
+pub const LIBRARY_FUNCTIONS: [&str; 27] = [
+    "SINF", "COSF", "ATANF", "TANHF", "EXPF", "LOGF", "SQRTF", "ABSF", "XABSF", "INTF", "XINTF",
+    "MODF", "XMODF", "FLOATF", "XFIXF", "SIGNF", "XSIGNF", "DIMF", "XDIMF", "MAX0F", "MAX1F",
+    "XMAX0F", "XMAX1F", "MIN0F", "MIN1F", "XMIN0F", "XMIN1F",
+];
+
+fn library_function(name: &str, args: &[Value]) -> Result<Value, ErrorKind> {
+    let missing = || ErrorKind::MissingArgument(name.to_owned());
+    let float = |i: usize| args.get(i).map(|x| x.as_floating()).ok_or_else(missing);
+    let fixed = |i: usize| args.get(i).map(|x| x.as_fixed()).ok_or_else(missing);
+    let largest_fixed = args.iter().map(|x| x.as_fixed()).max().ok_or_else(missing);
+    let smallest_fixed = args.iter().map(|x| x.as_fixed()).min().ok_or_else(missing);
+    let largest_float = args.iter().map(|x| x.as_floating()).reduce(f32::max).ok_or_else(missing);
+    let smallest_float = args.iter().map(|x| x.as_floating()).reduce(f32::min).ok_or_else(missing);
+    Ok(match name {
+        "SINF" => Value::Floating(float(0)?.sin()),
+        "COSF" => Value::Floating(float(0)?.cos()),
+        "ATANF" => Value::Floating(float(0)?.atan()),
+        "TANHF" => Value::Floating(float(0)?.tanh()),
+        "EXPF" => Value::Floating(float(0)?.exp()),
+        "LOGF" => Value::Floating(float(0)?.ln()),
+        "SQRTF" => Value::Floating(float(0)?.sqrt()),
+        "ABSF" => Value::Floating(float(0)?.abs()),
+        "XABSF" => Value::Fixed(fixed(0)?.abs()),
+        "INTF" => Value::Floating(float(0)?.trunc()),
+        "XINTF" => Value::Fixed(float(0)? as i32),
+        "MODF" => Value::Floating(float(0)? % float(1)?),
+        "XMODF" => Value::Fixed(
+            fixed(0)?
+                .checked_rem(fixed(1)?)
+                .ok_or(ErrorKind::DivideByZero)?,
+        ),
+        "FLOATF" => Value::Floating(fixed(0)? as f32),
+        "XFIXF" => Value::Fixed(float(0)? as i32),
+        "SIGNF" => Value::Floating(float(0)?.abs() * float(1)?.signum()),
+        "XSIGNF" => Value::Fixed(fixed(0)?.abs() * fixed(1)?.signum()),
+        "DIMF" => Value::Floating((float(0)? - float(1)?).max(0.0)),
+        "XDIMF" => Value::Fixed((fixed(0)? - fixed(1)?).max(0)),
+        "MAX0F" => Value::Floating(largest_fixed? as f32),
+        "MAX1F" => Value::Floating(largest_float?),
+        "XMAX0F" => Value::Fixed(largest_fixed?),
+        "XMAX1F" => Value::Fixed(largest_float? as i32),
+        "MIN0F" => Value::Floating(smallest_fixed? as f32),
+        "MIN1F" => Value::Floating(smallest_float?),
+        "XMIN0F" => Value::Fixed(smallest_fixed?),
+        "XMIN1F" => Value::Fixed(smallest_float? as i32),
+        _ => return Err(ErrorKind::UnknownFunction(name.to_owned())),
+    })
+}
+
+/// # Returns (tokens, spans, diagnostics)
+pub fn tokenize(
+    mut in_string: String,
+    line_data: Vec<LineData>,
+) -> (Vec<Token>, Vec<Span>, Vec<Diagnostic>) {
+    let mut tokens = Vec::new();
+    let mut spans = Vec::new();
+    let mut diagnostics = Vec::new();
+    in_string = in_string.replace("\r\n", "\n");
+
+    let mut statement = vec![];
+    let mut label = 0;
+    let mut card = 0;
+    let mut started = false;
+    let mut continuations = 0;
+
+    for (current_line, text) in in_string.split('\n').enumerate() {
+        if current_line >= line_data.len() {
+            break;
+        }
+        let data = line_data[current_line];
+        if data.comment {
+            continue;
+        }
+
+        // a continuation card carries on the statement of the card before it
+        if data.continuation {
+            if started {
+                continuations += 1;
+                if continuations == MAX_CONTINUATIONS + 1 {
+                    diagnostics.push(Diagnostic::error(
+                        Span::new(current_line, 6..7),
+                        format!(
+                            "a statement can have at most {} continuation cards",
+                            MAX_CONTINUATIONS
+                        ),
+                    ));
                 }
-                let func = !(i == 0 || !matches!(tokens[i - 1], Token::Identifier(_)));
-                if func {
-                    functions.push(id_1 + id_2);
+                if data.label != 0 {
+                    diagnostics.push(Diagnostic::error(
+                        Span::new(current_line, 1..6),
+                        format!("statement number {} is on a continuation card", data.label),
+                    ));
                 }
-                tokens[i] = Token::OpenParen {
-                    id: Some(id_1 + id_2),
-                    function: func,
-                };
-            }
-            Token::CloseParen {
-                id: None,
-                function: _,
-            } => {
-                tokens[i] = Token::CloseParen {
-                    id: Some(id_2 + id_1),
-                    function: functions.contains(&(id_2 + id_1)),
-                };
-                id_1 -= 1;
+                statement.append(&mut source_chars(text, current_line));
+                continue;
             }
-            _ => {}
+            diagnostics.push(Diagnostic::error(
+                Span::new(current_line, 6..7),
+                "continuation card has no statement to continue".to_string(),
+            ));
         }
+
+        push_statement(&mut tokens, &mut spans, &mut diagnostics, card, label, &statement);
+        statement = source_chars(text, current_line);
+        label = data.label;
+        card = current_line;
+        started = true;
+        continuations = 0;
     }
+    push_statement(&mut tokens, &mut spans, &mut diagnostics, card, label, &statement);
+
+    (tokens, spans, diagnostics)
 }
 
-fn get_char_type(c: char) -> CharType {
-    if c.is_alphabetic() {
-        return CharType::Letter;
-    } else if c.is_numeric() || c == '.' {
-        return CharType::Digit;
-    } else {
-        return CharType::Operator;
+/// A character of the statement field and where it was punched.
+#[derive(Debug, Clone, Copy)]
+struct SourceChar {
+    c: char,
+    card: usize,
+    column: usize,
+}
+
+impl SourceChar {
+    fn span(&self) -> Span {
+        Span::new(self.card, self.column..self.column + 1)
     }
 }
 
-fn inline_functions(tokens: &mut Vec<Token>) {
-    // let mut id = 0;
-    // let mut current_args = vec![];
-    let mut new_ids = -1;
-
-    let mut functions: HashMap<String, (Vec<Token>, Vec<Token>)> = HashMap::new();
-    let mut sub_in = vec![];
-    for i in 1..tokens.len() {
-        match (&tokens[i - 1], &tokens[i]) {
-            (
-                Token::Identifier(a),
-                Token::OpenParen {
-                    id: Some(b),
-                    function: true,
-                },
-            ) => {
-                let mut inside = vec![];
-                if functions.contains_key(a) {
-                    let (args, mut inside): (Vec<Token>, Vec<Token>) =
-                        functions.get(a).unwrap().clone();
-                    if let Some(index) = tokens.iter().position(|r| {
-                        r == &Token::CloseParen {
-                            id: Some(b.clone()),
-                            function: true,
-                        }
-                    }) {
-                        let mut arg_num = 0;
-                        let mut current_args: Vec<Token> = vec![];
-                        for i in tokens.iter().skip(i + 1) {
-                            match i {
-                                Token::CloseParen {
-                                    id: Some(close_id),
-                                    function: true,
-                                } if close_id == b => {
-                                    inside = inside
-                                        .iter()
-                                        .flat_map(|x| match x {
-                                            Token::Identifier(_) if x == &args[arg_num] => {
-                                                current_args.clone()
-                                            }
-                                            _ => vec![x.clone()],
-                                        })
-                                        .collect::<Vec<Token>>();
-                                    break;
-                                }
-                                Token::Comma => {
-                                    inside = inside
-                                        .iter()
-                                        .flat_map(|x| match x {
-                                            Token::Identifier(_) if x == &args[arg_num] => {
-                                                current_args.clone()
-                                            }
-                                            _ => vec![x.clone()],
-                                        })
-                                        .collect::<Vec<Token>>();
-
-                                    current_args = vec![];
-                                    arg_num += 1;
-                                }
-                                i => {
-                                    current_args.push(i.clone());
-                                }
-                            }
-                        }
+/// The statement field starts in column 7.
+fn source_chars(text: &str, card: usize) -> Vec<SourceChar> {
+    text.chars()
+        .enumerate()
+        .map(|(i, c)| SourceChar {
+            c,
+            card,
+            column: i + 7,
+        })
+        .collect()
+}
 
-                        for i in tokens.iter().skip(i + 1) {
-                            if *i == Token::Newline {
-                                break;
-                            }
+/// Blanks carry no meaning on the 704, so statements are recognised on the
+/// blank-stripped statement field.
+fn push_statement(
+    tokens: &mut Vec<Token>,
+    spans: &mut Vec<Span>,
+    diagnostics: &mut Vec<Diagnostic>,
+    card: usize,
+    label: i32,
+    statement: &[SourceChar],
+) {
+    let (statement, positions) = strip_blanks(statement, diagnostics);
+    if statement.is_empty() && label == 0 {
+        return;
+    }
+    if label != 0 {
+        tokens.push(Token::Label(label));
+        spans.push(Span::new(card, 1..6));
+    }
+    let end = positions.last().map_or(Span::new(card, 7..8), |x| {
+        Span::new(x.card, x.column + 1..x.column + 2)
+    });
+
+    let mut lexer = Lexer {
+        card,
+        positions,
+        tokens: vec![],
+        spans: vec![],
+        diagnostics,
+    };
+    lexer.statement(&statement);
+    tokens.append(&mut lexer.tokens);
+    spans.append(&mut lexer.spans);
+
+    tokens.push(Token::Newline);
+    spans.push(end);
+}
+
+/// Removes every blank outside of the Hollerith fields of a FORMAT statement.
+///
+/// Letters outside Hollerith fields are read in upper case, as a keypunch
+/// would have punched them; anything else outside the 704 character set is
+/// reported and dropped.
+///
+/// # Returns (statement, where each character of it was punched)
+fn strip_blanks(
+    statement: &[SourceChar],
+    diagnostics: &mut Vec<Diagnostic>,
+) -> (String, Vec<SourceChar>) {
+    let mut stripped = String::new();
+    let mut positions = vec![];
+    let mut chars = statement.iter();
+    while let Some(source) = chars.next() {
+        let c = source.c.to_ascii_uppercase();
+        if c == ' ' {
+            continue;
+        }
+        if !check_bcd(source, c, diagnostics) {
+            continue;
+        }
+        stripped.push(c);
+        positions.push(SourceChar { c, ..*source });
+        if c == 'H' && stripped.starts_with("FORMAT(") {
+            if let Some(count) = hollerith_count(&stripped[..stripped.len() - 1]) {
+                for _ in 0..count {
+                    match chars.next() {
+                        Some(source) if check_bcd(source, source.c, diagnostics) => {
+                            stripped.push(source.c);
+                            positions.push(*source);
                         }
-                        new_ids -= 1;
-                        sub_in.push((
-                            i - 1,
-                            index,
-                            [
-                                vec![Token::OpenParen {
-                                    id: Some(new_ids),
-                                    function: false,
-                                }],
-                                inside.clone(),
-                                vec![Token::CloseParen {
-                                    id: Some(new_ids),
-                                    function: false,
-                                }],
-                            ]
-                            .concat(),
-                        ))
-                    }
-                }
-                if let Some(index) = tokens.iter().position(|r| {
-                    r == &Token::CloseParen {
-                        id: Some(b.clone()),
-                        function: true,
-                    }
-                }) {
-                    if index < tokens.len() - 1 && tokens[index + 1] == Token::Equals {
-                        let args = tokens[i + 1..index]
-                            .to_vec()
-                            .iter()
-                            .filter(|x| !matches!(x, Token::Comma))
-                            .map(|x| x.clone())
-                            .collect::<Vec<Token>>();
-                        for i in tokens.iter().skip(index + 2) {
-                            if *i == Token::Newline {
-                                break;
-                            }
-                            inside.push(i.clone());
+                        Some(source) => {
+                            stripped.push(' ');
+                            positions.push(*source);
                         }
-                        functions.insert(a.clone(), (args, inside));
+                        None => {}
                     }
                 }
             }
-            _ => {}
         }
     }
+    (stripped, positions)
+}
 
-    for i in sub_in.iter().rev() {
-        if tokens.len() >= i.1 + 1 {
-            tokens.splice(i.0..i.1 + 1, i.2.clone());
-        } else {
-        }
+fn check_bcd(source: &SourceChar, c: char, diagnostics: &mut Vec<Diagnostic>) -> bool {
+    if bcd::is_bcd(c) {
+        return true;
     }
+    diagnostics.push(Diagnostic::error(
+        source.span(),
+        format!("{:?} is not in the 704 character set", source.c),
+    ));
+    false
 }
-#[derive(Debug, Clone)]
-pub struct DoStatement {
-    start: usize,
-    end: usize,
-    max: i32,
-    current: i32,
-    variable: String,
-    step: i32,
+
+/// The field count of an `nH` Hollerith field, given the text before the `H`.
+fn hollerith_count(before: &str) -> Option<usize> {
+    let digits = before.len() - before.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let start = before.len() - digits;
+    if digits == 0 || !before[..start].ends_with(['(', ',', '/']) {
+        return None;
+    }
+    before[start..].parse::<usize>().ok()
 }
 
-/// # Returns (recurse, io, line_num)
-pub fn run(
-    tokens: Vec<Token>,
-    io: IO704,
-    mut line_num: usize,
-    mut variables: HashMap<String, Vec<Token>>,
-    mut do_statements: Vec<DoStatement>,
-) -> (
-    bool,
-    IO704,
-    usize,
-    Vec<DoStatement>,
-    HashMap<String, Vec<Token>>,
-    bool,
-) {
-    let mut io = io;
-    let mut lines: Vec<Vec<Token>> = vec![];
-    let mut line = vec![];
-    let mut labels: HashMap<i32, usize> = HashMap::new();
-
-    let mut update_io = false;
-
-    for i in 0..tokens.len() {
-        match &tokens[i] {
-            Token::Newline => {
-                line.push(tokens[i].clone());
-                lines.push(line.clone());
-                line = vec![];
-            }
-            Token::Label(a) => {
-                labels.insert(a.clone(), lines.len());
-            }
-            _ => {
-                line.push(tokens[i].clone());
-            }
+/// An arithmetic statement has an `=` outside of brackets with no comma after it,
+/// which is what tells `DO10I=1.5` apart from `DO10I=1,5`.
+fn is_assignment(statement: &str) -> bool {
+    let mut depth = 0;
+    let mut equals = false;
+    for c in statement.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '=' if depth == 0 => equals = true,
+            ',' if depth == 0 && equals => return false,
+            _ => {}
         }
     }
-    lines.push(line.clone());
-    let mut return_ = false;
-    loop {
-        if line_num >= lines.len() {
-            break;
+    equals
+}
+
+/// Lexes one blank-stripped statement.
+///
+/// Offsets are byte offsets into the statement; after `strip_blanks` every
+/// character is in the 704 set, so they are also indices into `positions`.
+struct Lexer<'a> {
+    card: usize,
+    positions: Vec<SourceChar>,
+    tokens: Vec<Token>,
+    spans: Vec<Span>,
+    diagnostics: &'a mut Vec<Diagnostic>,
+}
+
+impl Lexer<'_> {
+    /// Works out what kind of statement this is from its leading characters.
+    fn statement(&mut self, statement: &str) {
+        if is_assignment(statement) {
+            return self.lex(statement, 0);
         }
-        let line = lines[line_num].clone();
-        let mut index = 0;
-        for i in line.iter().skip(1) {
-            index += 1;
-            match i {
-                Token::Identifier(a) => {
-                    if variables.contains_key(a) {
-                        let new_line: Vec<Token> = variables.get(a).unwrap().clone();
-                        lines[line_num].splice(index..index + 1, new_line.clone());
-                        // lines.insert(line_num+1, new_line);
-                        return_ = true;
-                        combine_and_expand(&mut lines[line_num]);
-                    }
+
+        let keywords = [
+            vec![("IF", Token::If), ("(", Token::OpenParen), ("SENSELIGHT", Token::SenseLight)],
+            vec![("IF", Token::If), ("(", Token::OpenParen), ("SENSESWITCH", Token::SenseSwitch)],
+            vec![("IF", Token::If)],
+            vec![("GOTO", Token::GoTo)],
+            vec![("SENSELIGHT", Token::SenseLight)],
+            vec![("PRINT", Token::Print)],
+            vec![("STOP", Token::Stop)],
+            vec![("PAUSE", Token::Pause)],
+            vec![("CONTINUE", Token::Continue)],
+            vec![("DIMENSION", Token::Dimension)],
+        ];
+        for keyword in keywords {
+            let text: String = keyword.iter().map(|(x, _)| *x).collect();
+            if statement.starts_with(&text) {
+                let mut offset = 0;
+                for (x, token) in keyword {
+                    self.push(token, offset, offset + x.len());
+                    offset += x.len();
                 }
-                _ => {}
+                return self.lex(&statement[offset..], offset);
             }
         }
 
-        let line = lines[line_num].clone();
-
-        if line.len() > 1 {
-            match (&line[0], &line[1]) {
-                (Token::Identifier(a), Token::Equals) => {
-                    variables.insert(
-                        a.to_owned(),
-                        line[2..]
-                            .iter()
-                            .filter(|x| !matches!(x, Token::Newline))
-                            .map(|x| x.clone())
-                            .collect::<Vec<Token>>(),
-                    );
-                    
-                }
-                (Token::SenseLight, Token::Int(a)) if io.sense_lights.len() + 1 > *a as usize => {
-                    if a > &0 {
-                        io.sense_lights[*a as usize - 1] = true;
-                        return_ = true;
-                    } else {
-                        io.sense_lights = vec![false; 4];
-                        return_ = true;
-                    }
-                    update_io = true;
-                }
-                (Token::GoTo, Token::Int(a)) if labels.contains_key(a) => {
-                    line_num = labels.get(a).unwrap().clone();
-                    return_ = true;
-                }
-                (Token::Print, _) => {
-                    info!("{:?}", line);
-                }
-                _ => {}
+        if let Some(rest) = statement.strip_prefix("FORMAT") {
+            self.push(Token::Format, 0, 6);
+            return self.lex_format(rest, 6);
+        }
+
+        if let Some(rest) = statement.strip_prefix("DO") {
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            if digits > 0 {
+                self.push(Token::Do, 0, 2);
+                self.lex(&rest[..digits], 2);
+                return self.lex(&rest[digits..], 2 + digits);
             }
         }
 
-        if line.len() > 4 {
-            match (&line[0], &line[1], &line[2], &line[3], &line[4]) {
-                (Token::If, Token::Int(x), Token::Int(a), Token::Int(b), Token::Int(c))
-                    if labels.contains_key(a)
-                        && labels.contains_key(b)
-                        && labels.contains_key(c) =>
-                {
-                    match x {
-                        0 => {
-                            line_num = labels.get(b).unwrap().clone();
-                        }
-                        _ if x < &1 => {
-                            line_num = labels.get(a).unwrap().clone();
-                        }
-                        _ => {
-                            line_num = labels.get(c).unwrap().clone();
-                        }
-                    }
-                }
+        self.lex(statement, 0)
+    }
 
-                _ => {}
-            }
+    /// Where the characters `start..end` of the statement were punched.
+    fn span(&self, start: usize, end: usize) -> Span {
+        match (self.positions.get(start), self.positions.get(end.max(start + 1) - 1)) {
+            (Some(a), Some(b)) => a.span().to(&b.span()),
+            (Some(a), None) => a.span(),
+            _ => Span::new(self.card, 7..8),
         }
+    }
+
+    fn push(&mut self, token: Token, start: usize, end: usize) {
+        self.tokens.push(token);
+        self.spans.push(self.span(start, end));
+    }
 
-        if line.len() > 9 {
-            match (
-                &line[0], &line[1], &line[2], &line[3], &line[4], &line[5], &line[6], &line[7],
-                &line[8],
-            ) {
-                (
-                    Token::Do,
-                    Token::Int(x),
-                    Token::Identifier(a),
-                    Token::Equals,
-                    Token::Int(b),
-                    Token::Comma,
-                    Token::Int(c),
-                    Token::Comma,
-                    Token::Int(d),
-                ) if labels.contains_key(x) => {
-                    let do_statement = DoStatement {
-                        start: line_num,
-                        end: labels.get(x).unwrap().clone(),
-                        max: *c,
-                        step: *d,
-                        current: *b,
-                        variable: a.to_owned(),
-                    };
-                    variables.insert(a.to_owned(), vec![Token::Int(do_statement.current)]);
-                    do_statements.push(do_statement);
-                    return_ = true;
+    fn error(&mut self, start: usize, end: usize, message: String) {
+        self.diagnostics
+            .push(Diagnostic::error(self.span(start, end), message));
+    }
+
+    fn lex(&mut self, text: &str, offset: usize) {
+        let chars: Vec<char> = text.chars().collect();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let start = i;
+            match get_char_type(chars[i]) {
+                // a name is a letter followed by letters and digits
+                CharType::Letter => {
+                    while i < chars.len() && chars[i].is_alphanumeric() {
+                        i += 1;
+                    }
+                    let name: String = chars[start..i].iter().collect();
+                    let function = name.len() == MAX_FUNCTION_NAME_LENGTH
+                        && name.ends_with('F')
+                        && chars.get(i) == Some(&'(');
+                    if name.len() > MAX_NAME_LENGTH && !function {
+                        self.error(
+                            offset + start,
+                            offset + i,
+                            format!("{} is longer than {} characters", name, MAX_NAME_LENGTH),
+                        );
+                    }
+                    self.push(Token::Identifier(name), offset + start, offset + i);
                 }
+                CharType::Digit => {
+                    while i < chars.len() && get_char_type(chars[i]) == CharType::Digit {
+                        i += 1;
+                    }
+                    let mantissa: String = chars[start..i].iter().collect();
 
-                _ => {}
-            }
-        }
-        if line.len() >= 8 {
-            match (
-                &line[0], &line[1], &line[2], &line[3], &line[4], &line[5], &line[6], &line[7],
-            ) {
-                (
-                    Token::If,
-                    Token::OpenParen { id: _, function: _ },
-                    Token::SenseLight,
-                    Token::Int(x),
-                    Token::CloseParen { id: _, function: _ },
-                    Token::Int(a),
-                    Token::Comma,
-                    Token::Int(b),
-                ) if labels.contains_key(a) && labels.contains_key(b) && io.sense_lights.len() as i32 >= *x => {
-                    if io.sense_lights[*x as usize - 1] {
-                        line_num = labels.get(a).unwrap().clone();
-                    } else {
-                        line_num = labels.get(b).unwrap().clone();
+                    // an E straight after a constant is its exponent, not the start of a name
+                    let mut exponent = None;
+                    if chars.get(i) == Some(&'E') {
+                        i += 1;
+                        let exponent_start = i;
+                        if matches!(chars.get(i), Some('+') | Some('-')) {
+                            i += 1;
+                        }
+                        while i < chars.len() && chars[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                        let text: String = chars[exponent_start..i].iter().collect();
+                        if !text.ends_with(|c: char| c.is_ascii_digit()) {
+                            self.error(
+                                offset + start,
+                                offset + i,
+                                format!("the exponent of {}E has no digits", mantissa),
+                            );
+                            self.push(Token::Float(0.0), offset + start, offset + i);
+                            continue;
+                        }
+                        exponent = Some(text);
+                    }
+
+                    match Token::number(&mantissa, exponent.as_deref()) {
+                        Ok(token) => self.push(token, offset + start, offset + i),
+                        Err(message) => {
+                            self.error(offset + start, offset + i, message);
+                            let token = if mantissa.contains('.') || exponent.is_some() {
+                                Token::Float(0.0)
+                            } else {
+                                Token::Int(0)
+                            };
+                            self.push(token, offset + start, offset + i);
+                        }
                     }
                 }
-                (
-                    Token::If,
-                    Token::OpenParen { id: _, function: _ },
-                    Token::SenseSwitch,
-                    Token::Int(x),
-                    Token::CloseParen { id: _, function: _ },
-                    Token::Int(a),
-                    Token::Comma,
-                    Token::Int(b),
-                ) if labels.contains_key(a) && labels.contains_key(b) && io.sense_switches.len() as i32 >= *x => {
-                    if io.sense_switches[*x as usize - 1] {
-                        line_num = labels.get(a).unwrap().clone();
+                CharType::Operator => {
+                    if chars[i] == '*' && chars.get(i + 1) == Some(&'*') {
+                        self.push(Token::Power, offset + i, offset + i + 2);
+                        i += 2;
                     } else {
-                        line_num = labels.get(b).unwrap().clone();
+                        let token = Token::tokenize_symbols(chars[i].to_string());
+                        self.push(token, offset + i, offset + i + 1);
+                        i += 1;
                     }
                 }
-                _ => {}
-            }
-        }
-        if line.len() > 7 {
-            match (
-                &line[0], &line[1], &line[2], &line[3], &line[4], &line[5], &line[6],
-            )  {
-                (
-                    Token::Do,
-                    Token::Int(x),
-                    Token::Identifier(a),
-                    Token::Equals,
-                    Token::Int(b),
-                    Token::Comma,
-                    Token::Int(c),
-                ) if labels.contains_key(x) && line.get(7) != Some(&Token::Comma) => {
-                    let do_statement = DoStatement {
-                        start: line_num,
-                        end: labels.get(x).unwrap().clone(),
-                        max: *c,
-                        step: 1,
-                        current: *b,
-                        variable: a.to_owned(),
-                    };
-                    variables.insert(a.to_owned(), vec![Token::Int(do_statement.current)]);
-                    do_statements.push(do_statement);
-                    return_ = true;
-                }
-                _ => {}
             }
         }
-        if line.len() > 5 {
-            match (&line[0], &line[1], &line[2], &line[3], &line[4]) {
-                (Token::Do, Token::Int(x), Token::Identifier(a), Token::Equals, Token::Int(b))
-                    if labels.contains_key(x) && line.get(5) != Some(&Token::Comma) =>
-                {
-                    let do_statement = DoStatement {
-                        start: line_num,
-                        end: labels.get(x).unwrap().clone(),
-                        max: *b,
-                        step: 1,
-                        current: 1,
-                        variable: a.to_owned(),
-                    };
-                    variables.insert(a.to_owned(), vec![Token::Int(do_statement.current)]);
-                    do_statements.push(do_statement);
-                    return_ = true;
+    }
+
+    /// Lexes a format specification, keeping Hollerith fields as they were punched.
+    fn lex_format(&mut self, text: &str, offset: usize) {
+        let mut start = 0;
+        let mut i = 0;
+        while i < text.len() {
+            if text[i..].starts_with('H') {
+                if let Some(count) = hollerith_count(&text[start..i]) {
+                    let digits = text[start..i].len()
+                        - text[start..i].trim_end_matches(|c: char| c.is_ascii_digit()).len();
+                    self.lex_fields(&text[start..i - digits], offset + start);
+                    let end = text.len().min(i + 1 + count);
+                    let field = Token::Hollerith(text[i + 1..end].to_string());
+                    self.push(field, offset + i - digits, offset + end);
+                    i = end;
+                    start = end;
+                    continue;
                 }
-                _ => {}
             }
+            i += text[i..].chars().next().map_or(1, |c| c.len_utf8());
         }
+        self.lex_fields(&text[start..], offset + start);
+    }
 
-        if !do_statements.is_empty() {
-            let statement = do_statements.last_mut().unwrap();
-            if line_num == statement.end {
-                statement.current += statement.step;
-                if statement.current <= statement.max {
-                    line_num = statement.start;
-                    variables.insert(
-                        statement.variable.to_owned(),
-                        vec![Token::Int(statement.current)],
-                    );
-                    return_ = true;
-                } else {
-                    do_statements.pop();
+    /// Lexes the part of a format specification between Hollerith fields. A
+    /// field like `2E14.5` is read as a field, not as the constant `2E14`.
+    fn lex_fields(&mut self, text: &str, offset: usize) {
+        let chars: Vec<char> = text.chars().collect();
+        let mut start = 0;
+        let mut i = 0;
+        while i < chars.len() {
+            let delimited = i == 0 || matches!(chars[i - 1], '(' | ',' | '/');
+            match field(&chars[i..]) {
+                Some((token, length)) if delimited => {
+                    self.lex(&chars[start..i].iter().collect::<String>(), offset + start);
+                    self.push(token, offset + i, offset + i + length);
+                    i += length;
+                    start = i;
                 }
+                _ => i += 1,
             }
         }
+        self.lex(&chars[start..].iter().collect::<String>(), offset + start);
+    }
+}
 
-        line_num += 1;
-        if return_ {
-            return (true, io, line_num, do_statements, variables, update_io);
+/// Reads an `nEw.d`, `nFw.d` or `nIw` field at the start of `chars`, with the
+/// number of characters it takes up. The repeat count can be left out.
+fn field(chars: &[char]) -> Option<(Token, usize)> {
+    fn digits(chars: &[char], i: &mut usize) -> Option<u32> {
+        let start = *i;
+        while *i < chars.len() && chars[*i].is_ascii_digit() {
+            *i += 1;
         }
+        chars[start..*i].iter().collect::<String>().parse().ok()
     }
+    let mut i = 0;
+    let repeat = digits(chars, &mut i).unwrap_or(1);
+    let conversion = *chars.get(i).filter(|c| matches!(c, 'E' | 'F' | 'I'))?;
+    i += 1;
+    let width = digits(chars, &mut i)?;
+    let decimals = if conversion == 'I' {
+        None
+    } else if chars.get(i) == Some(&'.') {
+        i += 1;
+        Some(digits(chars, &mut i)?)
+    } else {
+        return None;
+    };
+    // the field has to end where the specification does or at the next one
+    if !matches!(chars.get(i), None | Some(',') | Some(')') | Some('/')) {
+        return None;
+    }
+    let token = Token::Field {
+        repeat,
+        conversion,
+        width,
+        decimals,
+    };
+    Some((token, i))
+}
 
-    update_io = true;
-
-    return (false, io, line_num, do_statements, variables, update_io);
+/// Reads the program off the cards.
+///
+/// # Returns (statements, diagnostics)
+pub fn process(str: String, line_data: Vec<LineData>) -> (Vec<Statement>, Vec<Diagnostic>) {
+    let (tokens, spans, mut diagnostics) = tokenize(str, line_data);
+    let (statements, mut parse_diagnostics) = parse(&tokens, &spans);
+    diagnostics.append(&mut parse_diagnostics);
+    diagnostics.append(&mut check(&statements));
+    diagnostics.sort_by_key(|x| (x.span.card, x.span.columns.start));
+    (statements, diagnostics)
 }
 
-pub fn tokenize(mut in_string: String, mut line_data: Vec<LineData>) -> Vec<Token> {
-    let mut tokens = Vec::new();
-    in_string = in_string.to_uppercase();
-    in_string = in_string.replace("\r\n", "\n");
-    in_string = in_string.replace("SENSE LIGHT", "SenseLight");
-    in_string = in_string.replace("SENSE SWITCH", "SenseSwitch");
-    in_string = in_string.replace("GO TO", "GoTo");
-    let chars = in_string.chars();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::import_cards;
 
-    let mut string = String::new();
-    let mut number = String::new();
+    fn tokens(deck: &str) -> (Vec<Token>, Vec<Diagnostic>) {
+        let (text, line_data, _) = import_cards(deck);
+        let (tokens, _, diagnostics) = tokenize(text, line_data);
+        (tokens, diagnostics)
+    }
 
-    let mut current_line = 0;
+    #[test]
+    fn format_fields_are_not_constants() {
+        let (tokens, diagnostics) = tokens("10    FORMAT(2E14.5, F8.2/3I5, 4HE1.2)\n");
+        assert_eq!(diagnostics, vec![]);
+        let field = |repeat, conversion, width, decimals| Token::Field {
+            repeat,
+            conversion,
+            width,
+            decimals,
+        };
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Label(10),
+                Token::Format,
+                Token::OpenParen,
+                field(2, 'E', 14, Some(5)),
+                Token::Comma,
+                field(1, 'F', 8, Some(2)),
+                Token::Divide,
+                field(3, 'I', 5, None),
+                Token::Comma,
+                Token::Hollerith("E1.2".to_string()),
+                Token::CloseParen,
+                Token::Newline,
+            ]
+        );
+    }
 
-    for c in chars {
-        if c == '\n' {
-            current_line += 1;
-        }
-        if current_line >= line_data.len() {
-            continue;
-        }
-        if line_data[current_line].continuation && c == '\n' {
-            continue;
-        }
+    #[test]
+    fn constants_outside_format_keep_their_exponent_rules() {
+        let (_, diagnostics) = tokens("      X = 2E14\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("needs a decimal point"));
+    }
 
-        if line_data[current_line].label != 0 {
-            tokens.push(Token::Label(line_data[current_line].label));
-            line_data[current_line].label = 0;
-        } else if line_data[current_line].comment {
-            continue;
-        }
-        let c_type = get_char_type(c);
+    #[test]
+    fn blanks_carry_no_meaning() {
+        let (spaced, _) = tokens("      GO TO 1 0\n");
+        let (packed, _) = tokens("      GOTO10\n");
+        assert_eq!(spaced, packed);
+        assert_eq!(packed, vec![Token::GoTo, Token::Int(10), Token::Newline]);
+    }
 
-        if c_type != CharType::Letter && string.len() > 0 {
-            println!("string: {}", string);
-            tokens.push(Token::identifier_builtin(string));
-            string = String::new();
-        } else if CharType::Letter == c_type {
-            string.push(c);
-        }
+    #[test]
+    fn a_comma_after_the_equals_sign_makes_a_do() {
+        assert!(is_assignment("DO10I=1.5"));
+        assert!(!is_assignment("DO10I=1,5"));
+        assert!(is_assignment("A(I,J)=B(J,I)"));
+        assert!(!is_assignment("IF(A-B)1,2,3"));
+        let (assignment, _) = tokens("      DO 10 I = 1.5\n");
+        assert_eq!(
+            assignment[..3],
+            [
+                Token::Identifier("DO10I".to_string()),
+                Token::Equals,
+                Token::Float(1.5)
+            ]
+        );
+        let (do_statement, _) = tokens("      DO 10 I = 1, 5\n");
+        assert_eq!(
+            do_statement[..4],
+            [
+                Token::Do,
+                Token::Int(10),
+                Token::Identifier("I".to_string()),
+                Token::Equals
+            ]
+        );
+    }
 
-        if c_type != CharType::Digit && number.len() > 0 {
-            tokens.push(Token::number(number));
-            number = String::new();
-        } else if CharType::Digit == c_type {
-            number.push(c);
-        }
-        if c_type == CharType::Operator {
-            tokens.push(Token::tokenize_symbols(c.to_string()));
+    #[test]
+    fn names_have_at_most_six_characters() {
+        let (found, diagnostics) = tokens("      ALPHA1 = BETA2\n");
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(found[0], Token::Identifier("ALPHA1".to_string()));
+        let (_, diagnostics) = tokens("      ALPHABET = 1.0\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "ALPHABET is longer than 6 characters"
+        );
+        assert_eq!(diagnostics[0].span, Span::new(0, 7..15));
+    }
+
+    #[test]
+    fn a_function_name_can_have_a_seventh_character() {
+        let (_, diagnostics) = tokens("      X = XMAXABF(Y)\n");
+        assert_eq!(diagnostics, vec![]);
+        let (_, diagnostics) = tokens("      X = XMAXABF\n");
+        assert_eq!(diagnostics.len(), 1);
+        let (_, diagnostics) = tokens("      X = XMAXABCF(Y)\n");
+        assert_eq!(diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn constants_are_checked_against_the_704_word_formats() {
+        assert_eq!(Token::number("32767", None), Ok(Token::Int(32767)));
+        assert_eq!(
+            Token::number("32768", None),
+            Err("fixed point constant 32768 is larger than 32767".to_string())
+        );
+        // floating point magnitudes run from 2 to the -129 up to 2 to the 127
+        assert!(Token::number("1.7014118", Some("38")).is_ok());
+        assert!(Token::number("1.7015", Some("+38")).is_err());
+        assert!(Token::number("1.4693679", Some("-39")).is_ok());
+        assert!(Token::number("1.4693", Some("-39")).is_err());
+        assert_eq!(Token::number("0.0", Some("-50")), Ok(Token::Float(0.0)));
+        assert_eq!(Token::number("1.5", Some("+2")), Ok(Token::Float(150.0)));
+        assert_eq!(Token::number(".5", Some("-1")), Ok(Token::Float(0.05)));
+        assert!(Token::number("1.2.3", None).is_err());
+    }
+
+    /// A statement on one card and `continuations` more, then a STOP.
+    fn continued(continuations: usize) -> String {
+        let mut deck = "      X = 1.0\n".to_string();
+        for _ in 0..continuations {
+            deck.push_str("     1 + 1.0\n");
         }
+        deck + "      STOP\n"
     }
-    if number.len() > 0 {
-        tokens.push(Token::number(number));
+
+    #[test]
+    fn a_statement_can_run_onto_nine_continuation_cards() {
+        let (found, diagnostics) = tokens(&continued(9));
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(
+            found.iter().filter(|x| **x == Token::Float(1.0)).count(),
+            10
+        );
+        assert_eq!(found.iter().filter(|x| **x == Token::Newline).count(), 2);
+        let (_, diagnostics) = tokens(&continued(10));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "a statement can have at most 9 continuation cards"
+        );
+        assert_eq!(diagnostics[0].span, Span::new(10, 6..7));
     }
-    if string.len() > 0 {
-        tokens.push(Token::identifier_builtin(string));
+
+    #[test]
+    fn a_continuation_card_has_no_number_and_follows_a_statement() {
+        let (_, diagnostics) = tokens("      X = 1.0\n   10+ + Y\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "statement number 10 is on a continuation card"
+        );
+        let (_, diagnostics) = tokens("     1X = 1.0\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "continuation card has no statement to continue"
+        );
     }
 
-    return tokens;
-}
+    #[test]
+    fn hollerith_text_keeps_its_blanks() {
+        let (found, diagnostics) = tokens("10    FORMAT(6H A B C, I5)\n");
+        assert_eq!(diagnostics, vec![]);
+        assert!(found.contains(&Token::Hollerith(" A B C".to_string())));
+    }
 
-pub fn process(str: String, line_data: Vec<LineData>) -> Vec<Token> {
-    let mut tokens = tokenize(str, line_data);
-    tokens = tokens
-        .iter()
-        .filter(|x| !matches!(x, Token::Space))
-        .map(|x| x.clone())
-        .collect();
-    id_brackets(&mut tokens);
-    combine_and_expand(&mut tokens);
-    for i in 0..3 {
-        inline_functions(&mut tokens);
-    }
-    combine_and_expand(&mut tokens);
-    return tokens;
+    #[test]
+    fn letters_are_read_in_upper_case_outside_hollerith_fields_only() {
+        let (found, diagnostics) = tokens("      x = y\n");
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(found[0], Token::Identifier("X".to_string()));
+        // a lower case letter could not be punched, so it is reported and read as a blank
+        let (found, diagnostics) = tokens("10    FORMAT(3Hab )\n");
+        assert_eq!(diagnostics.len(), 2);
+        assert!(found.contains(&Token::Hollerith("   ".to_string())));
+    }
+
+    #[test]
+    fn characters_outside_the_704_set_are_dropped() {
+        let (found, diagnostics) = tokens("      X = Y; \n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "';' is not in the 704 character set"
+        );
+        assert_eq!(diagnostics[0].span, Span::new(0, 12..13));
+        assert_eq!(found.len(), 4);
+    }
 }
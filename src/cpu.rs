@@ -0,0 +1,987 @@
+//! The 704 itself, one instruction at a time.
+//!
+//! Words are 36 bit `u64`s as in `word`. The accumulator has two bits more
+//! than a word between its sign and bit 1, Q and P, which catch what carries
+//! out of bit 1. An index register is subtracted from the address it
+//! modifies, and a tag naming more than one register uses their contents
+//! OR'ed together, as on the real machine.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::word::{MAGNITUDE, SIGN, WORD};
+use crate::IO704;
+
+/// Bit P of the accumulator, where a stored word has its sign.
+const P: u64 = 1 << 35;
+/// Bit Q of the accumulator, above P.
+const Q: u64 = 1 << 36;
+/// Q, P and bits 1 to 35 of the accumulator.
+const AC_MAGNITUDE: u64 = Q | P | MAGNITUDE;
+/// The address and decrement fields are 15 bits.
+const ADDRESS: u64 = 0o77777;
+const DECREMENT_SHIFT: u32 = 18;
+
+/// Floating point words have an excess 128 characteristic in bits 1 to 8 and
+/// a 27 bit fraction in bits 9 to 35.
+const FRACTION_BITS: u32 = 27;
+const FRACTION: u64 = (1 << FRACTION_BITS) - 1;
+/// A characteristic that runs past bit 1 goes on into P and Q.
+const CHARACTERISTIC: u64 = 0o1777 << FRACTION_BITS;
+
+// type A instructions, by their prefix in the sign and bits 1 and 2
+pub const TXI: u64 = 1;
+pub const TIX: u64 = 2;
+pub const TXH: u64 = 3;
+pub const TNX: u64 = 6;
+pub const TXL: u64 = 7;
+
+// type B instructions, by the sign and bits 1 to 11, minus instructions
+// with the sign bit set
+pub const HTR: u64 = 0o0000;
+pub const TRA: u64 = 0o0020;
+pub const TSX: u64 = 0o0074;
+pub const TZE: u64 = 0o0100;
+pub const TNZ: u64 = 0o4100;
+pub const TPL: u64 = 0o0120;
+pub const TMI: u64 = 0o4120;
+pub const TOV: u64 = 0o0140;
+pub const TNO: u64 = 0o4140;
+pub const TQP: u64 = 0o0162;
+pub const XCA: u64 = 0o0131;
+pub const MPY: u64 = 0o0200;
+pub const DVH: u64 = 0o0220;
+pub const DVP: u64 = 0o0221;
+pub const FDP: u64 = 0o0240;
+pub const FMP: u64 = 0o0260;
+pub const FAD: u64 = 0o0300;
+pub const FSB: u64 = 0o0302;
+pub const ANS: u64 = 0o0320;
+pub const ANA: u64 = 0o4320;
+pub const ACL: u64 = 0o0361;
+pub const ADD: u64 = 0o0400;
+pub const ADM: u64 = 0o0401;
+pub const SUB: u64 = 0o0402;
+pub const SBM: u64 = 0o4400;
+pub const HPR: u64 = 0o0420;
+pub const CLA: u64 = 0o0500;
+pub const CAL: u64 = 0o4500;
+pub const ORA: u64 = 0o4501;
+pub const CLS: u64 = 0o0502;
+pub const LXA: u64 = 0o0534;
+pub const LXD: u64 = 0o4534;
+pub const LDQ: u64 = 0o0560;
+pub const STZ: u64 = 0o0600;
+pub const STQ: u64 = 0o4600;
+pub const STO: u64 = 0o0601;
+pub const SLW: u64 = 0o0602;
+pub const ORS: u64 = 0o4602;
+pub const STA: u64 = 0o0621;
+pub const STD: u64 = 0o0622;
+pub const SXA: u64 = 0o0634;
+pub const SXD: u64 = 0o4634;
+pub const PAX: u64 = 0o0734;
+pub const PDX: u64 = 0o4734;
+pub const PXA: u64 = 0o0754;
+pub const PXD: u64 = 0o4754;
+/// CLM, CHS, SSP, COM, RND, DCT, SLF, SLN and SWT, told apart by the address
+pub const PLUS_SENSE: u64 = 0o0760;
+/// SSM and SLT, told apart by the address
+pub const MINUS_SENSE: u64 = 0o4760;
+pub const NOP: u64 = 0o0761;
+pub const CPY: u64 = 0o0700;
+/// copies like CPY and adds the word to the AC as ACL does, for checksums
+pub const CAD: u64 = 0o4700;
+pub const RDS: u64 = 0o0762;
+pub const LLS: u64 = 0o0763;
+pub const LRS: u64 = 0o0765;
+pub const ALS: u64 = 0o0767;
+pub const ARS: u64 = 0o0771;
+pub const RQL: u64 = 0o4773;
+
+/// The address RDS selects the card reader with.
+pub const CARD_READER: u64 = 0o321;
+/// Words on a card read in row binary, left and right halves of 12 rows.
+pub const CARD_WORDS: usize = 24;
+
+/// The core storage installed, in words.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CoreSize {
+    K4,
+    K8,
+    K32,
+}
+
+impl CoreSize {
+    pub fn words(self) -> usize {
+        match self {
+            CoreSize::K4 => 4096,
+            CoreSize::K8 => 8192,
+            CoreSize::K32 => 32768,
+        }
+    }
+
+    /// `4K`, `8K` or `32K`.
+    pub fn parse(text: &str) -> Option<CoreSize> {
+        match text.to_uppercase().as_str() {
+            "4K" => Some(CoreSize::K4),
+            "8K" => Some(CoreSize::K8),
+            "32K" => Some(CoreSize::K32),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for CoreSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}K", self.words() / 1024)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpuStep {
+    Ran,
+    /// an HPR or HTR, or a DVH that failed; start carries on from the IC
+    Halted,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CpuError {
+    /// the operation code is not one this 704 has
+    IllegalInstruction { location: usize, word: u64 },
+    /// an RDS of something other than the card reader
+    NoSuchDevice { location: usize, device: u64 },
+}
+
+impl std::fmt::Display for CpuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CpuError::IllegalInstruction { location, word } => {
+                write!(f, "illegal instruction {:012o} at {:05o}", word, location)
+            }
+            CpuError::NoSuchDevice { location, device } => {
+                write!(f, "no device {:04o} for RDS at {:05o}", device, location)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cpu {
+    pub core_size: CoreSize,
+    pub memory: Vec<u64>,
+    /// the accumulator sign, true for minus
+    pub ac_negative: bool,
+    /// Q, P and bits 1 to 35 of the accumulator
+    pub ac: u64,
+    /// the multiplier-quotient register, a whole word
+    pub mq: u64,
+    /// index registers A, B and C, tagged 1, 2 and 4
+    pub index: [u64; 3],
+    /// the instruction counter, the location of the next instruction
+    pub ic: usize,
+    pub overflow: bool,
+    pub divide_check: bool,
+    pub io: IO704,
+    pub halted: bool,
+    pub executed: u64,
+    /// cards waiting in the reader's hopper, 24 row binary words each
+    pub hopper: VecDeque<Vec<u64>>,
+    /// what is left of the card an RDS started reading
+    pub reading: Option<VecDeque<u64>>,
+}
+
+impl Cpu {
+    pub fn new(core_size: CoreSize) -> Cpu {
+        Cpu {
+            core_size,
+            memory: vec![0; core_size.words()],
+            ac_negative: false,
+            ac: 0,
+            mq: 0,
+            index: [0; 3],
+            ic: 0,
+            overflow: false,
+            divide_check: false,
+            io: IO704::new(),
+            halted: false,
+            executed: 0,
+            hopper: VecDeque::new(),
+            reading: None,
+        }
+    }
+
+    /// Puts words into core from `address` on, wrapping at the top of core.
+    pub fn load(&mut self, address: usize, words: &[u64]) {
+        for (i, word) in words.iter().enumerate() {
+            let at = self.at((address + i) as u64);
+            self.memory[at] = word & WORD;
+        }
+    }
+
+    /// Loads a hand assembled program, one word of octal digits to a line.
+    ///
+    /// Spaces inside a word are ignored so the fields can be set apart, as in
+    /// `0500 00 0 00100` for CLA 100. `@100` sets the location of the next
+    /// word and `#` starts a comment. Returns the location of the first word.
+    pub fn load_octal(&mut self, text: &str) -> Result<usize, String> {
+        let mut location = 0;
+        let mut start = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let digits = line.split_whitespace().collect::<String>();
+            if let Some(address) = digits.strip_prefix('@') {
+                match usize::from_str_radix(address, 8) {
+                    Ok(a) if a < self.memory.len() => location = a,
+                    _ => {
+                        return Err(format!(
+                            "line {}: {} is not a location in {} of core",
+                            number + 1,
+                            address,
+                            self.core_size
+                        ))
+                    }
+                }
+                continue;
+            }
+            if digits.len() != 12 {
+                return Err(format!(
+                    "line {}: a word is 12 octal digits, not {}",
+                    number + 1,
+                    digits.len()
+                ));
+            }
+            let Ok(word) = u64::from_str_radix(&digits, 8) else {
+                return Err(format!("line {}: {} is not octal", number + 1, digits));
+            };
+            start.get_or_insert(location);
+            self.load(location, &[word]);
+            location = self.at(location as u64 + 1);
+        }
+        Ok(start.unwrap_or(0))
+    }
+
+    /// The LOAD CARDS button: puts a deck in the reader, copies the first
+    /// three words of its first card into 0 to 2 and starts at 0, leaving
+    /// the rest of the card to be copied by the program.
+    pub fn load_cards(&mut self, cards: Vec<Vec<u64>>) {
+        self.hopper = cards.into();
+        self.reading = self.hopper.pop_front().map(VecDeque::from);
+        for location in 0..3 {
+            let word = self.reading.as_mut().and_then(|x| x.pop_front());
+            self.memory[location] = word.unwrap_or(0);
+        }
+        self.ic = 0;
+        self.start();
+    }
+
+    /// The console start button: carries on from the IC after a halt.
+    pub fn start(&mut self) {
+        self.halted = false;
+        self.io.stop_light = false;
+    }
+
+    /// Runs until a halt, or for `budget` instructions.
+    pub fn run(&mut self, budget: usize) -> Result<CpuStep, CpuError> {
+        self.start();
+        for _ in 0..budget {
+            if self.step()? == CpuStep::Halted {
+                return Ok(CpuStep::Halted);
+            }
+        }
+        Ok(CpuStep::Ran)
+    }
+
+    /// Runs the instruction at the IC.
+    pub fn step(&mut self) -> Result<CpuStep, CpuError> {
+        let location = self.ic;
+        let word = self.memory[location];
+        self.ic = self.at(location as u64 + 1);
+        self.executed += 1;
+
+        let prefix = word >> 33;
+        let tag = (word >> 15 & 7) as usize;
+        let address = word & ADDRESS;
+        let decrement = word >> DECREMENT_SHIFT & ADDRESS;
+        if let TXI | TIX | TXH | TNX | TXL = prefix {
+            let register = self.tagged(tag);
+            let transfer = match prefix {
+                TXI => {
+                    self.set_index(tag, register + decrement);
+                    true
+                }
+                TIX | TNX if register > decrement => {
+                    self.set_index(tag, register - decrement);
+                    prefix == TIX
+                }
+                TIX => false,
+                TNX => true,
+                TXH => register > decrement,
+                _ => register <= decrement,
+            };
+            if transfer {
+                self.ic = self.at(address);
+            }
+            return Ok(CpuStep::Ran);
+        }
+
+        // index register instructions name a register with the tag, the
+        // rest have their address modified by it
+        let y = self.at(address.wrapping_sub(self.tagged(tag)));
+        let value = self.memory[y];
+        let negative = value & SIGN != 0;
+        let mut step = CpuStep::Ran;
+        let operation = word >> 24 & 0o7777;
+        match operation {
+            HTR => {
+                self.ic = y;
+                step = self.halt(y);
+            }
+            HPR => step = self.halt(y),
+            NOP => {}
+            RDS => {
+                if address != CARD_READER {
+                    self.ic = location;
+                    self.executed -= 1;
+                    self.halted = true;
+                    return Err(CpuError::NoSuchDevice {
+                        location,
+                        device: address,
+                    });
+                }
+                self.reading = self.hopper.pop_front().map(VecDeque::from);
+            }
+            // the next instruction at the end of the deck, the one after at
+            // the end of the card, and the third after once a word is copied
+            CPY | CAD => match self.reading.as_mut().map(|x| x.pop_front()) {
+                Some(Some(card_word)) => {
+                    self.memory[y] = card_word;
+                    if operation == CAD {
+                        self.add_logical(card_word);
+                    }
+                    self.skip();
+                    self.skip();
+                }
+                Some(None) => {
+                    self.reading = None;
+                    self.skip();
+                }
+                None => {}
+            },
+            TRA => self.ic = y,
+            TSX => {
+                // the two's complement of the location, so TRA 1,T returns
+                self.set_index(tag, (location as u64).wrapping_neg());
+                self.ic = self.at(address);
+            }
+            TZE | TNZ | TPL | TMI | TQP => {
+                let transfer = match operation {
+                    TZE => self.ac == 0,
+                    TNZ => self.ac != 0,
+                    TPL => !self.ac_negative,
+                    TMI => self.ac_negative,
+                    _ => self.mq & SIGN == 0,
+                };
+                if transfer {
+                    self.ic = y;
+                }
+            }
+            TOV => {
+                if self.overflow {
+                    self.overflow = false;
+                    self.ic = y;
+                }
+            }
+            TNO => {
+                if self.overflow {
+                    self.overflow = false;
+                } else {
+                    self.ic = y;
+                }
+            }
+            CLA => {
+                self.ac_negative = negative;
+                self.ac = value & MAGNITUDE;
+            }
+            CLS => {
+                self.ac_negative = !negative;
+                self.ac = value & MAGNITUDE;
+            }
+            CAL => {
+                // the sign of the word goes into P
+                self.ac_negative = false;
+                self.ac = value;
+            }
+            ADD => self.add(negative, value & MAGNITUDE),
+            SUB => self.add(!negative, value & MAGNITUDE),
+            ADM => self.add(false, value & MAGNITUDE),
+            SBM => self.add(true, value & MAGNITUDE),
+            ACL => self.add_logical(value),
+            ANA => {
+                self.ac_negative = false;
+                self.ac &= value;
+            }
+            ORA => self.ac |= value,
+            ANS => self.memory[y] &= self.ac & WORD,
+            ORS => self.memory[y] |= self.ac & WORD,
+            MPY => {
+                let product = (self.mq & MAGNITUDE) as u128 * (value & MAGNITUDE) as u128;
+                let sign = if negative != (self.mq & SIGN != 0) {
+                    SIGN
+                } else {
+                    0
+                };
+                self.ac_negative = sign != 0;
+                self.ac = (product >> 35) as u64;
+                self.mq = sign | (product as u64 & MAGNITUDE);
+            }
+            DVH | DVP => {
+                let divisor = value & MAGNITUDE;
+                // the quotient has to fit in the MQ
+                if self.ac >= divisor {
+                    self.divide_check = true;
+                    if operation == DVH {
+                        step = self.halt(y);
+                    }
+                } else {
+                    let dividend = (self.ac as u128) << 35 | (self.mq & MAGNITUDE) as u128;
+                    let sign = if negative != self.ac_negative {
+                        SIGN
+                    } else {
+                        0
+                    };
+                    self.mq = sign | (dividend / divisor as u128) as u64;
+                    self.ac = (dividend % divisor as u128) as u64;
+                }
+            }
+            FAD => self.floating_add(negative, value),
+            FSB => self.floating_add(!negative, value),
+            FMP => self.floating_multiply(value),
+            FDP => self.floating_divide(value),
+            LDQ => self.mq = value,
+            STQ => self.memory[y] = self.mq,
+            STO => self.memory[y] = self.ac_word(),
+            STZ => self.memory[y] = 0,
+            SLW => self.memory[y] = self.ac & WORD,
+            STA => self.memory[y] = value & !ADDRESS | self.ac & ADDRESS,
+            STD => {
+                let field = ADDRESS << DECREMENT_SHIFT;
+                self.memory[y] = value & !field | self.ac & field;
+            }
+            XCA => {
+                let mq = self.mq;
+                self.mq = self.ac_word();
+                self.ac_negative = mq & SIGN != 0;
+                self.ac = mq & MAGNITUDE;
+            }
+            ALS => {
+                let count = (y & 0o377) as u32;
+                let magnitude = self.ac & MAGNITUDE;
+                // a one shifted into or through P
+                if magnitude != 0 && 64 - magnitude.leading_zeros() + count > 35 {
+                    self.overflow = true;
+                }
+                self.ac = self.ac.checked_shl(count).unwrap_or(0) & AC_MAGNITUDE;
+            }
+            ARS => self.ac = self.ac.checked_shr((y & 0o377) as u32).unwrap_or(0),
+            LLS | LRS => {
+                let count = (y & 0o377) as u32;
+                let mq_sign = self.mq & SIGN;
+                let both = (self.ac as u128) << 35 | (self.mq & MAGNITUDE) as u128;
+                let shifted = if word >> 24 & 0o7777 == LLS {
+                    let magnitude = both & ((1 << 70) - 1);
+                    if magnitude != 0 && 128 - magnitude.leading_zeros() + count > 70 {
+                        self.overflow = true;
+                    }
+                    self.ac_negative = mq_sign != 0;
+                    both.checked_shl(count).unwrap_or(0)
+                } else {
+                    both.checked_shr(count).unwrap_or(0)
+                };
+                self.ac = (shifted >> 35) as u64 & AC_MAGNITUDE;
+                let sign = if self.ac_negative { SIGN } else { 0 };
+                self.mq = sign | (shifted as u64 & MAGNITUDE);
+            }
+            RQL => {
+                let count = (y & 0o377) as u32 % 36;
+                self.mq = (self.mq << count | self.mq >> (36 - count)) & WORD;
+            }
+            LXA => self.set_index(tag, self.memory[self.at(address)]),
+            LXD => self.set_index(tag, self.memory[self.at(address)] >> DECREMENT_SHIFT),
+            SXA => {
+                let at = self.at(address);
+                self.memory[at] = self.memory[at] & !ADDRESS | self.tagged(tag);
+            }
+            SXD => {
+                let at = self.at(address);
+                let field = ADDRESS << DECREMENT_SHIFT;
+                self.memory[at] = self.memory[at] & !field | self.tagged(tag) << DECREMENT_SHIFT;
+            }
+            PAX => self.set_index(tag, self.ac),
+            PDX => self.set_index(tag, self.ac >> DECREMENT_SHIFT),
+            PXA => {
+                self.ac_negative = false;
+                self.ac = self.tagged(tag);
+            }
+            PXD => {
+                self.ac_negative = false;
+                self.ac = self.tagged(tag) << DECREMENT_SHIFT;
+            }
+            PLUS_SENSE => match address {
+                // CLM
+                0o0 => self.ac = 0,
+                // CHS
+                0o2 => self.ac_negative = !self.ac_negative,
+                // SSP
+                0o3 => self.ac_negative = false,
+                // COM
+                0o6 => self.ac = !self.ac & AC_MAGNITUDE,
+                // RND, from bit 1 of the MQ
+                0o10 => {
+                    if self.mq & 1 << 34 != 0 {
+                        self.add(self.ac_negative, 1);
+                    }
+                }
+                // DCT skips when the divide check indicator is off
+                0o12 => {
+                    if self.divide_check {
+                        self.divide_check = false;
+                    } else {
+                        self.skip();
+                    }
+                }
+                // SLF
+                0o140 => self.io.sense_lights.iter_mut().for_each(|x| *x = false),
+                // SLN
+                0o141..=0o144 => self.io.sense_lights[(address - 0o141) as usize] = true,
+                // SWT skips when the switch is down
+                0o161..=0o166 => {
+                    if self.io.sense_switches[(address - 0o161) as usize] {
+                        self.skip();
+                    }
+                }
+                _ => return self.illegal(location, word),
+            },
+            MINUS_SENSE => match address {
+                // SSM
+                0o3 => self.ac_negative = true,
+                // SLT skips when the light was on, and turns it off
+                0o141..=0o144 => {
+                    let light = (address - 0o141) as usize;
+                    if self.io.sense_lights[light] {
+                        self.io.sense_lights[light] = false;
+                        self.skip();
+                    }
+                }
+                _ => return self.illegal(location, word),
+            },
+            _ => return self.illegal(location, word),
+        }
+        Ok(step)
+    }
+
+    /// A location in core; addresses past the top wrap round to 0.
+    fn at(&self, address: u64) -> usize {
+        address as usize & (self.memory.len() - 1)
+    }
+
+    /// The contents of the index registers named by a tag, OR'ed together.
+    fn tagged(&self, tag: usize) -> u64 {
+        (0..3)
+            .filter(|x| tag & 1 << x != 0)
+            .fold(0, |total, x| total | self.index[x])
+    }
+
+    fn set_index(&mut self, tag: usize, value: u64) {
+        for x in (0..3).filter(|x| tag & 1 << x != 0) {
+            self.index[x] = value & ADDRESS;
+        }
+    }
+
+    /// The accumulator as STO stores it, sign and bits 1 to 35.
+    fn ac_word(&self) -> u64 {
+        let sign = if self.ac_negative { SIGN } else { 0 };
+        sign | self.ac & MAGNITUDE
+    }
+
+    fn skip(&mut self) {
+        self.ic = self.at(self.ic as u64 + 1);
+    }
+
+    /// Stops with the address of the halt in the address lights.
+    fn halt(&mut self, address: usize) -> CpuStep {
+        self.halted = true;
+        self.io.stop_light = true;
+        self.io.display = address as i32;
+        CpuStep::Halted
+    }
+
+    /// Leaves the IC on the instruction, as the machine stopped with it.
+    fn illegal(&mut self, location: usize, word: u64) -> Result<CpuStep, CpuError> {
+        self.ic = location;
+        self.executed -= 1;
+        self.halted = true;
+        Err(CpuError::IllegalInstruction { location, word })
+    }
+
+    /// Fixed point addition of a signed magnitude, as ADD does it.
+    ///
+    /// A carry out of bit 1 into P turns the overflow indicator on; one out
+    /// of Q is lost.
+    fn add(&mut self, negative: bool, magnitude: u64) {
+        if negative == self.ac_negative {
+            if (self.ac & MAGNITUDE) + magnitude > MAGNITUDE {
+                self.overflow = true;
+            }
+            self.ac = (self.ac + magnitude) & AC_MAGNITUDE;
+        } else if self.ac >= magnitude {
+            // a zero result keeps the accumulator's sign
+            self.ac -= magnitude;
+        } else {
+            self.ac = magnitude - self.ac;
+            self.ac_negative = negative;
+        }
+    }
+
+    /// Adds a word to P and bits 1 to 35 as a 36 bit number, as ACL does,
+    /// with the carry out of P coming round into bit 35.
+    fn add_logical(&mut self, value: u64) {
+        let mut sum = (self.ac & WORD) + value;
+        if sum > WORD {
+            sum = (sum & WORD) + 1;
+        }
+        self.ac = self.ac & Q | sum;
+    }
+
+    /// Normalized floating point addition of C(Y), with its sign as given,
+    /// to the accumulator.
+    fn floating_add(&mut self, negative: bool, value: u64) {
+        let a = (
+            self.ac_negative,
+            characteristic(self.ac),
+            self.ac & FRACTION,
+        );
+        let b = (negative, characteristic(value), value & FRACTION);
+        // the number with the smaller characteristic is shifted right to line up
+        let (large, small) = if a.1 >= b.1 { (a, b) } else { (b, a) };
+        let shift = (large.1 - small.1) as u32;
+        let large_fraction = (large.2 as u128) << FRACTION_BITS;
+        let small_fraction = ((small.2 as u128) << FRACTION_BITS)
+            .checked_shr(shift)
+            .unwrap_or(0);
+        let (negative, sum) = if large.0 == small.0 {
+            (large.0, large_fraction + small_fraction)
+        } else if large_fraction >= small_fraction {
+            (large.0, large_fraction - small_fraction)
+        } else {
+            (small.0, small_fraction - large_fraction)
+        };
+        self.set_floating(negative, large.1, sum);
+    }
+
+    /// C(MQ) times C(Y), the high order half in the AC and the low in the MQ.
+    fn floating_multiply(&mut self, value: u64) {
+        let negative = (self.mq & SIGN != 0) != (value & SIGN != 0);
+        let product = (self.mq & FRACTION) as u128 * (value & FRACTION) as u128;
+        let exponent = characteristic(self.mq) + characteristic(value) - 128;
+        self.set_floating(negative, exponent, product);
+    }
+
+    /// C(AC) divided by C(Y), the quotient in the MQ and the remainder in
+    /// the AC.
+    ///
+    /// The divide check indicator is turned on and nothing else changes when
+    /// the quotient would not be a fraction, a divisor of zero among them.
+    fn floating_divide(&mut self, value: u64) {
+        let dividend = self.ac & FRACTION;
+        let divisor = value & FRACTION;
+        if divisor == 0 || dividend >= 2 * divisor {
+            self.divide_check = true;
+            return;
+        }
+        let sign = if self.ac_negative != (value & SIGN != 0) {
+            SIGN
+        } else {
+            0
+        };
+        if dividend == 0 {
+            self.mq = sign;
+            return;
+        }
+        // a dividend bigger than the divisor is shifted one place right first
+        let shift = if dividend >= divisor { 1 } else { 0 };
+        let quotient = ((dividend as u128) << (FRACTION_BITS - shift)) / divisor as u128;
+        let remainder = ((dividend as u128) << (FRACTION_BITS - shift)) % divisor as u128;
+        let exponent = characteristic(self.ac) - characteristic(value) + 128 + shift as i32;
+        if !(0..=255).contains(&exponent) {
+            self.overflow = true;
+        }
+        self.mq = sign
+            | (exponent as u64) << FRACTION_BITS & CHARACTERISTIC & MAGNITUDE
+            | quotient as u64;
+        let exponent = characteristic(self.ac) - FRACTION_BITS as i32;
+        self.ac =
+            (exponent as u64) << FRACTION_BITS & CHARACTERISTIC & MAGNITUDE | remainder as u64;
+    }
+
+    /// Normalizes a fraction with 54 bits after the point, and perhaps a carry
+    /// before it, into the AC with the low order 27 bits in the MQ.
+    ///
+    /// A characteristic that goes past 255 or below 0 runs into Q and P,
+    /// and turns the overflow indicator on.
+    fn set_floating(&mut self, negative: bool, characteristic: i32, fraction: u128) {
+        let sign = if negative { SIGN } else { 0 };
+        self.ac_negative = negative;
+        if fraction == 0 {
+            self.ac = 0;
+            self.mq = sign;
+            return;
+        }
+        let mut fraction = fraction;
+        let mut characteristic = characteristic;
+        let top = 2 * FRACTION_BITS;
+        while fraction >> top != 0 {
+            fraction >>= 1;
+            characteristic += 1;
+        }
+        while fraction >> (top - 1) == 0 {
+            fraction <<= 1;
+            characteristic -= 1;
+        }
+        if !(0..=255).contains(&characteristic) {
+            self.overflow = true;
+        }
+        self.ac = (characteristic as u64) << FRACTION_BITS & CHARACTERISTIC
+            | (fraction >> FRACTION_BITS) as u64;
+        let low = (characteristic - FRACTION_BITS as i32) as u64;
+        self.mq =
+            sign | low << FRACTION_BITS & CHARACTERISTIC & MAGNITUDE | fraction as u64 & FRACTION;
+    }
+}
+
+/// Bits 1 to 8 of a word, or of the accumulator.
+fn characteristic(word: u64) -> i32 {
+    (word >> FRACTION_BITS & 0o377) as i32
+}
+
+/// The registers and indicators as the console lights show them.
+impl std::fmt::Display for Cpu {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = |x: bool| if x { '-' } else { '+' };
+        let bit = |x: bool| if x { '1' } else { '0' };
+        writeln!(f, "IC  {:05o}", self.ic)?;
+        writeln!(
+            f,
+            "AC  {}{}{} {:012o}",
+            sign(self.ac_negative),
+            bit(self.ac & Q != 0),
+            bit(self.ac & P != 0),
+            self.ac & MAGNITUDE
+        )?;
+        writeln!(
+            f,
+            "MQ  {}   {:012o}",
+            sign(self.mq & SIGN != 0),
+            self.mq & MAGNITUDE
+        )?;
+        writeln!(
+            f,
+            "IR  A {:05o}  B {:05o}  C {:05o}",
+            self.index[0], self.index[1], self.index[2]
+        )?;
+        writeln!(
+            f,
+            "OV  {}  DC  {}  LIGHTS {}",
+            bit(self.overflow),
+            bit(self.divide_check),
+            self.io
+                .sense_lights
+                .iter()
+                .map(|x| bit(*x))
+                .collect::<String>()
+        )?;
+        // the address of the HPR or HTR the program stopped at
+        if self.io.stop_light {
+            writeln!(f, "HALT {:05o}", self.io.display)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::word::{floating_word, word_floating};
+
+    /// Runs a hand assembled program from its first word to a halt.
+    fn run(program: &str) -> Cpu {
+        let mut cpu = Cpu::new(CoreSize::K4);
+        cpu.ic = cpu.load_octal(program).unwrap();
+        assert_eq!(cpu.run(1000), Ok(CpuStep::Halted));
+        cpu
+    }
+
+    #[test]
+    fn fixed_point_arithmetic() {
+        let cpu = run("
+            0500 00 0 00100  # CLA 100
+            0400 00 0 00101  # ADD 101
+            0402 00 0 00102  # SUB 102
+            0601 00 0 00103  # STO 103
+            0502 00 0 00100  # CLS 100
+            0601 00 0 00104  # STO 104
+            0420 00 0 00000  # HPR
+            @100
+            0000 00 0 00005
+            0000 00 0 00007
+            4000 00 0 00003
+        ");
+        assert_eq!(cpu.memory[0o103], 15);
+        assert_eq!(cpu.memory[0o104], SIGN | 5);
+        assert!(!cpu.overflow);
+    }
+
+    #[test]
+    fn floating_point_arithmetic() {
+        let (a, b) = (floating_word(1.5), floating_word(-0.25));
+        let cpu = run(&format!(
+            "
+            0500 00 0 00100  # CLA 100
+            0300 00 0 00101  # FAD 101
+            0601 00 0 00102  # STO 102
+            0560 00 0 00100  # LDQ 100
+            0260 00 0 00101  # FMP 101
+            0601 00 0 00103  # STO 103
+            0500 00 0 00100  # CLA 100
+            0240 00 0 00101  # FDP 101
+            4600 00 0 00104  # STQ 104
+            0420 00 0 00000  # HPR
+            @100
+            {:012o}
+            {:012o}
+            ",
+            a, b
+        ));
+        assert_eq!(word_floating(cpu.memory[0o102]), 1.25);
+        assert_eq!(word_floating(cpu.memory[0o103]), -0.375);
+        assert_eq!(word_floating(cpu.memory[0o104]), -6.0);
+        assert!(!cpu.overflow && !cpu.divide_check);
+    }
+
+    #[test]
+    fn transfers_index_registers_and_subroutines() {
+        let cpu = run("
+            0534 00 1 00100  # LXA 100,1   three times round
+            0500 00 0 00101  # CLA 101
+            0400 00 0 00102  # ADD 102
+            2 00001 1 00002  # TIX 2,1,1
+            0601 00 0 00103  # STO 103
+            0074 00 4 00020  # TSX 20,4
+            0100 00 0 00011  # TZE 11
+            0000 00 0 00007  # HTR 7
+            0000 00 0 00010  # HTR 10
+            0420 00 0 00011  # HPR 11
+            @20
+            0500 00 0 00103  # CLA 103
+            0402 00 0 00103  # SUB 103
+            0020 00 4 00001  # TRA 1,4
+            @100
+            0000 00 0 00003
+            0000 00 0 00000
+            0000 00 0 00001
+        ");
+        assert_eq!(cpu.memory[0o103], 3);
+        assert_eq!(cpu.index[0], 1);
+        // the TSX left the two's complement of its own location
+        assert_eq!(cpu.index[2], 0o100000 - 5);
+        assert_eq!(cpu.io.display, 0o11);
+        assert_eq!(cpu.ic, 0o12);
+    }
+
+    #[test]
+    fn overflow_into_p_and_q() {
+        let cpu = run("
+            0500 00 0 00100  # CLA 100
+            0400 00 0 00101  # ADD 101
+            0601 00 0 00102  # STO 102
+            0140 00 0 00005  # TOV 5
+            0000 00 0 00004  # HTR 4
+            0400 00 0 00100  # ADD 100
+            0400 00 0 00100  # ADD 100
+            0420 00 0 00000  # HPR
+            @100
+            3777 77 7 77777
+            0000 00 0 00001
+        ");
+        // the carry went into P, and the stored word lost it
+        assert_eq!(cpu.memory[0o102], 0);
+        // TOV took the transfer and turned the indicator off, and the second
+        // ADD after it turned it back on carrying into Q
+        assert!(cpu.overflow);
+        assert_eq!(cpu.ac, Q | (MAGNITUDE - 1));
+        assert!(cpu.to_string().contains("AC  +10 377777777776"));
+    }
+
+    #[test]
+    fn carry_out_of_q_is_lost() {
+        let cpu = run("
+            0500 00 0 00100  # CLA 100
+            0400 00 0 00100  # ADD 100
+            0400 00 0 00100  # ADD 100
+            0400 00 0 00100  # ADD 100
+            0400 00 0 00100  # ADD 100
+            0420 00 0 00000  # HPR
+            @100
+            3777 77 7 77777
+        ");
+        assert_eq!(cpu.ac, (5 * MAGNITUDE) & AC_MAGNITUDE);
+    }
+
+    #[test]
+    fn sense_lights_and_switches() {
+        let mut cpu = Cpu::new(CoreSize::K4);
+        cpu.load_octal("
+            0760 00 0 00142  # SLN 2
+            4760 00 0 00141  # SLT 1   off, so no skip
+            0020 00 0 00004  # TRA 4
+            0000 00 0 00003  # HTR 3
+            4760 00 0 00142  # SLT 2   on, so it skips and turns it off
+            0000 00 0 00005  # HTR 5
+            0760 00 0 00163  # SWT 3   down, so it skips
+            0000 00 0 00007  # HTR 7
+            0760 00 0 00161  # SWT 1   up
+            0760 00 0 00144  # SLN 4
+            0420 00 0 00012  # HPR 12
+        ")
+        .unwrap();
+        cpu.io.sense_switches[2] = true;
+        assert_eq!(cpu.run(100), Ok(CpuStep::Halted));
+        assert_eq!(cpu.io.display, 0o12);
+        assert_eq!(cpu.io.sense_lights, vec![false, false, false, true]);
+    }
+
+    #[test]
+    fn htr_stops_and_start_carries_on_at_its_address() {
+        let mut cpu = Cpu::new(CoreSize::K4);
+        cpu.load_octal("
+            0000 00 0 00005  # HTR 5
+            @5
+            0500 00 0 00100  # CLA 100
+            0420 00 0 00006  # HPR 6
+            @100
+            0000 00 0 00042
+        ")
+        .unwrap();
+        assert_eq!(cpu.run(100), Ok(CpuStep::Halted));
+        assert!(cpu.halted && cpu.io.stop_light);
+        assert_eq!((cpu.ic, cpu.io.display), (5, 5));
+        assert_eq!(cpu.ac, 0);
+        assert_eq!(cpu.run(100), Ok(CpuStep::Halted));
+        assert_eq!(cpu.ac, 0o42);
+        assert_eq!((cpu.ic, cpu.io.display), (7, 6));
+        assert_eq!(cpu.executed, 3);
+    }
+}
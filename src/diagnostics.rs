@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+
+/// Where something was punched: a card (0 based line of the deck) and a range
+/// of 1 based card columns.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Span {
+    pub card: usize,
+    pub columns: Range<usize>,
+}
+
+impl Span {
+    pub fn new(card: usize, columns: Range<usize>) -> Span {
+        Span { card, columns }
+    }
+
+    /// From the start of `self` to the end of `other`, clipped to the first card.
+    pub fn to(&self, other: &Span) -> Span {
+        let end = if other.card == self.card {
+            other.columns.end.max(self.columns.end)
+        } else {
+            STATEMENT_END + 1
+        };
+        Span::new(self.card, self.columns.start..end)
+    }
+}
+
+/// The last column of the statement field.
+pub const STATEMENT_END: usize = 72;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem with the program, from the lexer, the parser or the runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            span,
+            message,
+        }
+    }
+
+    pub fn warning(span: Span, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            span,
+            message,
+        }
+    }
+
+    pub fn card(&self) -> usize {
+        self.span.card
+    }
+
+    /// Compiler style, e.g. `deck.f:3:7: error: message`, followed by the card
+    /// and a marker under the offending columns.
+    pub fn render(&self, file: &str, cards: &[&str]) -> String {
+        let mut text = format!(
+            "{}:{}:{}: {}: {}",
+            file,
+            self.span.card + 1,
+            self.span.columns.start,
+            self.severity,
+            self.message
+        );
+        if let Some(card) = cards.get(self.span.card) {
+            let start = self.span.columns.start.max(1);
+            // a span to the end of the statement field stops at the end of the card
+            let end = self.span.columns.end.min(card.chars().count() + 1);
+            let width = end.saturating_sub(start).max(1);
+            text.push_str(&format!(
+                "\n{:>5} | {}\n      | {}{}",
+                self.span.card + 1,
+                card,
+                " ".repeat(start - 1),
+                "^".repeat(width)
+            ));
+        }
+        text
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "card {}, columns {}-{}: {}: {}",
+            self.span.card + 1,
+            self.span.columns.start,
+            self.span.columns.end.saturating_sub(1).max(self.span.columns.start),
+            self.severity,
+            self.message
+        )
+    }
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|x| x.severity == Severity::Error)
+}
@@ -0,0 +1,204 @@
+use sycamore::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{Event, HtmlInputElement};
+
+use crate::compile::array_words;
+use crate::compute::{is_fixed_name, Value};
+use crate::machine::Machine;
+use crate::word;
+
+/// A variable or array as the inspector shows it.
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    name: String,
+    /// the DIMENSION sizes, empty for a scalar
+    sizes: Vec<i32>,
+    /// the value of a scalar; an array's are only read when its table is shown
+    value: Value,
+}
+
+fn entries(machine: &Machine) -> Vec<Entry> {
+    let mut names = machine
+        .variables
+        .keys()
+        .chain(machine.program.arrays.keys())
+        .cloned()
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .map(|name| {
+            let sizes = machine.program.arrays.get(&name).cloned().unwrap_or_default();
+            let value = match sizes.is_empty() {
+                true => machine.variables.get(&name).and_then(|x| x.first()).copied(),
+                false => None,
+            };
+            Entry {
+                value: value.unwrap_or(Value::zero(&name)),
+                name,
+                sizes,
+            }
+        })
+        .collect()
+}
+
+/// Stores a value typed into the inspector, as an assignment would.
+fn store(machine: &Signal<Machine>, name: &str, index: usize, value: Value) {
+    let mut machine = machine.modify();
+    let size = machine
+        .program
+        .arrays
+        .get(name)
+        .and_then(|x| array_words(x))
+        .unwrap_or(1);
+    let values = machine.variables.entry(name.to_owned()).or_default();
+    if values.len() < size {
+        values.resize(size, Value::zero(name));
+    }
+    if let Some(x) = values.get_mut(index) {
+        *x = value;
+    }
+}
+
+fn input_value(event: Event) -> Option<String> {
+    Some(event.target()?.unchecked_into::<HtmlInputElement>().value())
+}
+
+/// One word of memory, in decimal and in octal, editable while the program is paused.
+fn field<'a, G: Html>(
+    cx: Scope<'a>,
+    machine: &'a Signal<Machine>,
+    paused: &'a ReadSignal<bool>,
+    name: String,
+    index: usize,
+    value: Value,
+) -> View<G> {
+    let octal_name = name.clone();
+    view! { cx,
+        input(class="decimal", value=value.to_string(), disabled=!*paused.get(),
+            on:change=move |event: Event| {
+                match input_value(event).and_then(|x| Value::parse_for(&name, &x)) {
+                    Some(value) => store(machine, &name, index, value),
+                    // put back what is in memory
+                    None => machine.trigger_subscribers(),
+                }
+            }
+        ) {}
+        input(class="octal", value=word::octal(value.word()), disabled=!*paused.get(),
+            on:change=move |event: Event| {
+                match input_value(event).and_then(|x| word::parse_octal(x.trim())) {
+                    Some(word) => store(machine, &octal_name, index, Value::from_word(&octal_name, word)),
+                    None => machine.trigger_subscribers(),
+                }
+            }
+        ) {}
+    }
+}
+
+/// Arrays are stored by columns, so the first subscript runs down the rows
+/// and the others across. An array with no elements has no table.
+fn table<'a, G: Html>(
+    cx: Scope<'a>,
+    machine: &'a Signal<Machine>,
+    paused: &'a ReadSignal<bool>,
+    name: String,
+    sizes: Vec<i32>,
+) -> View<G> {
+    let Some(words) = array_words(&sizes) else {
+        return View::empty();
+    };
+    let rows = sizes[0] as usize;
+    let columns = words / rows;
+    let values = machine.get().variables.get(&name).cloned().unwrap_or_default();
+    let heading = View::new_fragment(
+        (0..columns)
+            .map(|column| {
+                let mut subscripts = vec![];
+                let mut rest = column;
+                for size in &sizes[1..] {
+                    subscripts.push((rest % *size as usize + 1).to_string());
+                    rest /= *size as usize;
+                }
+                let text = subscripts.join(",");
+                view! { cx, th { (text) } }
+            })
+            .collect(),
+    );
+    let body = View::new_fragment(
+        (0..rows)
+            .map(|row| {
+                let cells = View::new_fragment(
+                    (0..columns)
+                        .map(|column| {
+                            let index = column * rows + row;
+                            let value = values.get(index).copied().unwrap_or(Value::zero(&name));
+                            let cell = field(cx, machine, paused, name.clone(), index, value);
+                            view! { cx, td { (cell) } }
+                        })
+                        .collect(),
+                );
+                view! { cx, tr { th { (row + 1) } (cells) } }
+            })
+            .collect(),
+    );
+    view! { cx,
+        table {
+            tr { th {} (heading) }
+            (body)
+        }
+    }
+}
+
+/// The variables and arrays of the machine, with their 704 words.
+pub fn inspector<'a, G: Html>(
+    cx: Scope<'a>,
+    machine: &'a Signal<Machine>,
+    paused: &'a ReadSignal<bool>,
+) -> View<G> {
+    let entries = create_memo(cx, move || entries(&machine.get()));
+    // the one array whose elements are shown
+    let expanded = create_signal(cx, None::<String>);
+    view! { cx,
+        div(class="inspector labeled") {
+            p{("Variables")}
+            Indexed(
+                iterable=entries,
+                view=move |cx, entry| {
+                    let kind = if is_fixed_name(&entry.name) { "fixed" } else { "floating" };
+                    let name = entry.name.clone();
+                    let contents = if entry.sizes.is_empty() {
+                        field(cx, machine, paused, entry.name.clone(), 0, entry.value)
+                    } else {
+                        let sizes = entry.sizes.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(",");
+                        let shown = create_selector(cx, {
+                            let name = entry.name.clone();
+                            move || expanded.get().as_ref() == &Some(name.clone())
+                        });
+                        let toggle = entry.name.clone();
+                        view! { cx,
+                            span { "(" (sizes) ")" }
+                            button(on:click=move |_| {
+                                expanded.set(if *shown.get() { None } else { Some(toggle.clone()) });
+                            }) {
+                                (if *shown.get() { "hide" } else { "show" })
+                            }
+                            (if *shown.get() {
+                                table(cx, machine, paused, entry.name.clone(), entry.sizes.clone())
+                            } else {
+                                View::empty()
+                            })
+                        }
+                    };
+                    view! { cx,
+                        div(class="variable") {
+                            span(class="variable-name") { (name) }
+                            span(class="variable-kind") { (kind) }
+                            (contents)
+                        }
+                    }
+                },
+            )
+        }
+    }
+}
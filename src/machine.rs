@@ -0,0 +1,785 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use crate::bcd;
+use crate::compile::{array_words, Op, Program};
+use crate::compute::{ErrorKind, Evaluator, Value};
+use crate::diagnostics::{Diagnostic, Span};
+use crate::parse::Expr;
+use crate::snapshot::{Snapshot, SNAPSHOT_VERSION};
+use crate::trace::{Before, Record, Write, TRACE_LIMIT};
+use crate::timing;
+use crate::watch::{Hit, Watchpoint};
+use crate::{LineData, IO704};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DoStatement {
+    /// the DO statement itself
+    pub start: usize,
+    /// the last statement of the range
+    pub end: usize,
+    pub max: i32,
+    pub variable: String,
+    pub step: i32,
+}
+
+/// The machine as it was when the failing statement started.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineState {
+    pub line_num: usize,
+    pub io: IO704,
+    pub variables: HashMap<String, Vec<Value>>,
+    pub do_statements: Vec<DoStatement>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub span: Span,
+    pub state: Box<MachineState>,
+}
+
+impl RuntimeError {
+    pub fn card(&self) -> usize {
+        self.span.card
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(self.span.clone(), self.kind.to_string())
+    }
+}
+
+/// What a step did, so callers only redraw the console when they need to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Ran,
+    /// a light, the display or the printer changed
+    Io,
+    /// the next statement is on a card with a breakpoint
+    Break,
+    /// the statement stored a value a watchpoint was waiting for
+    Watch,
+    Halted,
+}
+
+/// A 704 running a compiled program.
+#[derive(Debug, Clone)]
+pub struct Machine {
+    pub program: Program,
+    /// the values of every variable and array, by name
+    pub variables: HashMap<String, Vec<Value>>,
+    /// the console indicators and the devices attached to it
+    pub io: IO704,
+    /// the statement that runs next
+    pub pc: usize,
+    pub do_statements: Vec<DoStatement>,
+    pub halted: bool,
+    /// statements run since the last reset
+    pub executed: u64,
+    /// simulated 704 time since the last reset, in machine cycles
+    pub cycles: u64,
+    /// the time spent in each statement of the program
+    pub statement_cycles: Vec<u64>,
+    /// how many times each statement of the program has run
+    pub statement_counts: Vec<u64>,
+    /// cards to pause before
+    pub breakpoints: BTreeSet<usize>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// the watchpoints the last statement set off
+    pub hits: Vec<Hit>,
+    /// whether statements are recorded so they can be stepped back over
+    pub tracing: bool,
+    /// the most recent statements, oldest first
+    pub trace: VecDeque<Record>,
+    /// the statement a run last paused before, so the next run can leave it
+    break_at: Option<usize>,
+}
+
+impl Machine {
+    pub fn new(program: Program) -> Machine {
+        Machine {
+            statement_cycles: vec![0; program.instructions.len()],
+            statement_counts: vec![0; program.instructions.len()],
+            program,
+            variables: HashMap::new(),
+            io: IO704::new(),
+            pc: 0,
+            do_statements: vec![],
+            halted: false,
+            executed: 0,
+            cycles: 0,
+            breakpoints: BTreeSet::new(),
+            watchpoints: vec![],
+            hits: vec![],
+            tracing: false,
+            trace: VecDeque::new(),
+            break_at: None,
+        }
+    }
+
+    /// Clears memory and the console back to the start of the program.
+    ///
+    /// The sense switches are set by the operator, so they are left alone.
+    pub fn reset(&mut self) {
+        self.variables.clear();
+        self.do_statements.clear();
+        self.pc = 0;
+        self.halted = false;
+        self.executed = 0;
+        self.cycles = 0;
+        self.statement_cycles = vec![0; self.program.instructions.len()];
+        self.statement_counts = vec![0; self.program.instructions.len()];
+        self.break_at = None;
+        self.hits.clear();
+        self.trace.clear();
+        self.io = IO704 {
+            sense_switches: self.io.sense_switches.clone(),
+            ..IO704::new()
+        };
+    }
+
+    /// The card of the statement that runs next.
+    pub fn card(&self) -> Option<usize> {
+        self.program.instructions.get(self.pc).map(|x| x.span.card)
+    }
+
+    pub fn state(&self) -> MachineState {
+        MachineState {
+            line_num: self.pc,
+            io: self.io.clone(),
+            variables: self.variables.clone(),
+            do_statements: self.do_statements.clone(),
+        }
+    }
+
+    pub fn run_until_halt(&mut self) -> Result<(), RuntimeError> {
+        while self.step()? != Step::Halted {}
+        Ok(())
+    }
+
+    /// Runs at most `budget` statements, so a caller can give control back
+    /// between slices.
+    ///
+    /// # Returns Halted if the program stopped, Break at a breakpoint, Watch
+    /// after a watchpoint, Io if anything on the console changed
+    pub fn run_for(&mut self, budget: usize) -> Result<Step, RuntimeError> {
+        let mut result = Step::Ran;
+        for _ in 0..budget {
+            if self.at_breakpoint() && self.break_at != Some(self.pc) {
+                self.break_at = Some(self.pc);
+                return Ok(Step::Break);
+            }
+            match self.step()? {
+                Step::Ran | Step::Break => {}
+                Step::Io => result = Step::Io,
+                Step::Watch => return Ok(Step::Watch),
+                Step::Halted => return Ok(Step::Halted),
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn at_breakpoint(&self) -> bool {
+        self.card().is_some_and(|x| self.breakpoints.contains(&x))
+    }
+
+    /// Undoes the last statement in the trace.
+    ///
+    /// # Returns false when there is nothing left to undo
+    pub fn step_back(&mut self) -> bool {
+        let Some(record) = self.trace.pop_back() else {
+            return false;
+        };
+        for write in record.writes.iter().rev() {
+            match write.old {
+                Some(old) => {
+                    if let Some(x) = self
+                        .variables
+                        .get_mut(&write.name)
+                        .and_then(|x| x.get_mut(write.index))
+                    {
+                        *x = old;
+                    }
+                }
+                None => {
+                    self.variables.remove(&write.name);
+                }
+            }
+        }
+        let before = record.before;
+        self.pc = record.statement;
+        self.do_statements = before.do_statements;
+        self.io.sense_lights = before.sense_lights;
+        self.io.display = before.display;
+        self.io.stop_light = before.stop_light;
+        self.io.print.truncate(before.printed);
+        self.halted = before.halted;
+        self.executed = self.executed.saturating_sub(1);
+        if let Some(x) = self.statement_cycles.get_mut(record.statement) {
+            *x = x.saturating_sub(self.cycles - before.cycles);
+        }
+        self.cycles = before.cycles;
+        if let Some(x) = self.statement_counts.get_mut(record.statement) {
+            *x = x.saturating_sub(1);
+        }
+        self.hits.clear();
+        // running forward again starts with this statement, even on a breakpoint
+        self.break_at = Some(self.pc);
+        true
+    }
+
+    /// Steps back until the statement that runs next is on a card with a
+    /// breakpoint.
+    ///
+    /// # Returns false if the start of the trace came first
+    pub fn run_back(&mut self) -> bool {
+        while self.step_back() {
+            if self.at_breakpoint() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The run as it stands, with the deck it was compiled from.
+    pub fn snapshot(&self, text: String, line_data: Vec<LineData>) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            text,
+            line_data,
+            program: self.program.clone(),
+            variables: self.variables.clone().into_iter().collect(),
+            io: self.io.clone(),
+            pc: self.pc,
+            do_statements: self.do_statements.clone(),
+            halted: self.halted,
+            executed: self.executed,
+            cycles: self.cycles,
+            statement_cycles: self.statement_cycles.clone(),
+            statement_counts: self.statement_counts.clone(),
+            breakpoints: self.breakpoints.clone(),
+            break_at: self.break_at,
+        }
+    }
+
+    /// A machine that carries on from a snapshot. The trace starts again
+    /// from here.
+    pub fn restore(snapshot: Snapshot) -> Machine {
+        Machine {
+            variables: snapshot.variables.into_iter().collect(),
+            io: snapshot.io,
+            pc: snapshot.pc,
+            do_statements: snapshot.do_statements,
+            halted: snapshot.halted,
+            executed: snapshot.executed,
+            cycles: snapshot.cycles,
+            statement_cycles: snapshot.statement_cycles,
+            statement_counts: snapshot.statement_counts,
+            breakpoints: snapshot.breakpoints,
+            break_at: snapshot.break_at,
+            ..Machine::new(snapshot.program)
+        }
+    }
+
+    fn before(&self) -> Before {
+        Before {
+            do_statements: self.do_statements.clone(),
+            sense_lights: self.io.sense_lights.clone(),
+            display: self.io.display,
+            stop_light: self.io.stop_light,
+            printed: self.io.print.len(),
+            halted: self.halted,
+            cycles: self.cycles,
+        }
+    }
+
+    /// Stops the program where it is, as the operator's stop key would.
+    pub fn interrupt(&mut self) {
+        self.halted = true;
+        self.io.stop_light = true;
+    }
+
+    /// Runs one statement of the program.
+    pub fn step(&mut self) -> Result<Step, RuntimeError> {
+        if self.halted {
+            return Ok(Step::Halted);
+        }
+        let Some(instruction) = self.program.instructions.get(self.pc) else {
+            self.halted = true;
+            self.io.stop_light = true;
+            return Ok(Step::Halted);
+        };
+        self.executed += 1;
+        self.break_at = None;
+        self.hits.clear();
+        let span = &instruction.span;
+        let label = instruction.label;
+        let watching = !self.watchpoints.is_empty();
+        let before = self.tracing.then(|| self.before());
+        let statement = self.pc;
+        let mut cycles = instruction.cycles;
+        let arrays = &self.program.arrays;
+        let functions = &self.program.functions;
+        let Machine {
+            variables,
+            io,
+            pc,
+            do_statements,
+            ..
+        } = self;
+
+        // nothing is stored before a statement fails, so this is the state it started in
+        macro_rules! fail {
+            ($kind:expr) => {{
+                let state = Box::new(self.state());
+                self.halted = true;
+                self.io.stop_light = true;
+                return Err(RuntimeError {
+                    kind: $kind,
+                    span: span.clone(),
+                    state,
+                });
+            }};
+        }
+        macro_rules! evaluate {
+            ($expr:expr) => {{
+                let evaluator = Evaluator {
+                    variables,
+                    arrays,
+                    functions,
+                    arguments: HashMap::new(),
+                };
+                match evaluator.evaluate($expr) {
+                    Ok(value) => value,
+                    Err(kind) => fail!(kind),
+                }
+            }};
+        }
+
+        let mut step = Step::Ran;
+        let mut jump = None;
+        // what was stored, with the values it replaced, for the watchpoints and the trace
+        let mut stored: Vec<(String, usize, Option<Value>)> = vec![];
+        macro_rules! store {
+            ($name:expr, $index:expr) => {
+                if watching || before.is_some() {
+                    let old = variables.get($name).and_then(|x| x.get($index)).copied();
+                    stored.push(($name.to_owned(), $index, old));
+                }
+            };
+        }
+
+        match &instruction.op {
+            Op::Assign {
+                variable,
+                subscripts,
+                value,
+            } => {
+                let value = evaluate!(value).convert_for(variable);
+                if subscripts.is_empty() {
+                    store!(variable, 0);
+                    variables.insert(variable.to_owned(), vec![value]);
+                } else {
+                    let evaluator = Evaluator {
+                        variables,
+                        arrays,
+                        functions,
+                        arguments: HashMap::new(),
+                    };
+                    let index = match evaluator.element(variable, subscripts) {
+                        Ok(a) => a,
+                        Err(kind) => fail!(kind),
+                    };
+                    let size = arrays.get(variable).and_then(|x| array_words(x)).unwrap_or(1);
+                    store!(variable, index);
+                    let array = variables.entry(variable.to_owned()).or_default();
+                    if array.len() < size {
+                        array.resize(size, Value::zero(variable));
+                    }
+                    if let Some(x) = array.get_mut(index) {
+                        *x = value;
+                    }
+                }
+            }
+            Op::GoTo(a) => jump = Some(*a),
+            Op::ComputedGoTo { targets, index } => {
+                let i = evaluate!(&Expr::Variable(index.to_owned())).as_fixed();
+                match targets.get((i - 1).max(0) as usize) {
+                    Some(a) if i >= 1 => jump = Some(*a),
+                    _ => fail!(ErrorKind::BranchOutOfRange {
+                        index: index.to_owned(),
+                        value: i,
+                        branches: targets.len(),
+                    }),
+                }
+            }
+            Op::If { value, targets } => {
+                let value = evaluate!(value).as_floating();
+                jump = Some(if value < 0.0 {
+                    targets[0]
+                } else if value == 0.0 {
+                    targets[1]
+                } else {
+                    targets[2]
+                });
+            }
+            Op::IfSenseLight { light, targets } => {
+                let index = (*light - 1) as usize;
+                // testing a light turns it off
+                if io.sense_lights.get(index) == Some(&true) {
+                    io.sense_lights[index] = false;
+                    step = Step::Io;
+                    jump = Some(targets[0]);
+                } else {
+                    jump = Some(targets[1]);
+                }
+            }
+            Op::IfSenseSwitch { switch, targets } => {
+                if io.sense_switches.get((*switch - 1) as usize) == Some(&true) {
+                    jump = Some(targets[0]);
+                } else {
+                    jump = Some(targets[1]);
+                }
+            }
+            Op::SenseLight(a) => {
+                if *a == 0 {
+                    io.sense_lights = vec![false; 4];
+                } else if let Some(light) = io.sense_lights.get_mut((*a - 1) as usize) {
+                    *light = true;
+                } else {
+                    fail!(ErrorKind::NoSuchSenseLight(*a));
+                }
+                step = Step::Io;
+            }
+            Op::Do {
+                end,
+                variable,
+                start,
+                limit,
+                step: increment,
+            } => {
+                let do_statement = DoStatement {
+                    start: *pc,
+                    end: *end,
+                    max: evaluate!(limit).as_fixed(),
+                    step: evaluate!(increment).as_fixed(),
+                    variable: variable.to_owned(),
+                };
+                let first = evaluate!(start).convert_for(variable);
+                store!(variable, 0);
+                variables.insert(variable.to_owned(), vec![first]);
+                do_statements.push(do_statement);
+            }
+            Op::Print(items) => {
+                let mut line = vec![];
+                for item in items {
+                    line.push(evaluate!(item).to_string());
+                }
+                let line = line.join(" ");
+                info!("{}", line);
+                // the printer only has the 704 character set
+                io.print.push_str(
+                    &line
+                        .chars()
+                        .map(|c| if bcd::is_bcd(c) { c } else { ' ' })
+                        .collect::<String>(),
+                );
+                io.print.push('\n');
+                step = Step::Io;
+            }
+            Op::Pause(a) => {
+                io.display = a.unwrap_or(0);
+                step = Step::Io;
+            }
+            Op::Stop(a) => {
+                io.display = a.unwrap_or(0);
+                io.stop_light = true;
+                self.halted = true;
+                step = Step::Halted;
+            }
+            Op::Continue => {}
+        }
+
+        match jump {
+            // STOP leaves the program where it stopped
+            _ if self.halted => {}
+            Some(target) => {
+                // leaving a DO range by a transfer ends the loop
+                while let Some(statement) = do_statements.last() {
+                    if target > statement.start && target <= statement.end {
+                        break;
+                    }
+                    do_statements.pop();
+                }
+                *pc = target;
+            }
+            None => {
+                *pc += 1;
+                // several loops may end on the same statement
+                while let Some(statement) = do_statements.last_mut() {
+                    if *pc - 1 != statement.end {
+                        break;
+                    }
+                    let current = variables
+                        .get(&statement.variable)
+                        .and_then(|x| x.first())
+                        .map_or(0, |x| x.as_fixed())
+                        + statement.step;
+                    cycles += timing::TIX;
+                    if current <= statement.max {
+                        store!(&statement.variable, 0);
+                        variables.insert(
+                            statement.variable.to_owned(),
+                            vec![Value::Fixed(current).convert_for(&statement.variable)],
+                        );
+                        *pc = statement.start + 1;
+                        break;
+                    }
+                    do_statements.pop();
+                }
+            }
+        }
+
+        self.cycles += cycles;
+        if let Some(x) = self.statement_cycles.get_mut(statement) {
+            *x += cycles;
+        }
+        if let Some(x) = self.statement_counts.get_mut(statement) {
+            *x += 1;
+        }
+
+        let mut writes = vec![];
+        for (name, index, old) in stored {
+            let Some(value) = self.variables.get(&name).and_then(|x| x.get(index)).copied() else {
+                continue;
+            };
+            let old_value = old.unwrap_or(Value::zero(&name));
+            for watchpoint in self
+                .watchpoints
+                .iter()
+                .filter(|x| x.name == name && x.index == index && x.hits(old_value, value))
+            {
+                self.hits.push(Hit {
+                    watchpoint: watchpoint.clone(),
+                    card: span.card,
+                    old: old_value,
+                    value,
+                });
+                step = Step::Watch;
+            }
+            writes.push(Write {
+                name,
+                index,
+                old,
+                value,
+            });
+        }
+
+        if let Some(before) = before {
+            self.trace.push_back(Record {
+                statement,
+                card: span.card,
+                label,
+                writes,
+                events: before.events(&self.io),
+                before,
+            });
+            if self.trace.len() > TRACE_LIMIT {
+                self.trace.pop_front();
+            }
+        }
+
+        Ok(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::import_cards;
+    use crate::compile::compile;
+    use crate::compute::process;
+    use crate::trace;
+
+    fn program(deck: &str) -> Program {
+        let (text, line_data, _) = import_cards(deck);
+        let (statements, diagnostics) = process(text, line_data);
+        assert_eq!(diagnostics, vec![]);
+        compile(&statements).unwrap()
+    }
+
+    #[test]
+    fn a_runtime_error_keeps_the_machine_as_the_statement_found_it() {
+        let mut machine = Machine::new(program(
+            "      X = 2.0
+      DO 10 I = 1, 3
+      GO TO (10, 10), I
+   10 CONTINUE
+      STOP
+",
+        ));
+        let error = machine.run_until_halt().unwrap_err();
+        assert!(matches!(
+            error.kind,
+            ErrorKind::BranchOutOfRange {
+                value: 3,
+                branches: 2,
+                ..
+            }
+        ));
+        assert_eq!(error.card(), 2);
+        assert_eq!(error.state.line_num, 2);
+        assert_eq!(error.state.variables["X"], vec![Value::Floating(2.0)]);
+        assert_eq!(error.state.variables["I"], vec![Value::Fixed(3)]);
+        assert_eq!(error.state.do_statements.len(), 1);
+        assert_eq!(error.state.do_statements[0].end, 3);
+        assert!(machine.halted);
+        assert!(machine.io.stop_light);
+    }
+
+    #[test]
+    fn a_run_gives_control_back_after_its_budget() {
+        let mut machine = Machine::new(program(
+            "   10 I = I + 1
+      GO TO 10
+",
+        ));
+        assert_eq!(machine.run_for(1000).unwrap(), Step::Ran);
+        assert_eq!(machine.executed, 1000);
+        assert_eq!(machine.variables["I"], vec![Value::Fixed(500)]);
+        assert_eq!(machine.run_for(0).unwrap(), Step::Ran);
+        assert_eq!(machine.executed, 1000);
+
+        machine.interrupt();
+        assert!(machine.io.stop_light);
+        assert_eq!(machine.run_for(1000).unwrap(), Step::Halted);
+        assert_eq!(machine.executed, 1000);
+    }
+
+    #[test]
+    fn a_slice_reports_the_console_changing() {
+        let mut machine = Machine::new(program(
+            "      PAUSE 7
+      I = 1
+      STOP 3
+",
+        ));
+        assert_eq!(machine.run_for(2).unwrap(), Step::Io);
+        assert_eq!(machine.io.display, 7);
+        assert_eq!(machine.run_for(2).unwrap(), Step::Halted);
+        assert_eq!(machine.io.display, 3);
+        assert_eq!(machine.executed, 3);
+    }
+
+    /// Everything stepping back has to put back.
+    #[derive(Debug, PartialEq)]
+    struct Run {
+        pc: usize,
+        variables: HashMap<String, Vec<Value>>,
+        do_statements: Vec<DoStatement>,
+        executed: u64,
+        cycles: u64,
+        statement_cycles: Vec<u64>,
+        statement_counts: Vec<u64>,
+        sense_lights: Vec<bool>,
+        display: i32,
+        stop_light: bool,
+        print: String,
+        halted: bool,
+    }
+
+    fn run_state(machine: &Machine) -> Run {
+        Run {
+            pc: machine.pc,
+            variables: machine.variables.clone(),
+            do_statements: machine.do_statements.clone(),
+            executed: machine.executed,
+            cycles: machine.cycles,
+            statement_cycles: machine.statement_cycles.clone(),
+            statement_counts: machine.statement_counts.clone(),
+            sense_lights: machine.io.sense_lights.clone(),
+            display: machine.io.display,
+            stop_light: machine.io.stop_light,
+            print: machine.io.print.clone(),
+            halted: machine.halted,
+        }
+    }
+
+    const STORES: &str = "      DIMENSION A(3)
+      DO 10 I = 1, 3
+   10 A(I) = FLOATF(I) * 2.0
+      SENSE LIGHT 2
+    1 FORMAT(F8.2)
+      PRINT 1, A(3)
+      PAUSE 7
+      STOP
+";
+
+    #[test]
+    fn stepping_back_undoes_each_statement_in_turn() {
+        let mut machine = Machine::new(program(STORES));
+        machine.tracing = true;
+        let mut states = vec![run_state(&machine)];
+        loop {
+            let step = machine.step().unwrap();
+            states.push(run_state(&machine));
+            if step == Step::Halted {
+                break;
+            }
+        }
+        assert_eq!(machine.trace.len(), states.len() - 1);
+        assert_eq!(machine.io.print, "6E0\n");
+        assert!(trace::export(&machine.trace).contains("\"Display\": 7"));
+
+        while let Some(state) = states.pop() {
+            assert_eq!(run_state(&machine), state);
+            assert_eq!(machine.step_back(), !states.is_empty());
+        }
+        // and forward again the same way
+        machine.run_until_halt().unwrap();
+        let mut fresh = Machine::new(program(STORES));
+        fresh.run_until_halt().unwrap();
+        assert_eq!(run_state(&machine), run_state(&fresh));
+    }
+
+    #[test]
+    fn running_back_stops_before_a_breakpoint() {
+        let mut machine = Machine::new(program(STORES));
+        machine.tracing = true;
+        machine.run_until_halt().unwrap();
+        machine.breakpoints.insert(2);
+        assert!(machine.run_back());
+        assert_eq!(machine.pc, 2);
+        assert_eq!(machine.variables["I"], vec![Value::Fixed(3)]);
+        assert_eq!(
+            machine.variables["A"],
+            vec![
+                Value::Floating(2.0),
+                Value::Floating(4.0),
+                Value::Floating(0.0)
+            ]
+        );
+        // running on leaves the breakpoint it stopped at
+        assert_eq!(machine.run_for(100).unwrap(), Step::Halted);
+        assert_eq!(machine.variables["A"][2], Value::Floating(6.0));
+        machine.breakpoints.clear();
+        assert!(!machine.run_back());
+        assert_eq!(machine.pc, 0);
+    }
+
+    #[test]
+    fn the_trace_forgets_the_oldest_statements() {
+        let mut machine = Machine::new(program(
+            "   10 I = I + 1
+      GO TO 10
+",
+        ));
+        machine.tracing = true;
+        machine.run_for(TRACE_LIMIT + 10).unwrap();
+        assert_eq!(machine.trace.len(), TRACE_LIMIT);
+        assert!(!machine.run_back());
+        assert_eq!(machine.executed, 10);
+        assert_eq!(machine.variables["I"], vec![Value::Fixed(5)]);
+    }
+}
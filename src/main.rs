@@ -1,13 +1,55 @@
+mod assembler;
+mod bcd;
+mod card;
+mod check;
+mod cli;
+mod compile;
 mod compute;
+mod cpu;
+mod diagnostics;
+mod inspector;
+mod machine;
+mod object;
+mod optimize;
+mod parse;
+mod sap;
+mod snapshot;
+mod storage;
+mod timing;
+mod trace;
+mod watch;
+mod word;
+mod worker;
+use card::*;
+use compile::*;
 use compute::*;
+use diagnostics::*;
+use machine::*;
+use snapshot::Snapshot;
+use timing::{format_time, not_counted};
+use watch::Watchpoint;
+use worker::{FromWorker, ToWorker};
 use log::info;
 use log::Level;
-use std::collections::HashMap;
 // extern crate console_error_panic_hook;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::panic;
 
+use serde::{Deserialize, Serialize};
 use sycamore::prelude::*;
-#[derive(Clone, Debug)]
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+extern "C" {
+    /// Offers text to the user as a download, see `saveFile` in index.html.
+    #[wasm_bindgen(js_name = saveFile)]
+    fn save_file(name: &str, text: &str);
+}
+
+/// Statements run per click of the run button before the page gets control back.
+const SLICE: usize = 10_000;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct IO704 {
     pub sense_switches: Vec<bool>,
     pub sense_lights: Vec<bool>,
@@ -17,7 +59,28 @@ pub struct IO704 {
     
 
 }
-#[derive(Clone, Debug, PartialEq, Copy, Hash, Eq)]
+
+impl IO704 {
+    /// The console as it is switched on: 4 lights off, the switches up and
+    /// nothing printed.
+    pub fn new() -> IO704 {
+        IO704 {
+            sense_switches: vec![false; 20],
+            sense_lights: vec![false; 4],
+            display: 0,
+            stop_light: false,
+            print: "".to_string(),
+        }
+    }
+}
+
+impl Default for IO704 {
+    fn default() -> Self {
+        IO704::new()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Copy, Hash, Eq, Serialize, Deserialize)]
 pub struct LineData {
     pub number: i32,
     pub continuation: bool,
@@ -26,11 +89,22 @@ pub struct LineData {
 }
 
 fn main() {
+    if !cfg!(target_arch = "wasm32") {
+        return cli::main();
+    }
     panic::set_hook(Box::new(console_error_panic_hook::hook));
-    console_log::init_with_level(Level::Debug);
-    
+    let _ = console_log::init_with_level(Level::Debug);
+    // the same module is loaded by the worker, which has no window
+    if web_sys::window().is_some() {
+        ui()
+    } else {
+        worker::main()
+    }
+}
+
+fn ui() {
     sycamore::render(|cx| {
-        let sense_switches = create_signal(cx, vec![(0, false); 20].iter().enumerate().map(|(i, x)| {
+        let sense_switches = create_signal(cx, [(0, false); 20].iter().enumerate().map(|(i, x)| {
             (i, x.1)
         }).collect::<Vec<(usize, bool)>>());
 
@@ -43,45 +117,275 @@ fn main() {
         let a = create_signal(cx, "".to_owned());
 
 
-        let tokens = create_memo(cx, move || process(input.get().to_string(), line_info.get().to_vec()));
+        let compiled = create_memo(cx, move || process(input.get().to_string(), line_info.get().to_vec()));
+        // what was wrong with the card images of the last deck loaded
+        let deck_diagnostics = create_signal(cx, Vec::<Diagnostic>::new());
+        let diagnostics = create_memo(cx, move || {
+            [deck_diagnostics.get().as_slice(), compiled.get().1.as_slice()].concat()
+        });
+        // only a deck without errors is compiled, the statement numbers are all there
+        let program = create_memo(cx, move || {
+            if has_errors(&diagnostics.get()) {
+                None
+            } else {
+                compile(&compiled.get().0).ok()
+            }
+        });
+        let runtime_error = create_signal(cx, None::<RuntimeError>);
         let display = create_signal(cx, 0);
         let stop_light = create_signal(cx, true);
+        // simulated 704 time, in machine cycles
+        let clock = create_signal(cx, 0u64);
 
-        let edit_line_text = create_signal(cx, "".to_string());
+        let deck = create_signal(cx, "".to_string());
+        // the text of a deck just loaded, until the editor has caught up with it
+        let imported = create_signal(cx, "".to_string());
 
         let do_loop = create_signal(cx, false);
-        let current_line = create_signal(cx, 0);
-
-        let variables = create_signal(cx, HashMap::<String, Vec<Token>>::new());
-        let do_statements = create_signal(cx, vec![]);
-        
-
-
+        let console_message = create_signal(cx, "".to_string());
+        // the page records a trace so the debugger can step back
+        let machine = create_signal(cx, Machine::new(Program::default()));
+        machine.modify().tracing = true;
+        let printer = create_signal(cx, "".to_string());
+
+        // in worker mode the program runs off the main thread and its console is sent back
+        let use_worker = create_signal(cx, false);
+        let worker_running = create_signal(cx, false);
+        // memory can only be changed by hand while nothing is running
+        let paused = create_memo(cx, move || !*do_loop.get() && !*worker_running.get());
+        let worker = create_signal(cx, None::<worker::Handle>);
+        let from_worker = create_rc_signal(None::<FromWorker>);
+
+        create_effect(cx, {
+            let from_worker = from_worker.clone();
+            move || {
+                let Some(message) = from_worker.get().as_ref().clone() else {return};
+                match message {
+                    // taken by `worker::spawn` to let through what was waiting
+                    FromWorker::Ready => {}
+                    FromWorker::Console { sense_lights: lights, display: number, stop_light: stop, cycles } => {
+                        clock.set(cycles);
+                        sense_lights.set(lights);
+                        display.set(number);
+                        stop_light.set(stop);
+                    }
+                    FromWorker::Print(line) => {
+                        let mut printer = printer.modify();
+                        printer.push_str(&line);
+                        printer.push('\n');
+                    }
+                    FromWorker::Halted { .. } => worker_running.set(false),
+                    FromWorker::Interrupted { card, executed } => {
+                        worker_running.set(false);
+                        console_message.set(format!("interrupted at card {} after {} statements", card + 1, executed));
+                    }
+                    FromWorker::Error(error) => {
+                        worker_running.set(false);
+                        stop_light.set(true);
+                        runtime_error.set(Some(error));
+                    }
+                }
+            }
+        });
 
-        // let print_text = cre
+        // the debugger's view of the machine
+        let breakpoints = create_signal(cx, BTreeSet::<usize>::new());
+        let break_label = create_signal(cx, "".to_string());
+        // statement numbers to break at, found again on the cards whenever the deck changes
+        let break_labels = create_signal(cx, BTreeSet::<i32>::new());
+        let label_cards = create_memo(cx, move || {
+            let program = program.get();
+            let Some(program) = program.as_ref() else {return BTreeMap::new()};
+            break_labels.get().iter()
+                .filter_map(|label| program.instructions.iter()
+                    .find(|x| x.label == *label)
+                    .map(|x| (x.span.card, *label)))
+                .collect::<BTreeMap<usize, i32>>()
+        });
+        let break_cards = create_memo(cx, move || {
+            let mut cards = breakpoints.get().as_ref().clone();
+            cards.extend(label_cards.get().keys());
+            cards
+        });
+        let timing = create_memo(cx, move || {
+            let machine = machine.get();
+            machine.program.instructions.iter().zip(&machine.statement_cycles)
+                .filter(|x| *x.1 > 0)
+                .map(|(instruction, cycles)| format!("card {}: {} ({:.1}%)",
+                    instruction.span.card + 1,
+                    format_time(*cycles),
+                    *cycles as f64 * 100.0 / machine.cycles.max(1) as f64))
+                .collect::<Vec<String>>()
+        });
+        // runs and share of the busiest statement's time, by the card each statement starts on
+        let heat = create_memo(cx, move || {
+            let machine = machine.get();
+            let busiest = machine.statement_cycles.iter().copied().max().unwrap_or(0).max(1);
+            machine.program.instructions.iter()
+                .zip(machine.statement_counts.iter().zip(&machine.statement_cycles))
+                .filter(|x| x.0.executable)
+                .map(|(instruction, (count, cycles))| {
+                    (instruction.span.card, (*count, *cycles as f64 / busiest as f64))
+                })
+                .collect::<HashMap<usize, (u64, f64)>>()
+        });
+        // the optimizer passes the 704 code is generated with
+        let fold_pass = create_signal(cx, false);
+        let common_pass = create_signal(cx, false);
+        let invariant_pass = create_signal(cx, false);
+        let index_pass = create_signal(cx, false);
+        let passes = create_memo(cx, move || optimize::Passes {
+            fold: *fold_pass.get(),
+            common: *common_pass.get(),
+            invariant: *invariant_pass.get(),
+            index: *index_pass.get(),
+        });
+        // each card beside the SAP generated for it, without and with the passes
+        let assembly = create_memo(cx, move || {
+            let Some(program) = program.get().as_ref().clone() else {return vec![]};
+            let before = sap::generate(&program);
+            let after = match passes.get().any() {
+                true => sap::generate_with(&program, *passes.get()),
+                false => sap::Listing::default(),
+            };
+            let text = input.get();
+            let cards = text.lines().collect::<Vec<&str>>();
+            sap::compare(&before, &after).into_iter().map(|(card, before, after)| {
+                let fortran = card.map_or("".to_string(), |x| {
+                    let label = line_info.get().get(x).map_or(0, |x| x.label);
+                    let label = if label == 0 { "".to_string() } else { label.to_string() };
+                    format!("{:<5} {}", label, cards.get(x).unwrap_or(&""))
+                });
+                (fortran, before, after)
+            }).collect::<Vec<(String, String, String)>>()
+        });
+        // where the compiled program would go in a 704 of the chosen size
+        let core_size = create_signal(cx, "32K".to_string());
+        let storage_map = create_memo(cx, move || {
+            let Some(program) = program.get().as_ref().clone() else {return "".to_string()};
+            let size = cpu::CoreSize::parse(&core_size.get()).unwrap_or(cpu::CoreSize::K32);
+            match storage::allocate(&program, size, *passes.get()) {
+                Ok((_, _, map)) => map.to_string(),
+                Err(message) => message,
+            }
+        });
+        let do_stack = create_memo(cx, move || {
+            let machine = machine.get();
+            machine.do_statements.iter().rev().map(|x| {
+                let card = machine.program.instructions.get(x.start).map_or(0, |i| i.span.card + 1);
+                let value = machine.variables.get(&x.variable).and_then(|v| v.first()).map_or("0".to_string(), |v| v.to_string());
+                format!("card {}: {} = {} (to {} by {})", card, x.variable, value, x.max, x.step)
+            }).collect::<Vec<String>>()
+        });
+        create_effect(cx, move || {
+            let cards = break_cards.get().as_ref().clone();
+            machine.modify().breakpoints = cards;
+        });
+        // kept as typed, so they follow the deck when it is edited
+        let watches = create_signal(cx, Vec::<String>::new());
+        let watch_text = create_signal(cx, "".to_string());
+        create_effect(cx, move || {
+            let arrays = program.get().as_ref().clone().unwrap_or_default().arrays;
+            let watchpoints = watches.get().iter()
+                .filter_map(|x| Watchpoint::parse(x, &arrays).ok())
+                .collect();
+            machine.modify().watchpoints = watchpoints;
+        });
 
-        let io = create_memo(cx, move || IO704 {
-            sense_switches: sense_switches.get().iter().map(|(i,x)| *x).collect::<Vec<bool>>(),
-            sense_lights: sense_lights.get().to_vec(),
-            display: 0,
-            stop_light: true,
-            print: "".to_string(),
+        // shows what a slice or a single step did on the console
+        let finish = move |machine: &Machine, result: Result<Step, RuntimeError>| {
+            match result {
+                Ok(step) => {
+                    if step != Step::Ran {
+                        printer.set(machine.io.print.clone());
+                    }
+                    display.set(machine.io.display);
+                    sense_lights.set(machine.io.sense_lights.clone());
+                    stop_light.set(machine.io.stop_light);
+                    clock.set(machine.cycles);
+                    if step == Step::Break || step == Step::Watch || step == Step::Halted {
+                        do_loop.set(false);
+                    }
+                    if step == Step::Watch {
+                        console_message.set(machine.hits.iter()
+                            .map(|x| format!("card {}: {}", x.card + 1, x))
+                            .collect::<Vec<String>>()
+                            .join("; "));
+                    }
+                    if step == Step::Break {
+                        console_message.set(format!("breakpoint at card {}", machine.card().map_or(0, |x| x + 1)));
+                    }
+                }
+                Err(error) => {
+                    info!("{}", error.diagnostic());
+                    do_loop.set(false);
+                    stop_light.set(true);
+                    runtime_error.set(Some(error));
+                }
+            }
+        };
+        // a snapshot file is read into a hidden textarea, see `loadDeck`
+        let snapshot_text = create_signal(cx, "".to_string());
+        create_effect(cx, move || {
+            if snapshot_text.get().is_empty() {
+                return;
+            }
+            match Snapshot::from_json(&snapshot_text.get()) {
+                Ok(snapshot) => {
+                    line_info.set(snapshot.line_data.clone());
+                    input.set(snapshot.text.clone());
+                    let breakpoints_at = snapshot.breakpoints.clone();
+                    let mut restored = Machine::restore(snapshot);
+                    restored.tracing = true;
+                    do_loop.set(false);
+                    runtime_error.set(None);
+                    console_message.set(format!("restored at card {} after {} statements",
+                        restored.card().map_or(0, |x| x + 1), restored.executed));
+                    finish(&restored, Ok(Step::Io));
+                    machine.set(restored);
+                    break_labels.set(BTreeSet::new());
+                    breakpoints.set(breakpoints_at);
+                }
+                Err(message) => console_message.set(message),
+            }
+            snapshot_text.set("".to_string());
+        });
+        // loads the program and stops before its first statement
+        let reset = move || {
+            runtime_error.set(None);
+            console_message.set("".to_string());
+            printer.set("".to_string());
+            sense_lights.set(vec![false; 4]);
+            display.set(0);
+            stop_light.set(false);
+            clock.set(0);
+            let mut machine = machine.modify();
+            machine.program = program.get().as_ref().clone().unwrap_or_default();
+            machine.reset();
+        };
+
+        create_effect(cx, move || {
+            let switches = sense_switches.get().iter().map(|(_, x)| *x).collect::<Vec<bool>>();
+            if let Some(worker) = worker.get_untracked().as_ref() {
+                worker::send(worker, &ToWorker::SenseSwitches(switches));
+            }
         });
 
-        // create_effect(cx, || {
-        //     sense_lights = io.get().sense_lights.clone();
-        //     sense_switches = io.get().sense_switches.clone();
-            
-        // });
 
         create_effect(cx, || {
             let num_of_lines = input.get().replace("\n","\n ").lines().count();
             if num_of_lines == 0 {return}
+            // an imported deck sets the line data before the text, so there is nothing to sync
+            if *input.get() == *imported.get_untracked() {
+                imported.set(String::new());
+                return;
+            }
             let mut new_line_info:Vec<LineData> = (*line_info.get_untracked()).clone();
             // get element by id with web sys
-            let document = web_sys::window().unwrap().document().unwrap();
-            let edit = document.get_element_by_id("lineNo").unwrap();
-            let current_line2 = edit.inner_html().parse::<usize>().unwrap();
+            let Some(document) = web_sys::window().and_then(|x| x.document()) else {return};
+            let Some(edit) = document.get_element_by_id("lineNo") else {return};
+            // before the first key press there is no cursor line, so edit the end of the deck
+            let current_line2 = edit.inner_html().parse::<usize>().unwrap_or(new_line_info.len());
             
             if num_of_lines > new_line_info.len() {
                 let new_data = LineData {
@@ -100,14 +404,14 @@ fn main() {
                 if current_line2 >= new_line_info.len() {
                     new_line_info.pop();
                 } else {
-                    new_line_info.remove(current_line2-1);
+                    // the line joined to the one above it, or the first line was deleted
+                    new_line_info.remove(current_line2.saturating_sub(1));
                 }
-                // new_line_info.remove(current_line2-1);
             }
             
         
-            for i in 0..new_line_info.len() {
-                new_line_info[i].number = i as i32;
+            for (i, data) in new_line_info.iter_mut().enumerate() {
+                data.number = i as i32;
             }
 
             line_info.set(new_line_info);
@@ -135,6 +439,10 @@ fn main() {
             p{("Stop Light")}
             input(type="radio", checked=*stop_light.get()) {}
         }
+        div(class="horizontal labeled") {
+            p{("704 Time")}
+            span(class="clock") { (format_time(*clock.get())) }
+        }
 
             div(class="horizontal labeled") {
             p{("Sense Switches")}
@@ -156,14 +464,259 @@ fn main() {
             )
             }
 
+            div(class="horizontal labeled") {
+                p{("Worker")}
+                input(type="checkbox", class="input-button", bind:checked=use_worker) {}
+            }
+
             button(class="start", on:click=move |_| {
-                do_loop.set(true);
-                current_line.set(0);
+                reset();
+                if *use_worker.get() && program.get().is_some() {
+                    if worker.get().is_none() {
+                        let from_worker = from_worker.clone();
+                        worker.set(worker::spawn(move |message| from_worker.set(Some(message))));
+                    }
+                    if let Some(worker) = worker.get().as_ref() {
+                        worker::send(worker, &ToWorker::Load {
+                            text: input.get().to_string(),
+                            line_data: line_info.get().to_vec(),
+                        });
+                        worker::send(worker, &ToWorker::SenseSwitches(
+                            sense_switches.get().iter().map(|(_, x)| *x).collect(),
+                        ));
+                        worker::send(worker, &ToWorker::Start);
+                        worker_running.set(true);
+                    }
+                    return;
+                }
+                // a deck with errors is not run
+                do_loop.set(program.get().is_some());
             }) {
                 "start"
             }
+            button(class="stop", disabled=!*do_loop.get() && !*worker_running.get(), on:click=move |_| {
+                if *worker_running.get() {
+                    if let Some(worker) = worker.get().as_ref() {
+                        worker::send(worker, &ToWorker::Stop);
+                    }
+                    return;
+                }
+                do_loop.set(false);
+                stop_light.set(true);
+                let mut machine = machine.modify();
+                machine.interrupt();
+                let message = format!(
+                    "interrupted at card {} after {} statements",
+                    machine.card().map_or(0, |x| x + 1),
+                    machine.executed
+                );
+                info!("{}", message);
+                console_message.set(message);
+            }) {
+                "stop"
+            }
+            button(on:click=move |_| {
+                do_loop.set(false);
+                reset();
+            }) {
+                "reset"
+            }
+            button(disabled=*do_loop.get() || machine.get().halted, on:click=move |_| {
+                let mut machine = machine.modify();
+                machine.io.sense_switches = sense_switches.get().iter().map(|(_, x)| *x).collect();
+                let result = machine.step();
+                finish(&machine, result);
+            }) {
+                "step"
+            }
+            button(disabled=*do_loop.get() || machine.get().trace.is_empty(), on:click=move |_| {
+                let mut machine = machine.modify();
+                machine.step_back();
+                runtime_error.set(None);
+                console_message.set("".to_string());
+                finish(&machine, Ok(Step::Io));
+            }) {
+                "step back"
+            }
+            button(disabled=*do_loop.get() || machine.get().trace.is_empty(), on:click=move |_| {
+                let mut machine = machine.modify();
+                runtime_error.set(None);
+                if machine.run_back() {
+                    console_message.set(format!("breakpoint at card {}", machine.card().map_or(0, |x| x + 1)));
+                } else {
+                    console_message.set("back at the start of the trace".to_string());
+                }
+                finish(&machine, Ok(Step::Io));
+            }) {
+                "run back"
+            }
+            button(disabled=*do_loop.get() || machine.get().halted || program.get().is_none(), on:click=move |_| {
+                console_message.set("".to_string());
+                do_loop.set(true);
+            }) {
+                "continue"
+            }
+            button(disabled=*do_loop.get() || machine.get().trace.is_empty(), on:click=move |_| {
+                save_file("trace.json", &trace::export(&machine.get().trace));
+            }) {
+                "export trace"
+            }
+            div(class="horizontal") {
+                input(type="text", class="break-label", placeholder="statement", bind:value=break_label) {}
+                button(on:click=move |_| {
+                    let Ok(label) = break_label.get().trim().parse::<i32>() else {return};
+                    let found = program.get().as_ref().as_ref()
+                        .is_some_and(|x| x.instructions.iter().any(|x| x.label == label));
+                    if found {
+                        let mut labels = break_labels.modify();
+                        if !labels.remove(&label) {
+                            labels.insert(label);
+                        }
+                    } else {
+                        console_message.set(format!("there is no statement {}", label));
+                    }
+                }) {
+                    "break at"
+                }
+            }
+            div(class="horizontal") {
+                input(type="text", class="watch-text", placeholder="X > 100.0", bind:value=watch_text) {}
+                button(on:click=move |_| {
+                    let text = watch_text.get().trim().to_uppercase();
+                    let arrays = program.get().as_ref().clone().unwrap_or_default().arrays;
+                    match Watchpoint::parse(&text, &arrays) {
+                        Ok(_) => {
+                            if !watches.get().contains(&text) {
+                                watches.modify().push(text);
+                            }
+                            watch_text.set("".to_string());
+                        }
+                        Err(message) => console_message.set(message),
+                    }
+                }) {
+                    "watch"
+                }
+            }
+            p(class="console-message") { (console_message.get()) }
         }
 
+            div(class="do-stack labeled") {
+                p{("DO Loops")}
+                ul {
+                    Indexed(
+                        iterable=do_stack,
+                        view=|cx, x| view! { cx, li { (x) } },
+                    )
+                }
+            }
+
+            (inspector::inspector(cx, machine, paused))
+
+            div(class="watchpoints labeled") {
+                p{("Watchpoints")}
+                ul {
+                    Keyed(
+                        iterable=watches,
+                        view=move |cx, x| {
+                            let text = x.clone();
+                            view! { cx,
+                                li(title="click to remove", on:click=move |_| {
+                                    watches.modify().retain(|w| *w != text);
+                                }) { (x) }
+                            }
+                        },
+                        key=|x| x.clone(),
+                    )
+                }
+            }
+
+            div(class="assembly labeled") {
+                p{("704 Assembly")}
+                div(class="horizontal") {
+                    label { input(type="checkbox", class="input-button", bind:checked=fold_pass) {} "fold constants" }
+                    label { input(type="checkbox", class="input-button", bind:checked=common_pass) {} "common subexpressions" }
+                    label { input(type="checkbox", class="input-button", bind:checked=invariant_pass) {} "hoist subscripts" }
+                    label { input(type="checkbox", class="input-button", bind:checked=index_pass) {} "index registers" }
+                }
+                table {
+                    Indexed(
+                        iterable=assembly,
+                        view=|cx, (fortran, before, after)| view! { cx,
+                            tr {
+                                td { pre { (fortran) } }
+                                td { pre { (before) } }
+                                td { pre { (after) } }
+                            }
+                        },
+                    )
+                }
+            }
+
+            div(class="storage labeled") {
+                p{("Storage Map")}
+                select(bind:value=core_size) {
+                    option(value="4K") { "4K" }
+                    option(value="8K") { "8K" }
+                    option(value="32K", selected=true) { "32K" }
+                }
+                pre { (storage_map.get()) }
+            }
+
+            div(class="timing labeled") {
+                p{("Time by Statement")}
+                p { (not_counted()) }
+                ul {
+                    Indexed(
+                        iterable=timing,
+                        view=|cx, x| view! { cx, li { (x) } },
+                    )
+                }
+            }
+
+            div(class="printer labeled") {
+                p{("Printer")}
+                pre { (printer.get()) }
+            }
+
+            div(class="deck labeled") {
+                p{("Card Deck")}
+                textarea(class="deck-input", id="deck-input", bind:value=deck,
+                    wrap="off",
+                    spellcheck=false,
+                    placeholder="paste 80 column card images here"
+                ) {}
+                div(class="horizontal") {
+                    input(type="file", class="input-button", accept=".f,.for,.ftn,.txt,.cards",
+                        onchange="loadDeck(this, document.getElementById('deck-input'));"
+                    ) {}
+                    button(on:click=move |_| {
+                        let (text, cards, found) = import_cards(&deck.get());
+                        deck_diagnostics.set(found);
+                        imported.set(text.clone());
+                        line_info.set(cards);
+                        input.set(text);
+                    }) {
+                        "Load Deck"
+                    }
+                }
+            }
+
+            div(class="snapshot labeled") {
+                p{("Snapshot")}
+                textarea(id="snapshot-input", class="hidden", bind:value=snapshot_text) {}
+                div(class="horizontal") {
+                    input(type="file", class="input-button", accept=".json",
+                        onchange="loadDeck(this, document.getElementById('snapshot-input')); this.value = '';"
+                    ) {}
+                    button(disabled=*do_loop.get() || *worker_running.get(), on:click=move |_| {
+                        let snapshot = machine.get().snapshot(input.get().to_string(), line_info.get().to_vec());
+                        save_file("snapshot.json", &snapshot.to_json());
+                    }) {
+                        "Save Snapshot"
+                    }
+                }
+            }
+
             div(class="card") {
                 div(class="card-header") {
                     div(class="card-header-title") {
@@ -172,6 +725,58 @@ fn main() {
                 }
                 div(class="card-content") {
                     div(class="content") {
+                        div(class="heat") {
+                            Keyed(
+                                iterable=line_info,
+                                view=move |cx, x| view! { cx,
+                                    (match heat.get().get(&(x.number as usize)).copied() {
+                                        // nothing has run yet, so nothing is uncovered
+                                        Some((0, _)) if machine.get().executed == 0 => view! { cx,
+                                            div(class="heat-box") { " " }
+                                        },
+                                        Some((0, _)) => view! { cx,
+                                            div(class="heat-box never-run", title="never run") { "#" }
+                                        },
+                                        Some((count, share)) => view! { cx,
+                                            div(class="heat-box", title=format!("run {} times", count),
+                                                style=format!("background-color: rgba(212, 84, 84, {:.2})", share)
+                                            ) { (count) }
+                                        },
+                                        None => view! { cx, div(class="heat-box") { " " } },
+                                    })
+                                },
+                                key=|x| *x,
+                            )
+                        }
+                        div(class="breakpoint") {
+                            Keyed(
+                                iterable=line_info,
+                                view=move |cx, x| view! { cx,
+                                    div(class=if machine.get().card() == Some(x.number as usize) && !machine.get().halted {
+                                        "breakpoint-box current"
+                                    } else {
+                                        "breakpoint-box"
+                                    }, on:click=move |_| {
+                                        let card = x.number as usize;
+                                        if let Some(label) = label_cards.get().get(&card) {
+                                            break_labels.modify().remove(label);
+                                            return;
+                                        }
+                                        let mut cards = breakpoints.modify();
+                                        if !cards.remove(&card) {
+                                            cards.insert(card);
+                                        }
+                                    }) {
+                                        (if break_cards.get().contains(&(x.number as usize)) {
+                                            "●"
+                                        } else {
+                                            " "
+                                        })
+                                    }
+                                },
+                                key=|x| *x,
+                            )
+                        }
                         div(class="comment") {
                             Keyed(
                                 iterable=line_info,
@@ -181,7 +786,7 @@ fn main() {
                                             if y.number == x.number {
                                                 LineData {
                                                     comment: !y.comment,
-                                                    ..y.clone()
+                                                    ..*y
                                                 }
                                             } else {
                                                 *y
@@ -221,7 +826,7 @@ fn main() {
                                             if z.number == x.number {
                                                 LineData {
                                                     label: a.get().parse::<i32>().unwrap_or(0).max(0),
-                                                    ..z.clone()
+                                                    ..*z
                                                 }
                                             } else {
                                                 *z
@@ -244,7 +849,7 @@ fn main() {
                                             if y.number == x.number {
                                                 LineData {
                                                     continuation: !y.continuation,
-                                                    ..y.clone()
+                                                    ..*y
                                                 }
                                             } else {
                                                 *y
@@ -268,55 +873,60 @@ fn main() {
                         oninput=format!("this.style.height = 'calc( ( 1em + 7px ) * {} )'", 8.0*((input.get().lines().count()+3)as f32/8.0).ceil()),
                         onkeyup="getLineNumber(this, document.getElementById('lineNo'));", onmouseup="this.onkeyup();"
                     ) {}
+                        div(class="diagnostics") {
+                            Keyed(
+                                iterable=line_info,
+                                view=move |cx, x| view! { cx,
+                                    div(class="diagnostic-box") {
+                                        (diagnostics.get().iter().cloned().chain(runtime_error.get().as_ref().as_ref().map(|x| x.diagnostic()))
+                                            .filter(|d| d.card() == x.number as usize)
+                                            .map(|d| format!("{}: {}", d.severity, d.message))
+                                            .collect::<Vec<String>>()
+                                            .join("; "))
+                                    }
+                                },
+                                key=|x| *x,
+                            )
+                        }
                             
                         }
 
                     }
                 }
 
-            
-
-            
-
             button(class="run",id="run-click",disabled=!*do_loop.get(), on:click=move |_| {
-                if *current_line.get() == 0 {
-                sense_lights.set(vec![false;4]);
-                variables.set(HashMap::<String, Vec<Token>>::new());
-                do_statements.set(vec![]);
-                }
-
-                let mut update_io = false;
-
-                // let mut a = 0;
                 if *do_loop.get() {
-                while !update_io {
-                    let (loop_2, io_2, a2, do_st, vari, update_io2) = run(
-                        tokens.get().to_vec(),
-                        (*io.get()).clone(),
-                        *current_line.get(),
-                        (*variables.get()).clone(),
-                        (*do_statements.get()).clone(),
-
-                    );
-                    update_io = update_io2;
-                    current_line.set(a2);
-                    do_loop.set(loop_2);
-                    display.set(io_2.display);
-                    variables.set(vari);
-                    do_statements.set(do_st);
-                    if update_io2 {
-                        sense_lights.set(io_2.sense_lights.clone());
-                        stop_light.set(io_2.stop_light);
-                        sense_switches.set(io_2.sense_switches.iter().enumerate().map(|(i, x)| {
-                            (i, *x)
-                        }).collect::<Vec<(usize, bool)>>());
-                    }
-
-                }
+                let mut machine = machine.modify();
+                // the operator can flip the switches while the program runs
+                machine.io.sense_switches = sense_switches.get().iter().map(|(_, x)| *x).collect();
+                // one slice per click, the page clicks again once the browser has had its turn
+                let result = machine.run_for(SLICE);
+                finish(&machine, result);
             }
             }) {
                 "Run"
             }
+
+            (if let Some(error) = runtime_error.get().as_ref() {
+                let mut names = error.state.variables.keys().cloned().collect::<Vec<String>>();
+                names.sort();
+                let variables = names.iter().map(|name| {
+                    let values = error.state.variables[name].iter().map(|x| x.to_string()).collect::<Vec<String>>();
+                    format!("{} = {}", name, values.join(", "))
+                }).collect::<Vec<String>>().join("\n");
+                let heading = format!("card {}: {}", error.card() + 1, error.kind);
+                let depth = format!("statement {}, DO depth {}", error.state.line_num + 1, error.state.do_statements.len());
+                view! { cx,
+                    div(class="error-panel") {
+                        p(class="error-heading") { (heading.clone()) }
+                        p { (depth.clone()) }
+                        pre { (variables.clone()) }
+                        button(on:click=move |_| runtime_error.set(None)) { "Dismiss" }
+                    }
+                }
+            } else {
+                view! { cx, }
+            })
         }
     });
 }
@@ -0,0 +1,257 @@
+//! Absolute binary object decks, punched as SIMH's column binary card images.
+//!
+//! The first card loads itself. LOAD CARDS puts its first three words in 0
+//! to 2 and starts at 0; they copy the other 21 into 4 to 24, a loader for
+//! the cards after it. Each of those has a control word in 9 left giving how
+//! many words it holds and where they go, a checksum of the control word and
+//! those words in 9 right, and the words from 8 left to 12 right. A card with no words is
+//! the transfer card, and its address is where the program starts.
+//!
+//! In a column binary image each column is two characters of 6 bits, rows 12
+//! to 3 and then rows 4 to 9, and the first character of a card has its top
+//! bit set. SIMH reads them with `attach -f cbn cdr deck.cbn` and `boot cdr`.
+
+use crate::assembler::{assemble, Assembly};
+use crate::cpu::{Cpu, CARD_WORDS};
+use crate::sap::{Line, Listing};
+use crate::word::WORD;
+
+/// Words a loaded card holds after its control word and checksum.
+pub const WORDS_PER_CARD: usize = CARD_WORDS - 2;
+
+const COLUMNS: usize = 80;
+/// Binary words are punched in columns 1 to 72, 36 to a half row.
+const HALF_ROW: usize = 36;
+/// Rows 12, 11 and 0 to 9, from the top bit of a column down.
+const ROWS: usize = 12;
+/// The first character of a card in a column binary image.
+const CARD_START: u8 = 0x80;
+/// Words the loader uses, which the cards after it must leave alone.
+const LOADER_WORDS: usize = 27;
+
+/// The self loading first card. RDS 209 selects the card reader, 321 in
+/// octal. The loader keeps the control word and checksum of the card it is
+/// reading in 25 and 26, and copies the rest of the card from the address
+/// on, with index register 1 counting down.
+const LOADER: &str = "
+       ORG 0
+       LXA 2,1
+       CPY 25,1
+       HTR 21
+       BSS 1
+       TIX 1,1,1
+0NEXT  RDS 209
+       CPY 0CTRL
+       HTR 0NEXT
+       TRA 0NEXT
+       CPY 0CHECK
+       HTR 0NEXT
+       TRA 0NEXT
+       LXD 0CTRL,1
+       CLA 0CTRL
+       STA 0GO
+0GO    TXL 0,1,0
+       ARS 18
+       ADD 0CTRL
+       STA 0COPY
+0COPY  CPY 0,1
+       HTR 0NEXT
+       TRA 0NEXT
+       TXI 0COPY,1,32767
+       BSS 2
+0CTRL  BSS 1
+0CHECK BSS 1
+       END 0
+";
+
+/// The words of the first card, which LOAD CARDS and the card it reads put
+/// in 0 to 2 and 4 to 24.
+fn loader_card() -> Vec<u64> {
+    let lines = LOADER
+        .lines()
+        .filter(|x| !x.is_empty())
+        .map(Line::parse)
+        .collect();
+    let assembly = assemble(&Listing {
+        lines,
+        ..Listing::default()
+    })
+    .expect("the loader assembles");
+    (0..=24)
+        .filter(|x| *x != 3)
+        .map(|x| assembly.words.get(&x).copied().unwrap_or(0))
+        .collect()
+}
+
+/// The sum the loader checks a card with: the control word and the loaded
+/// words added as 36 bit numbers, with the carry out of the top coming
+/// round, as CAD adds them.
+fn checksum(words: &[u64]) -> u64 {
+    words.iter().fold(0, |sum, word| {
+        let sum = sum + word;
+        if sum > WORD {
+            (sum & WORD) + 1
+        } else {
+            sum
+        }
+    })
+}
+
+fn card(control: u64, words: &[u64]) -> Vec<u64> {
+    let mut card = vec![0; CARD_WORDS];
+    card[0] = control;
+    card[1] = checksum(&[&[control], words].concat());
+    card[2..2 + words.len()].copy_from_slice(words);
+    card
+}
+
+/// The deck for an assembled program: the loader card, cards of 22 words in
+/// order of location, and the transfer card.
+pub fn punch(assembly: &Assembly) -> Vec<Vec<u64>> {
+    let mut cards = vec![loader_card()];
+    let mut words = assembly.words.iter().peekable();
+    while let Some((&start, &first)) = words.next() {
+        let mut run = vec![first];
+        // words set aside with BSS are not punched, so a card stops at a gap
+        while run.len() < WORDS_PER_CARD {
+            match words.next_if(|(location, _)| **location == start + run.len()) {
+                Some((_, word)) => run.push(*word),
+                None => break,
+            }
+        }
+        // the loader copies whole cards, so a short run is punched with the
+        // words before it rather than blanks after it, which could run off
+        // the top of core into the loader
+        let start = start
+            .saturating_sub(WORDS_PER_CARD - run.len())
+            .max(LOADER_WORDS)
+            .min(start);
+        let run = (start..start + WORDS_PER_CARD)
+            .map(|x| assembly.words.get(&x).copied().unwrap_or(0))
+            .collect::<Vec<_>>();
+        let control = (run.len() as u64) << 18 | start as u64;
+        cards.push(card(control, &run));
+    }
+    cards.push(card(assembly.entry as u64, &[]));
+    cards
+}
+
+/// Cards as a column binary image.
+pub fn column_binary(cards: &[Vec<u64>]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for card in cards {
+        for column in 0..COLUMNS {
+            let mut punches = 0u16;
+            for row in 0..ROWS {
+                // words run from 9 left up to 12 right
+                let index = 2 * (ROWS - 1 - row) + column / HALF_ROW;
+                let bit = HALF_ROW - 1 - column % HALF_ROW;
+                let punched = column < 2 * HALF_ROW
+                    && card.get(index).is_some_and(|word| word >> bit & 1 != 0);
+                punches = punches << 1 | punched as u16;
+            }
+            bytes.push((punches >> 6) as u8);
+            bytes.push((punches & 0o77) as u8);
+        }
+        let start = bytes.len() - 2 * COLUMNS;
+        bytes[start] |= CARD_START;
+    }
+    bytes
+}
+
+/// Reads a column binary image back into cards of row binary words.
+pub fn read_column_binary(bytes: &[u8]) -> Result<Vec<Vec<u64>>, String> {
+    let mut cards: Vec<Vec<u64>> = vec![];
+    let mut column = 0;
+    for pair in bytes.chunks(2) {
+        let [high, low] = pair else {
+            return Err("the image ends in the middle of a column".to_string());
+        };
+        if high & CARD_START != 0 {
+            cards.push(vec![0; CARD_WORDS]);
+            column = 0;
+        }
+        let Some(card) = cards.last_mut() else {
+            return Err("the image does not start with a card".to_string());
+        };
+        if column >= COLUMNS {
+            return Err(format!(
+                "card {} has more than {} columns",
+                cards.len(),
+                COLUMNS
+            ));
+        }
+        let punches = ((high & 0o77) as u16) << 6 | (low & 0o77) as u16;
+        if column < 2 * HALF_ROW {
+            for row in 0..ROWS {
+                if punches >> (ROWS - 1 - row) & 1 != 0 {
+                    let index = 2 * (ROWS - 1 - row) + column / HALF_ROW;
+                    card[index] |= 1 << (HALF_ROW - 1 - column % HALF_ROW);
+                }
+            }
+        }
+        column += 1;
+    }
+    Ok(cards)
+}
+
+/// Loads a deck into core the way its loader card would, checking each
+/// card's sum, and returns where the program starts.
+pub fn load(cpu: &mut Cpu, cards: &[Vec<u64>]) -> Result<usize, String> {
+    for (number, card) in cards.iter().enumerate().skip(1) {
+        let control = card[0];
+        let count = (control >> 18 & 0o77777) as usize;
+        let address = (control & 0o77777) as usize;
+        if count == 0 {
+            return Ok(address);
+        }
+        if count > WORDS_PER_CARD {
+            return Err(format!("card {} says it holds {} words", number + 1, count));
+        }
+        let words = &card[2..2 + count];
+        if checksum(&[&[control], words].concat()) != card[1] {
+            return Err(format!(
+                "card {} does not add up to its checksum",
+                number + 1
+            ));
+        }
+        // the loader copies to the end of the card, so the blank words after
+        // a short one are stored too
+        cpu.load(address, &card[2..]);
+    }
+    Err("the deck has no transfer card".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::import_cards;
+    use crate::compile::compile;
+    use crate::compute::process;
+    use crate::cpu::CoreSize;
+    use crate::optimize::Passes;
+    use crate::storage::allocate;
+
+    #[test]
+    fn a_punched_deck_loads_back_word_for_word() {
+        let deck = "      DIMENSION X(30)
+      DO 10 I = 1, 30
+   10 X(I) = FLOATF(I)**2
+      STOP
+";
+        let (text, line_data, _) = import_cards(deck);
+        let (statements, _) = process(text, line_data);
+        let program = compile(&statements).unwrap();
+        let (_, assembly, _) = allocate(&program, CoreSize::K4, Passes::default()).unwrap();
+
+        let cards = punch(&assembly);
+        let read = read_column_binary(&column_binary(&cards)).unwrap();
+        assert_eq!(read, cards);
+
+        let mut cpu = Cpu::new(CoreSize::K4);
+        assert_eq!(load(&mut cpu, &read), Ok(assembly.entry));
+        for (&location, &word) in &assembly.words {
+            assert_eq!(cpu.memory[location], word, "word {:05o}", location);
+        }
+    }
+}
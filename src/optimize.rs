@@ -0,0 +1,521 @@
+//! Optimizations of the kind the FORTRAN I compiler made, each of which can
+//! be turned on or off.
+//!
+//! Constant folding rewrites the program itself, working out expressions of
+//! constants with the interpreter's own arithmetic so a folded program gives
+//! the same answers. The others decide how the program is lowered to SAP:
+//! which expressions a statement works out once and keeps, which subscripts a
+//! DO loop works out before it starts, and which loops keep their variable in
+//! index register B, addressing arrays through it as the compiler did.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::compile::{Op, Program};
+use crate::compute::{Evaluator, Functions, Value, LIBRARY_FUNCTIONS};
+use crate::parse::Expr;
+use crate::sap::{offset, substitute};
+use crate::timing;
+
+/// The passes to make, all off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Passes {
+    /// expressions of constants worked out when compiling
+    pub fold: bool,
+    /// an expression repeated in a statement worked out once
+    pub common: bool,
+    /// subscripts a DO loop does not change worked out before it starts
+    pub invariant: bool,
+    /// the variable of an innermost DO loop kept in an index register
+    pub index: bool,
+}
+
+impl Passes {
+    pub fn all() -> Passes {
+        Passes {
+            fold: true,
+            common: true,
+            invariant: true,
+            index: true,
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        *self != Passes::default()
+    }
+
+    /// Pass names separated by commas, or `all` or `none`.
+    pub fn parse(text: &str) -> Option<Passes> {
+        let mut passes = Passes::default();
+        for name in text.split(',').map(|x| x.trim().to_lowercase()) {
+            match name.as_str() {
+                "all" => passes = Passes::all(),
+                "none" => {}
+                "fold" => passes.fold = true,
+                "cse" => passes.common = true,
+                "hoist" => passes.invariant = true,
+                "index" => passes.index = true,
+                _ => return None,
+            }
+        }
+        Some(passes)
+    }
+}
+
+/// The expressions a statement other than an assignment works out.
+fn expressions(op: &Op) -> Vec<&Expr> {
+    match op {
+        Op::If { value, .. } => vec![value],
+        Op::Do {
+            start, limit, step, ..
+        } => vec![start, limit, step],
+        Op::Print(items) => items.iter().collect(),
+        _ => vec![],
+    }
+}
+
+/// A statement with `f` applied to each of its expressions.
+fn map_expressions(op: &Op, f: impl Fn(&Expr) -> Expr) -> Op {
+    match op {
+        Op::Assign {
+            variable,
+            subscripts,
+            value,
+        } => Op::Assign {
+            variable: variable.clone(),
+            subscripts: subscripts.iter().map(&f).collect(),
+            value: f(value),
+        },
+        Op::If { value, targets } => Op::If {
+            value: f(value),
+            targets: *targets,
+        },
+        Op::Do {
+            end,
+            variable,
+            start,
+            limit,
+            step,
+        } => Op::Do {
+            end: *end,
+            variable: variable.clone(),
+            start: f(start),
+            limit: f(limit),
+            step: f(step),
+        },
+        Op::Print(items) => Op::Print(items.iter().map(f).collect()),
+        _ => op.clone(),
+    }
+}
+
+/// The program with every expression of constants worked out, including
+/// library functions of constants. A division by zero is left for the run
+/// to report.
+pub fn fold(program: &Program) -> Program {
+    let mut folded = program.clone();
+    for instruction in &mut folded.instructions {
+        instruction.op = map_expressions(&instruction.op, |x| fold_expr(x, &program.functions));
+        instruction.cycles = timing::cycles(&instruction.op);
+    }
+    for (_, body) in folded.functions.values_mut() {
+        *body = fold_expr(body, &program.functions);
+    }
+    folded
+}
+
+fn fold_expr(expr: &Expr, functions: &Functions) -> Expr {
+    let fold = |x: &Expr| fold_expr(x, functions);
+    let folded = match expr {
+        Expr::Element(name, subscripts) => {
+            Expr::Element(name.clone(), subscripts.iter().map(fold).collect())
+        }
+        Expr::Call(name, args) => Expr::Call(name.clone(), args.iter().map(fold).collect()),
+        Expr::Negate(a) => Expr::Negate(Box::new(fold(a))),
+        Expr::Binary(operator, a, b) => {
+            Expr::Binary(*operator, Box::new(fold(a)), Box::new(fold(b)))
+        }
+        Expr::Int(_) | Expr::Float(_) | Expr::Variable(_) => return expr.clone(),
+    };
+    let literal = |x: &Expr| matches!(x, Expr::Int(_) | Expr::Float(_));
+    let constant = match &folded {
+        Expr::Negate(a) => literal(a),
+        Expr::Binary(_, a, b) => literal(a) && literal(b),
+        // a statement function can use variables as well as its arguments
+        Expr::Call(name, args) => {
+            !functions.contains_key(name)
+                && LIBRARY_FUNCTIONS.contains(&name.as_str())
+                && args.iter().all(literal)
+        }
+        _ => false,
+    };
+    if !constant {
+        return folded;
+    }
+    let (variables, arrays) = (HashMap::new(), HashMap::new());
+    let evaluator = Evaluator {
+        variables: &variables,
+        arrays: &arrays,
+        functions,
+        arguments: HashMap::new(),
+    };
+    match evaluator.evaluate(&folded) {
+        Ok(Value::Fixed(a)) if folded.is_fixed() => Expr::Int(a),
+        Ok(Value::Floating(a)) if !folded.is_fixed() && a.is_finite() => Expr::Float(a),
+        _ => folded,
+    }
+}
+
+/// The expressions working out `expr` works out next: the offset of an
+/// element, the body of a statement function with its arguments in place,
+/// or the operands.
+fn parts(program: &Program, expr: &Expr) -> Vec<Expr> {
+    match expr {
+        Expr::Element(name, subscripts) => match offset(program, name, subscripts) {
+            Expr::Int(_) => vec![],
+            a => vec![a],
+        },
+        Expr::Call(name, args) => match program.functions.get(name) {
+            Some((params, body)) => vec![substitute(body, params, args)],
+            None => args.clone(),
+        },
+        Expr::Negate(a) => vec![a.as_ref().clone()],
+        Expr::Binary(_, a, b) => vec![a.as_ref().clone(), b.as_ref().clone()],
+        Expr::Int(_) | Expr::Float(_) | Expr::Variable(_) => vec![],
+    }
+}
+
+fn size(program: &Program, expr: &Expr) -> usize {
+    1 + parts(program, expr)
+        .iter()
+        .map(|x| size(program, x))
+        .sum::<usize>()
+}
+
+/// The expressions a statement works out, with the offset its result is
+/// stored at.
+fn roots(program: &Program, op: &Op) -> Vec<Expr> {
+    match op {
+        Op::Assign {
+            variable,
+            subscripts,
+            value,
+        } if !subscripts.is_empty() => vec![offset(program, variable, subscripts), value.clone()],
+        Op::Assign { value, .. } => vec![value.clone()],
+        _ => expressions(op).into_iter().cloned().collect(),
+    }
+}
+
+/// How many times each expression is worked out, with those in `kept`
+/// worked out only the first time.
+fn count(program: &Program, expr: &Expr, kept: &[Expr], counts: &mut Vec<(Expr, usize)>) {
+    let seen = match counts.iter_mut().find(|x| x.0 == *expr) {
+        Some(entry) => {
+            entry.1 += 1;
+            true
+        }
+        None => {
+            counts.push((expr.clone(), 1));
+            false
+        }
+    };
+    if seen && kept.contains(expr) {
+        return;
+    }
+    for part in parts(program, expr) {
+        count(program, &part, kept, counts);
+    }
+}
+
+/// The expressions a statement would work out more than once, smallest
+/// first so that each is worked out from the ones before it. The largest
+/// repeated expression is kept first, so its parts are only counted once.
+pub fn common_subexpressions(program: &Program, op: &Op) -> Vec<Expr> {
+    let roots = roots(program, op);
+    let mut kept: Vec<Expr> = vec![];
+    loop {
+        let mut counts = vec![];
+        for root in &roots {
+            count(program, root, &kept, &mut counts);
+        }
+        let repeated = counts
+            .into_iter()
+            .filter(|(expr, n)| *n > 1 && !parts(program, expr).is_empty() && !kept.contains(expr))
+            .map(|x| x.0)
+            .max_by_key(|x| size(program, x));
+        match repeated {
+            Some(expr) => kept.push(expr),
+            None => break,
+        }
+    }
+    kept.sort_by_key(|x| size(program, x));
+    kept
+}
+
+/// The last statement of a DO loop's range.
+fn range_end(program: &Program, index: usize) -> Option<usize> {
+    match &program.instructions.get(index)?.op {
+        Op::Do { end, .. } => Some(*end),
+        _ => None,
+    }
+}
+
+/// The variables a DO loop can change: its own, and those set in its range.
+fn assigned(program: &Program, index: usize, end: usize) -> BTreeSet<String> {
+    program.instructions[index..=end.min(program.instructions.len() - 1)]
+        .iter()
+        .filter_map(|x| match &x.op {
+            Op::Assign {
+                variable,
+                subscripts,
+                ..
+            } if subscripts.is_empty() => Some(variable.clone()),
+            Op::Do { variable, .. } => Some(variable.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether an expression only uses constants and variables not in `changed`.
+fn invariant(expr: &Expr, changed: &BTreeSet<String>) -> bool {
+    match expr {
+        Expr::Int(_) | Expr::Float(_) => true,
+        Expr::Variable(name) => !changed.contains(name),
+        Expr::Negate(a) => invariant(a, changed),
+        Expr::Binary(_, a, b) => invariant(a, changed) && invariant(b, changed),
+        // an element can be stored into and a function can read one
+        Expr::Element(..) | Expr::Call(..) => false,
+    }
+}
+
+/// The largest parts of the subscripts in a DO loop's range that the loop
+/// does not change, which can be worked out once before it starts.
+pub fn invariant_subscripts(program: &Program, index: usize) -> Vec<Expr> {
+    let Some(end) = range_end(program, index) else {
+        return vec![];
+    };
+    let changed = assigned(program, index, end);
+    let mut found = vec![];
+    // the offsets of elements, found among everything the range works out
+    fn search(program: &Program, expr: &Expr, offsets: &mut Vec<Expr>) {
+        if let Expr::Element(..) = expr {
+            offsets.extend(parts(program, expr));
+        }
+        for part in parts(program, expr) {
+            search(program, &part, offsets);
+        }
+    }
+    fn hoist(expr: &Expr, changed: &BTreeSet<String>, found: &mut Vec<Expr>) {
+        match expr {
+            Expr::Negate(_) | Expr::Binary(..) if invariant(expr, changed) => {
+                found.retain(|x| x != expr);
+                found.push(expr.clone());
+            }
+            Expr::Negate(a) => hoist(a, changed, found),
+            Expr::Binary(_, a, b) => {
+                hoist(a, changed, found);
+                hoist(b, changed, found);
+            }
+            _ => {}
+        }
+    }
+    for instruction in program.instructions.iter().take(end + 1).skip(index + 1) {
+        let mut offsets = vec![];
+        let roots = roots(program, &instruction.op);
+        if let Op::Assign { subscripts, .. } = &instruction.op {
+            if !subscripts.is_empty() {
+                offsets.push(roots[0].clone());
+            }
+        }
+        for root in &roots {
+            search(program, root, &mut offsets);
+        }
+        for offset in &offsets {
+            hoist(offset, &changed, &mut found);
+        }
+    }
+    found
+}
+
+/// The DO loops whose variable can be kept in index register B instead of
+/// core: innermost loops with a constant limit and a positive constant
+/// increment, whose range does not set the variable.
+pub fn index_loops(program: &Program) -> BTreeSet<usize> {
+    let mut loops = BTreeSet::new();
+    for (index, instruction) in program.instructions.iter().enumerate() {
+        let Op::Do {
+            end,
+            variable,
+            limit: Expr::Int(_),
+            step: Expr::Int(step),
+            ..
+        } = &instruction.op
+        else {
+            continue;
+        };
+        let range = program.instructions.iter().take(*end + 1).skip(index + 1);
+        let innermost = !range.clone().any(|x| matches!(x.op, Op::Do { .. }));
+        let changed = range.clone().any(|x| {
+            matches!(&x.op, Op::Assign { variable: v, subscripts, .. } if v == variable && subscripts.is_empty())
+        });
+        if *step > 0 && innermost && !changed {
+            loops.insert(index);
+        }
+    }
+    loops
+}
+
+/// The statements a statement can go to next other than the one after it.
+pub fn transfers(op: &Op) -> Vec<usize> {
+    match op {
+        Op::GoTo(a) => vec![*a],
+        Op::ComputedGoTo { targets, .. } => targets.clone(),
+        Op::If { targets, .. } => targets.to_vec(),
+        Op::IfSenseLight { targets, .. } | Op::IfSenseSwitch { targets, .. } => targets.to_vec(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::import_cards;
+    use crate::compile::compile;
+    use crate::compute::process;
+    use crate::cpu::{CoreSize, Cpu, CpuStep};
+    use crate::machine::Machine;
+    use crate::storage::allocate;
+
+    /// The variables and array elements a program leaves in core when it is
+    /// run on the 704 with `passes` made, by name.
+    fn run(program: &Program, passes: Passes) -> Vec<(String, u64)> {
+        let (_, assembly, map) = allocate(program, CoreSize::K4, passes).unwrap();
+        let mut cpu = Cpu::new(CoreSize::K4);
+        for (&location, &word) in &assembly.words {
+            cpu.memory[location] = word;
+        }
+        cpu.ic = assembly.entry;
+        assert_eq!(cpu.run(100_000), Ok(CpuStep::Halted));
+        let mut stored = vec![];
+        for (name, location) in &map.variables {
+            stored.push((name.clone(), cpu.memory[*location]));
+        }
+        for (name, first, size) in &map.arrays {
+            for i in 0..*size {
+                stored.push((format!("{}({})", name, i + 1), cpu.memory[first - i]));
+            }
+        }
+        stored
+    }
+
+    #[test]
+    fn an_optimized_program_stores_the_same_values() {
+        let deck = "      DIMENSION A(10), B(10, 10)
+      N = 3
+      DO 10 I = 1, 10
+      A(I) = FLOATF(I) * 2.0 + 1.0
+      DO 10 J = 1, 10
+   10 B(I, J) = A(I) * FLOATF(J) + A(I) * FLOATF(J)
+      K = 2 + 3 * 4
+      S = 0.0
+      DO 20 I = 1, 10
+   20 S = S + B(I, N) * B(I, N)
+      M = K * N - K / N + N**2
+      STOP
+";
+        let (text, line_data, _) = import_cards(deck);
+        let (statements, _) = process(text, line_data);
+        let program = compile(&statements).unwrap();
+        let plain = run(&program, Passes::default());
+        assert!(plain.iter().any(|(name, word)| name == "M" && *word != 0));
+        assert_eq!(run(&program, Passes::all()), plain);
+    }
+
+    /// The same values as `run` leaves, from the interpreter.
+    fn interpret(program: &Program) -> Vec<(String, u64)> {
+        let (_, _, map) = allocate(program, CoreSize::K4, Passes::default()).unwrap();
+        let mut machine = Machine::new(program.clone());
+        machine.run_until_halt().unwrap();
+        let word = |name: &str, i: usize| {
+            let value = machine.variables.get(name).and_then(|x| x.get(i));
+            value.map_or(0, |x| x.word())
+        };
+        let mut stored = vec![];
+        for (name, _) in &map.variables {
+            stored.push((name.clone(), word(name, 0)));
+        }
+        for (name, _, size) in &map.arrays {
+            for i in 0..*size {
+                stored.push((format!("{}({})", name, i + 1), word(name, i)));
+            }
+        }
+        stored
+    }
+
+    /// A program's values on the 704 with each pass, and with none, are the
+    /// interpreter's. Loop variables are left out, as a DO loop on the 704
+    /// leaves its variable one step past the limit.
+    fn compare_with_interpreter(deck: &str, loop_variables: &[&str]) -> Program {
+        let (text, line_data, _) = import_cards(deck);
+        let (statements, _) = process(text, line_data);
+        let program = compile(&statements).unwrap();
+        let kept = |values: Vec<(String, u64)>| {
+            values
+                .into_iter()
+                .filter(|x| !loop_variables.contains(&x.0.as_str()))
+                .collect::<Vec<_>>()
+        };
+        let interpreted = kept(interpret(&program));
+        assert!(interpreted.iter().any(|x| x.1 != 0));
+        let only = |f: fn(&mut Passes)| {
+            let mut passes = Passes::default();
+            f(&mut passes);
+            passes
+        };
+        for passes in [
+            Passes::default(),
+            only(|x| x.common = true),
+            only(|x| x.index = true),
+            only(|x| x.invariant = true),
+            Passes::all(),
+        ] {
+            assert_eq!(kept(run(&program, passes)), interpreted, "{:?}", passes);
+        }
+        program
+    }
+
+    #[test]
+    fn loops_in_an_index_register_store_what_the_interpreter_does() {
+        let program = compare_with_interpreter(
+            "      DIMENSION A(10), B(10, 10)
+      DO 10 I = 1, 10
+      A(I) = FLOATF(I) * 0.5
+      DO 10 J = 1, 10
+   10 B(I, J) = A(I) + FLOATF(J)
+      DO 20 K = 2, 10, 2
+      N = N + K * K
+   20 A(K) = B(K, 3) - A(K)
+      STOP
+",
+            &["I", "J", "K"],
+        );
+        assert_eq!(index_loops(&program), BTreeSet::from([3, 5]));
+    }
+
+    #[test]
+    fn common_subexpressions_store_what_the_interpreter_does() {
+        let program = compare_with_interpreter(
+            "      DIMENSION A(4)
+      N = 3
+      A(N) = 1.5
+      X = (A(N) + 2.0) * (A(N) + 2.0) - (A(N) + 2.0)
+      M = (N + 1) * (N + 1) + (N + 1) / 2
+      A(N + 1) = X / (A(N) + 2.0) + FLOATF(M)
+      STOP
+",
+            &[],
+        );
+        for statement in [3, 4] {
+            let op = &program.instructions[statement].op;
+            assert_eq!(common_subexpressions(&program, op).len(), 1);
+        }
+    }
+}
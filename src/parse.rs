@@ -0,0 +1,551 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::compute::{is_fixed_name, Token};
+use crate::diagnostics::{Diagnostic, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+}
+
+impl Operator {
+    /// Exponentiation binds tightest, then multiplication and division.
+    fn precedence(&self) -> u8 {
+        match self {
+            Operator::Add | Operator::Subtract => 1,
+            Operator::Multiply | Operator::Divide => 2,
+            Operator::Power => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Expr {
+    Int(i32),
+    Float(f32),
+    Variable(String),
+    /// an element of a dimensioned array
+    Element(String, Vec<Expr>),
+    /// a statement function or a library function
+    Call(String, Vec<Expr>),
+    Negate(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Whether the expression is fixed point: names starting I to N, and
+    /// functions starting with X.
+    pub fn is_fixed(&self) -> bool {
+        match self {
+            Expr::Int(_) => true,
+            Expr::Float(_) => false,
+            Expr::Variable(name) | Expr::Element(name, _) => is_fixed_name(name),
+            Expr::Call(name, _) => name.starts_with('X'),
+            Expr::Negate(a) => a.is_fixed(),
+            // modes are not mixed, except for a fixed point exponent
+            Expr::Binary(_, left, _) => left.is_fixed(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementKind {
+    Assign {
+        variable: String,
+        subscripts: Vec<Expr>,
+        value: Expr,
+    },
+    /// a statement function, `NAMEF(A, B) = expression`
+    Function {
+        name: String,
+        args: Vec<String>,
+        value: Expr,
+    },
+    GoTo(i32),
+    ComputedGoTo {
+        targets: Vec<i32>,
+        index: String,
+    },
+    If {
+        value: Expr,
+        targets: [i32; 3],
+    },
+    IfSenseLight {
+        light: i32,
+        targets: [i32; 2],
+    },
+    IfSenseSwitch {
+        switch: i32,
+        targets: [i32; 2],
+    },
+    SenseLight(i32),
+    Do {
+        end: i32,
+        variable: String,
+        start: Expr,
+        limit: Expr,
+        step: Expr,
+    },
+    Continue,
+    Pause(Option<i32>),
+    Stop(Option<i32>),
+    Print {
+        format: i32,
+        items: Vec<Expr>,
+    },
+    Format(Vec<Token>),
+    Dimension(Vec<(String, Vec<i32>)>),
+}
+
+impl StatementKind {
+    /// FORMAT, DIMENSION and statement functions describe the program rather
+    /// than being run.
+    pub fn is_executable(&self) -> bool {
+        !matches!(
+            self,
+            StatementKind::Format(_) | StatementKind::Dimension(_) | StatementKind::Function { .. }
+        )
+    }
+
+    /// The statement numbers this statement can transfer control to.
+    pub fn branch_targets(&self) -> Vec<i32> {
+        match self {
+            StatementKind::GoTo(a) => vec![*a],
+            StatementKind::ComputedGoTo { targets, .. } => targets.clone(),
+            StatementKind::If { targets, .. } => targets.to_vec(),
+            StatementKind::IfSenseLight { targets, .. }
+            | StatementKind::IfSenseSwitch { targets, .. } => targets.to_vec(),
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    /// the statement number, 0 when there is none
+    pub label: i32,
+    pub span: Span,
+    pub kind: StatementKind,
+}
+
+/// Builds statements from the tokens of `tokenize`.
+///
+/// A statement that cannot be read is reported and kept as a `CONTINUE`, so
+/// its statement number can still be branched to.
+///
+/// # Returns (statements, diagnostics)
+pub fn parse(tokens: &[Token], spans: &[Span]) -> (Vec<Statement>, Vec<Diagnostic>) {
+    let mut lines = vec![];
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if *token == Token::Newline {
+            lines.push(start..i + 1);
+            start = i + 1;
+        }
+    }
+
+    // arrays are told apart from function calls by their DIMENSION statements
+    let mut arrays = HashSet::new();
+    for line in &lines {
+        let mut parser = Parser::new(&tokens[line.clone()], &spans[line.clone()], &arrays);
+        if let Ok(Statement {
+            kind: StatementKind::Dimension(a),
+            ..
+        }) = parser.statement()
+        {
+            arrays.extend(a.into_iter().map(|(name, _)| name));
+        }
+    }
+
+    let mut statements = vec![];
+    let mut diagnostics = vec![];
+    for line in lines {
+        let mut parser = Parser::new(&tokens[line.clone()], &spans[line.clone()], &arrays);
+        match parser.statement() {
+            Ok(statement) => statements.push(statement),
+            Err(diagnostic) => {
+                diagnostics.push(diagnostic);
+                statements.push(Statement {
+                    label: parser.label,
+                    span: parser.statement_span(),
+                    kind: StatementKind::Continue,
+                });
+            }
+        }
+    }
+    (statements, diagnostics)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    spans: &'a [Span],
+    arrays: &'a HashSet<String>,
+    position: usize,
+    label: i32,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token], spans: &'a [Span], arrays: &'a HashSet<String>) -> Parser<'a> {
+        let mut parser = Parser {
+            tokens,
+            spans,
+            arrays,
+            position: 0,
+            label: 0,
+        };
+        if let Some(Token::Label(a)) = tokens.first() {
+            parser.label = *a;
+            parser.position = 1;
+        }
+        parser
+    }
+
+    fn peek(&self) -> &Token {
+        self.tokens.get(self.position).unwrap_or(&Token::Newline)
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        self.position += 1;
+        token
+    }
+
+    fn span(&self) -> Span {
+        self.spans
+            .get(self.position)
+            .or(self.spans.last())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The statement field, from its first token to its last.
+    fn statement_span(&self) -> Span {
+        let first = if self.label != 0 { 1 } else { 0 };
+        let last = self.spans.len().saturating_sub(2).max(first);
+        match (self.spans.get(first), self.spans.get(last)) {
+            (Some(a), Some(b)) => a.to(b),
+            _ => self.spans.first().cloned().unwrap_or_default(),
+        }
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, Diagnostic> {
+        Err(Diagnostic::error(self.span(), message))
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, Diagnostic> {
+        self.error(format!("expected {}, found {}", expected, self.peek().describe()))
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), Diagnostic> {
+        if *self.peek() == token {
+            self.position += 1;
+            Ok(())
+        } else {
+            self.unexpected(&token.describe())
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, Diagnostic> {
+        match self.peek().clone() {
+            Token::Identifier(a) => {
+                self.position += 1;
+                Ok(a)
+            }
+            _ => self.unexpected("a name"),
+        }
+    }
+
+    fn int(&mut self) -> Result<i32, Diagnostic> {
+        match *self.peek() {
+            Token::Int(a) => {
+                self.position += 1;
+                Ok(a)
+            }
+            _ => self.unexpected("a fixed point constant"),
+        }
+    }
+
+    fn end(&mut self) -> Result<(), Diagnostic> {
+        self.expect(Token::Newline)
+    }
+
+    fn statement(&mut self) -> Result<Statement, Diagnostic> {
+        let span = self.statement_span();
+        let kind = self.kind()?;
+        Ok(Statement {
+            label: self.label,
+            span,
+            kind,
+        })
+    }
+
+    fn kind(&mut self) -> Result<StatementKind, Diagnostic> {
+        let kind = match self.next() {
+            Token::GoTo => self.go_to()?,
+            Token::If => self.if_statement()?,
+            Token::SenseLight => StatementKind::SenseLight(self.int()?),
+            Token::Do => self.do_statement()?,
+            Token::Continue => StatementKind::Continue,
+            Token::Pause => StatementKind::Pause(self.optional_int()),
+            Token::Stop => StatementKind::Stop(self.optional_int()),
+            Token::Print => self.print()?,
+            Token::Format => {
+                let mut specification = vec![];
+                while *self.peek() != Token::Newline {
+                    specification.push(self.next());
+                }
+                StatementKind::Format(specification)
+            }
+            Token::Dimension => self.dimension()?,
+            Token::Identifier(a) => self.assignment(a)?,
+            Token::Newline => StatementKind::Continue,
+            _ => {
+                self.position -= 1;
+                return self.error("cannot tell what kind of statement this is".to_string());
+            }
+        };
+        self.end()?;
+        Ok(kind)
+    }
+
+    fn optional_int(&mut self) -> Option<i32> {
+        self.int().ok()
+    }
+
+    fn label_list(&mut self, count: usize) -> Result<Vec<i32>, Diagnostic> {
+        let mut labels = vec![self.int()?];
+        while labels.len() < count {
+            self.expect(Token::Comma)?;
+            labels.push(self.int()?);
+        }
+        Ok(labels)
+    }
+
+    fn go_to(&mut self) -> Result<StatementKind, Diagnostic> {
+        if *self.peek() != Token::OpenParen {
+            return Ok(StatementKind::GoTo(self.int()?));
+        }
+        self.position += 1;
+        let mut targets = vec![self.int()?];
+        while *self.peek() == Token::Comma {
+            self.position += 1;
+            targets.push(self.int()?);
+        }
+        self.expect(Token::CloseParen)?;
+        self.expect(Token::Comma)?;
+        let index = self.identifier()?;
+        Ok(StatementKind::ComputedGoTo { targets, index })
+    }
+
+    fn if_statement(&mut self) -> Result<StatementKind, Diagnostic> {
+        self.expect(Token::OpenParen)?;
+        let kind = match self.peek() {
+            Token::SenseLight | Token::SenseSwitch => {
+                let light = *self.peek() == Token::SenseLight;
+                self.position += 1;
+                let number = self.int()?;
+                self.expect(Token::CloseParen)?;
+                let targets = self.label_list(2)?;
+                let targets = [targets[0], targets[1]];
+                if light {
+                    StatementKind::IfSenseLight {
+                        light: number,
+                        targets,
+                    }
+                } else {
+                    StatementKind::IfSenseSwitch {
+                        switch: number,
+                        targets,
+                    }
+                }
+            }
+            _ => {
+                let value = self.expression()?;
+                self.expect(Token::CloseParen)?;
+                let targets = self.label_list(3)?;
+                StatementKind::If {
+                    value,
+                    targets: [targets[0], targets[1], targets[2]],
+                }
+            }
+        };
+        Ok(kind)
+    }
+
+    fn do_statement(&mut self) -> Result<StatementKind, Diagnostic> {
+        let end = self.int()?;
+        let variable = self.identifier()?;
+        self.expect(Token::Equals)?;
+        let start = self.expression()?;
+        self.expect(Token::Comma)?;
+        let limit = self.expression()?;
+        let step = if *self.peek() == Token::Comma {
+            self.position += 1;
+            self.expression()?
+        } else {
+            Expr::Int(1)
+        };
+        Ok(StatementKind::Do {
+            end,
+            variable,
+            start,
+            limit,
+            step,
+        })
+    }
+
+    fn print(&mut self) -> Result<StatementKind, Diagnostic> {
+        let format = self.int()?;
+        let mut items = vec![];
+        while *self.peek() == Token::Comma {
+            self.position += 1;
+            items.push(self.expression()?);
+        }
+        Ok(StatementKind::Print { format, items })
+    }
+
+    fn dimension(&mut self) -> Result<StatementKind, Diagnostic> {
+        let mut arrays = vec![];
+        loop {
+            let name = self.identifier()?;
+            self.expect(Token::OpenParen)?;
+            let mut sizes = vec![self.int()?];
+            while *self.peek() == Token::Comma {
+                self.position += 1;
+                sizes.push(self.int()?);
+            }
+            self.expect(Token::CloseParen)?;
+            arrays.push((name, sizes));
+            if *self.peek() != Token::Comma {
+                break;
+            }
+            self.position += 1;
+        }
+        Ok(StatementKind::Dimension(arrays))
+    }
+
+    /// An arithmetic statement, or a statement function when the name ends in
+    /// `F` and is not an array.
+    fn assignment(&mut self, name: String) -> Result<StatementKind, Diagnostic> {
+        let mut subscripts = vec![];
+        if *self.peek() == Token::OpenParen {
+            self.position += 1;
+            subscripts = self.arguments()?;
+        }
+        self.expect(Token::Equals)?;
+        let value = self.expression()?;
+
+        if !subscripts.is_empty() && !self.arrays.contains(&name) && name.ends_with('F') {
+            let mut args = vec![];
+            for arg in subscripts {
+                match arg {
+                    Expr::Variable(a) => args.push(a),
+                    _ => {
+                        return Err(Diagnostic::error(
+                            self.statement_span(),
+                            format!("the arguments of {} must be names", name),
+                        ))
+                    }
+                }
+            }
+            return Ok(StatementKind::Function { name, args, value });
+        }
+        Ok(StatementKind::Assign {
+            variable: name,
+            subscripts,
+            value,
+        })
+    }
+
+    /// A list of expressions after an open bracket, up to the close bracket.
+    fn arguments(&mut self) -> Result<Vec<Expr>, Diagnostic> {
+        let mut args = vec![self.expression()?];
+        while *self.peek() == Token::Comma {
+            self.position += 1;
+            args.push(self.expression()?);
+        }
+        self.expect(Token::CloseParen)?;
+        Ok(args)
+    }
+
+    fn expression(&mut self) -> Result<Expr, Diagnostic> {
+        self.binary(1)
+    }
+
+    fn operator(&self) -> Option<Operator> {
+        match self.peek() {
+            Token::Add => Some(Operator::Add),
+            Token::Subtract => Some(Operator::Subtract),
+            Token::Multiply => Some(Operator::Multiply),
+            Token::Divide => Some(Operator::Divide),
+            Token::Power => Some(Operator::Power),
+            _ => None,
+        }
+    }
+
+    /// Operators of the same precedence group from the left.
+    fn binary(&mut self, precedence: u8) -> Result<Expr, Diagnostic> {
+        let mut left = self.unary()?;
+        while let Some(operator) = self.operator() {
+            if operator.precedence() < precedence {
+                break;
+            }
+            self.position += 1;
+            let right = self.binary(operator.precedence() + 1)?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Diagnostic> {
+        match self.peek() {
+            Token::Subtract => {
+                self.position += 1;
+                Ok(Expr::Negate(Box::new(self.binary(2)?)))
+            }
+            Token::Add => {
+                self.position += 1;
+                self.binary(2)
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, Diagnostic> {
+        match self.peek().clone() {
+            Token::Int(a) => {
+                self.position += 1;
+                Ok(Expr::Int(a))
+            }
+            Token::Float(a) => {
+                self.position += 1;
+                Ok(Expr::Float(a))
+            }
+            Token::Identifier(name) => {
+                self.position += 1;
+                if *self.peek() != Token::OpenParen {
+                    return Ok(Expr::Variable(name));
+                }
+                self.position += 1;
+                let args = self.arguments()?;
+                if self.arrays.contains(&name) {
+                    Ok(Expr::Element(name, args))
+                } else {
+                    Ok(Expr::Call(name, args))
+                }
+            }
+            Token::OpenParen => {
+                self.position += 1;
+                let expr = self.expression()?;
+                self.expect(Token::CloseParen)?;
+                Ok(expr)
+            }
+            _ => self.unexpected("an expression"),
+        }
+    }
+}
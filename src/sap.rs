@@ -0,0 +1,912 @@
+//! Lowers a compiled program to SAP, the 704's Symbolic Assembly Program.
+//!
+//! The code is in the style of the FORTRAN I compiler: expressions are
+//! worked out in the accumulator, with the MQ for multiplication, division
+//! and second arguments, and index register 1 for subscripts. Fixed point
+//! numbers are kept in the decrement, as the compiler kept them. Arrays are
+//! stored backwards from their symbol so that the 704's indexing, which
+//! subtracts the index register from the address, steps through them.
+//! Printing, conversions and functions call routines from the library tape.
+//!
+//! Generated symbols start with a digit, which no FORTRAN name can, so they
+//! cannot clash with the program's own variables.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use crate::compile::{array_words, Op, Program};
+use crate::compute::{is_fixed_name, Value};
+use crate::optimize::{self, Passes};
+use crate::parse::{Expr, Operator};
+use crate::word;
+
+/// Where generated programs are assembled from, leaving the low words for the loader.
+pub const ORIGIN: usize = 100;
+
+/// The symbol of the first instruction, where the program is started.
+pub const ENTRY: &str = "0ENTRY";
+
+/// One card of SAP source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub location: String,
+    pub operation: String,
+    pub address: String,
+    /// the FORTRAN card the instruction was generated for
+    pub card: Option<usize>,
+}
+
+impl fmt::Display for Line {
+    /// The location in columns 1 to 6, the operation in 8 to 10 and the
+    /// variable field from 12.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = format!("{:<6} {:<3} {}", self.location, self.operation, self.address);
+        write!(f, "{}", text.trim_end())
+    }
+}
+
+impl Line {
+    /// A card of SAP source, in the columns `Display` writes.
+    pub fn parse(text: &str) -> Line {
+        let field = |from: usize, to: usize| {
+            text.get(from..to.min(text.len())).unwrap_or("").trim().to_string()
+        };
+        Line {
+            location: field(0, 6),
+            operation: field(7, 10),
+            address: field(11, text.len()),
+            card: None,
+        }
+    }
+}
+
+/// A whole program in SAP.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+    /// the library routines the program calls
+    pub externals: BTreeSet<String>,
+    /// the first line of the variables, constants and arrays after the code
+    pub data: usize,
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Two listings of a program side by side, grouped by the card each line was
+/// generated for: the lines before the first card, each card's lines, and
+/// the data after the last. Each group is the card and the text of both.
+pub fn compare(before: &Listing, after: &Listing) -> Vec<(Option<usize>, String, String)> {
+    let mut groups: BTreeMap<(u8, usize), (String, String)> = BTreeMap::new();
+    for (i, listing) in [before, after].into_iter().enumerate() {
+        let mut started = false;
+        for line in &listing.lines {
+            let key = match line.card {
+                Some(card) => (1, card),
+                None => (if started { 2 } else { 0 }, 0),
+            };
+            started |= line.card.is_some();
+            let group = groups.entry(key).or_default();
+            let text = if i == 0 { &mut group.0 } else { &mut group.1 };
+            text.push_str(&line.to_string());
+            text.push('\n');
+        }
+    }
+    groups
+        .into_iter()
+        .map(|((stage, card), (a, b))| ((stage == 1).then_some(card), a, b))
+        .collect()
+}
+
+/// A loop whose increment and test follow its last statement.
+struct Loop {
+    variable: String,
+    /// the first statement of the range
+    body: usize,
+    limit: String,
+    step: String,
+    /// the variable is in index register B, and the limit and increment are numbers
+    register: bool,
+}
+
+struct Generator<'a> {
+    program: &'a Program,
+    lines: Vec<Line>,
+    card: Option<usize>,
+    /// a location waiting for the next instruction
+    pending: Option<String>,
+    constants: BTreeMap<u64, String>,
+    variables: BTreeSet<String>,
+    /// temporaries in use, and the most ever in use at once
+    depth: usize,
+    temporaries: usize,
+    /// words set aside for DO limits and increments that are not constants
+    loop_words: Vec<String>,
+    tables: usize,
+    loops: HashMap<usize, Vec<Loop>>,
+    externals: BTreeSet<String>,
+    passes: Passes,
+    /// expressions the statement being generated has worked out and kept,
+    /// and the most any statement keeps
+    common: Vec<(Expr, String)>,
+    common_words: usize,
+    /// subscripts worked out before a loop, with the last statement of its range
+    invariants: Vec<(usize, Expr, String)>,
+    invariant_words: usize,
+    /// the loops that keep their variable in index register B
+    index_loops: BTreeSet<usize>,
+    /// the range and variable of the loop using index register B
+    register: Option<(usize, usize, String)>,
+}
+
+/// The symbol of a statement that is branched to.
+fn statement_symbol(program: &Program, index: usize) -> String {
+    match program.instructions.get(index).map_or(0, |x| x.label) {
+        0 => format!("{}B", index),
+        label => format!("{}S", label),
+    }
+}
+
+pub fn generate(program: &Program) -> Listing {
+    generate_with(program, Passes::default())
+}
+
+/// The program in SAP, with the optimizations in `passes` made.
+pub fn generate_with(program: &Program, passes: Passes) -> Listing {
+    let folded;
+    let program = if passes.fold {
+        folded = optimize::fold(program);
+        &folded
+    } else {
+        program
+    };
+    let mut generator = Generator {
+        program,
+        lines: vec![],
+        card: None,
+        pending: Some(ENTRY.to_string()),
+        constants: BTreeMap::new(),
+        variables: BTreeSet::new(),
+        depth: 0,
+        temporaries: 0,
+        loop_words: vec![],
+        tables: 0,
+        loops: HashMap::new(),
+        externals: BTreeSet::new(),
+        passes,
+        common: vec![],
+        common_words: 0,
+        invariants: vec![],
+        invariant_words: 0,
+        index_loops: match passes.index {
+            true => optimize::index_loops(program),
+            false => BTreeSet::new(),
+        },
+        register: None,
+    };
+    generator.lines.push(Line {
+        location: "".to_string(),
+        operation: "ORG".to_string(),
+        address: ORIGIN.to_string(),
+        card: None,
+    });
+
+    let mut targets = BTreeSet::new();
+    for (i, instruction) in program.instructions.iter().enumerate() {
+        match &instruction.op {
+            Op::GoTo(a) => {
+                targets.insert(*a);
+            }
+            Op::ComputedGoTo { targets: a, .. } => targets.extend(a),
+            Op::If { targets: a, .. } => targets.extend(a),
+            Op::IfSenseLight { targets: a, .. } | Op::IfSenseSwitch { targets: a, .. } => {
+                targets.extend(a)
+            }
+            Op::Do { .. } => {
+                targets.insert(i + 1);
+            }
+            _ => {}
+        }
+    }
+
+    for (i, instruction) in program.instructions.iter().enumerate() {
+        generator.card = Some(instruction.span.card);
+        if targets.contains(&i) {
+            generator.label(statement_symbol(program, i));
+        }
+        generator.statement(i, &instruction.op);
+        // the innermost loop ending here is tested first
+        for ending in generator.loops.remove(&i).unwrap_or_default().iter().rev() {
+            let body = statement_symbol(program, ending.body);
+            if ending.register {
+                // TXL goes round again while the index is no more than the limit
+                generator.emit("TXI", &format!("*+1,2,{}", ending.step));
+                generator.emit("TXL", &format!("{},2,{}", body, ending.limit));
+                generator.emit("SXD", &format!("{},2", ending.variable));
+                continue;
+            }
+            generator.emit("CLA", &ending.variable);
+            generator.emit("ADD", &ending.step);
+            generator.emit("STO", &ending.variable);
+            generator.emit("SUB", &ending.limit);
+            generator.emit("TMI", &body);
+            generator.emit("TZE", &body);
+        }
+        generator.invariants.retain(|x| x.0 != i);
+        if generator.register.as_ref().is_some_and(|x| x.1 == i) {
+            generator.register = None;
+        }
+    }
+    generator.card = None;
+    // a deck that ends without STOP stops at the end
+    generator.emit("HPR", "0");
+    generator.emit("TRA", "*-1");
+    let data = generator.lines.len();
+    generator.data();
+    generator.lines.push(Line {
+        location: "".to_string(),
+        operation: "END".to_string(),
+        address: ENTRY.to_string(),
+        card: None,
+    });
+
+    Listing {
+        lines: generator.lines,
+        externals: generator.externals,
+        data,
+    }
+}
+
+impl<'a> Generator<'a> {
+    fn emit(&mut self, operation: &str, address: &str) {
+        self.lines.push(Line {
+            location: self.pending.take().unwrap_or_default(),
+            operation: operation.to_string(),
+            address: address.to_string(),
+            card: self.card,
+        });
+    }
+
+    /// Names the next instruction. A second name for the same word is given
+    /// its own `BSS 0`.
+    fn label(&mut self, name: String) {
+        if let Some(earlier) = self.pending.replace(name) {
+            self.lines.push(Line {
+                location: earlier,
+                operation: "BSS".to_string(),
+                address: "0".to_string(),
+                card: self.card,
+            });
+        }
+    }
+
+    fn call(&mut self, routine: &str) {
+        self.externals.insert(routine.to_string());
+        self.emit("TSX", &format!("{},4", routine));
+    }
+
+    fn constant(&mut self, value: Value) -> String {
+        let count = self.constants.len();
+        self.constants
+            .entry(value.word())
+            .or_insert_with(|| format!("{}C", count + 1))
+            .clone()
+    }
+
+    fn temporary(&mut self) -> String {
+        self.depth += 1;
+        self.temporaries = self.temporaries.max(self.depth);
+        format!("{}T", self.depth)
+    }
+
+    fn release(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn statement(&mut self, index: usize, op: &Op) {
+        // the variable in index register B is stored before anything that
+        // leaves the loop, or reads it from core
+        if let Some((first, end, variable)) = self.register.clone() {
+            let leaves = optimize::transfers(op).iter().any(|x| *x < first || *x > end);
+            let reads = matches!(op, Op::ComputedGoTo { index, .. } if *index == variable);
+            if leaves || reads || matches!(op, Op::Pause(_) | Op::Stop(_)) {
+                self.emit("SXD", &format!("{},2", variable));
+            }
+        }
+        self.common.clear();
+        if self.passes.common {
+            // what is kept before the loop, or needs no code, is not kept again
+            let common = optimize::common_subexpressions(self.program, op)
+                .into_iter()
+                .filter(|x| self.kept(x).is_none() && !self.addressable(x))
+                .collect::<Vec<_>>();
+            for (i, expr) in common.into_iter().enumerate() {
+                self.expression(&expr);
+                let word = format!("{}E", i + 1);
+                self.emit("STO", &word);
+                self.common.push((expr, word));
+                self.common_words = self.common_words.max(i + 1);
+            }
+        }
+        match op {
+            Op::Assign {
+                variable,
+                subscripts,
+                value,
+            } => {
+                let fixed = is_fixed_name(variable);
+                if subscripts.is_empty() {
+                    self.variables.insert(variable.to_owned());
+                    self.value_for(value, fixed);
+                    self.emit("STO", variable);
+                    return;
+                }
+                let offset = offset(self.program, variable, subscripts);
+                if let Some(address) = self.indexed(variable, &offset) {
+                    self.value_for(value, fixed);
+                    self.emit("STO", &address);
+                } else if let Expr::Int(a) = offset {
+                    self.value_for(value, fixed);
+                    self.emit("STO", &displaced(variable, a));
+                } else {
+                    let (word, stored) = match self.kept(&offset) {
+                        Some(word) => (word, false),
+                        None => {
+                            self.expression(&offset);
+                            let temporary = self.temporary();
+                            self.emit("STO", &temporary);
+                            (temporary, true)
+                        }
+                    };
+                    self.value_for(value, fixed);
+                    self.emit("LXD", &format!("{},1", word));
+                    self.emit("STO", &format!("{},1", variable));
+                    if stored {
+                        self.release();
+                    }
+                }
+            }
+            Op::GoTo(a) => {
+                let target = statement_symbol(self.program, *a);
+                self.emit("TRA", &target);
+            }
+            Op::ComputedGoTo { targets, index } => {
+                // TRA Y,1 goes to Y minus the index, so the table is backwards
+                self.tables += 1;
+                let table = format!("{}J", self.tables);
+                self.variables.insert(index.to_owned());
+                self.emit("LXD", &format!("{},1", index));
+                self.emit("TRA", &format!("{},1", table));
+                for target in targets.iter().rev() {
+                    let target = statement_symbol(self.program, *target);
+                    self.emit("TRA", &target);
+                }
+                self.label(table);
+            }
+            Op::If { value, targets } => {
+                self.expression(value);
+                let [negative, zero, positive] = targets.map(|x| statement_symbol(self.program, x));
+                self.emit("TZE", &zero);
+                self.emit("TPL", &positive);
+                self.emit("TRA", &negative);
+            }
+            Op::IfSenseLight { light, targets } => {
+                // SLT skips the next instruction when the light was on, and turns it off
+                self.emit("SLT", &light.to_string());
+                self.emit("TRA", &statement_symbol(self.program, targets[1]));
+                self.emit("TRA", &statement_symbol(self.program, targets[0]));
+            }
+            Op::IfSenseSwitch { switch, targets } => {
+                // SWT skips the next instruction when the switch is down
+                self.emit("SWT", &switch.to_string());
+                self.emit("TRA", &statement_symbol(self.program, targets[1]));
+                self.emit("TRA", &statement_symbol(self.program, targets[0]));
+            }
+            Op::SenseLight(0) => self.emit("SLF", ""),
+            Op::SenseLight(a) => self.emit("SLN", &a.to_string()),
+            Op::Do {
+                end,
+                variable,
+                start,
+                limit,
+                step,
+            } => {
+                self.variables.insert(variable.to_owned());
+                self.value_for(start, true);
+                self.emit("STO", variable);
+                if self.passes.invariant {
+                    for expr in optimize::invariant_subscripts(self.program, index) {
+                        if self.invariants.iter().any(|x| x.1 == expr) {
+                            continue;
+                        }
+                        self.expression(&expr);
+                        self.invariant_words += 1;
+                        let word = format!("{}V", self.invariant_words);
+                        self.emit("STO", &word);
+                        self.invariants.push((*end, expr, word));
+                    }
+                }
+                let register = self.index_loops.contains(&index);
+                let (limit, step) = match (register, limit, step) {
+                    (true, Expr::Int(limit), Expr::Int(step)) => {
+                        self.emit("LXD", &format!("{},2", variable));
+                        self.register = Some((index + 1, *end, variable.to_owned()));
+                        (limit.to_string(), step.to_string())
+                    }
+                    _ => (self.loop_word(limit, "L"), self.loop_word(step, "I")),
+                };
+                self.loops.entry(*end).or_default().push(Loop {
+                    variable: variable.to_owned(),
+                    body: index + 1,
+                    limit,
+                    step,
+                    register,
+                });
+            }
+            Op::Print(items) => {
+                self.call("PRINT");
+                for item in items {
+                    self.expression(item);
+                    self.call(if item.is_fixed() { "PRFIX" } else { "PRFLT" });
+                }
+                self.call("PREND");
+            }
+            Op::Pause(a) => self.emit("HPR", &a.unwrap_or(0).to_string()),
+            Op::Stop(a) => {
+                self.emit("HPR", &a.unwrap_or(0).to_string());
+                self.emit("TRA", "*-1");
+            }
+            Op::Continue => {}
+        }
+    }
+
+    /// A DO limit or increment, worked out once when the loop starts.
+    fn loop_word(&mut self, expr: &Expr, kind: &str) -> String {
+        if let Some(operand) = self.operand(expr) {
+            return operand;
+        }
+        self.value_for(expr, true);
+        let word = format!("{}{}", self.loop_words.len() + 1, kind);
+        self.emit("STO", &word);
+        self.loop_words.push(word.clone());
+        word
+    }
+
+    /// Works out an expression, converted to the mode it is stored in.
+    fn value_for(&mut self, expr: &Expr, fixed: bool) {
+        self.expression(expr);
+        if expr.is_fixed() != fixed {
+            self.call(if fixed { "XFIXF" } else { "FLOATF" });
+        }
+    }
+
+    /// The address of a value that needs no code to reach.
+    fn operand(&mut self, expr: &Expr) -> Option<String> {
+        if let Some(word) = self.kept(expr) {
+            return Some(word);
+        }
+        match expr {
+            Expr::Variable(name) if self.in_register(name) => None,
+            Expr::Int(a) => Some(self.constant(Value::Fixed(*a))),
+            Expr::Float(a) => Some(self.constant(Value::Floating(*a))),
+            Expr::Variable(name) => {
+                self.variables.insert(name.to_owned());
+                Some(name.to_owned())
+            }
+            Expr::Element(name, subscripts) => {
+                let offset = offset(self.program, name, subscripts);
+                match offset {
+                    Expr::Int(a) => Some(displaced(name, a)),
+                    _ => self.indexed(name, &offset),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The word an expression was kept in, worked out earlier in the
+    /// statement or before the loop.
+    fn kept(&self, expr: &Expr) -> Option<String> {
+        let common = self.common.iter().map(|x| (&x.0, &x.1));
+        let invariants = self.invariants.iter().map(|x| (&x.1, &x.2));
+        common.chain(invariants).find(|x| x.0 == expr).map(|x| x.1.clone())
+    }
+
+    /// Whether an element is at an address known without working anything
+    /// out, or an expression is a subscript the index register takes care of.
+    fn addressable(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Element(name, subscripts) => {
+                let offset = offset(self.program, name, subscripts);
+                matches!(offset, Expr::Int(_)) || self.indexed(name, &offset).is_some()
+            }
+            _ => self.indexed("", expr).is_some(),
+        }
+    }
+
+    fn in_register(&self, name: &str) -> bool {
+        self.register.as_ref().is_some_and(|x| x.2 == name)
+    }
+
+    /// The address of an element subscripted by the variable in index
+    /// register B. The 704 subtracts the index from the address, and the
+    /// element `I` of `A` is `I - 1` below it, so it is at `A+1` less `I`.
+    fn indexed(&self, name: &str, offset: &Expr) -> Option<String> {
+        match offset {
+            Expr::Binary(Operator::Subtract, a, b) => match (a.as_ref(), b.as_ref()) {
+                (Expr::Variable(v), Expr::Int(1)) if self.in_register(v) => {
+                    Some(format!("{}+1,2", name))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Leaves the value of an expression in the accumulator.
+    fn expression(&mut self, expr: &Expr) {
+        if let Some(word) = self.kept(expr) {
+            self.emit("CLA", &word);
+            return;
+        }
+        match expr {
+            // the index is put in the decrement, where fixed point numbers are kept
+            Expr::Variable(name) if self.in_register(name) => self.emit("PXD", "0,2"),
+            Expr::Element(name, subscripts) => {
+                let offset = offset(self.program, name, subscripts);
+                if let Some(address) = self.indexed(name, &offset) {
+                    self.emit("CLA", &address);
+                } else if let Expr::Int(a) = offset {
+                    self.emit("CLA", &displaced(name, a));
+                } else if let Some(word) = self.kept(&offset) {
+                    self.emit("LXD", &format!("{},1", word));
+                    self.emit("CLA", &format!("{},1", name));
+                } else {
+                    self.expression(&offset);
+                    self.emit("PDX", "0,1");
+                    self.emit("CLA", &format!("{},1", name));
+                }
+            }
+            Expr::Int(_) | Expr::Float(_) | Expr::Variable(_) => {
+                let operand = self.operand(expr).unwrap_or_default();
+                self.emit("CLA", &operand);
+            }
+            Expr::Negate(a) => {
+                self.expression(a);
+                self.emit("CHS", "");
+            }
+            Expr::Call(name, args) => {
+                if let Some((params, body)) = self.program.functions.get(name) {
+                    // statement functions are written out where they are used
+                    let body = substitute(body, params, args);
+                    self.expression(&body);
+                } else {
+                    // a routine the library does not have is left for the
+                    // linker to report, as any other missing routine is
+                    self.library(name, args);
+                }
+            }
+            Expr::Binary(operator, left, right) => {
+                let fixed = left.is_fixed();
+                let (right, stored) = match self.operand(right) {
+                    Some(a) => (a, false),
+                    None => {
+                        self.expression(right);
+                        let temporary = self.temporary();
+                        self.emit("STO", &temporary);
+                        (temporary, true)
+                    }
+                };
+                self.expression(left);
+                match (operator, fixed) {
+                    (Operator::Add, true) => self.emit("ADD", &right),
+                    (Operator::Add, false) => self.emit("FAD", &right),
+                    (Operator::Subtract, true) => self.emit("SUB", &right),
+                    (Operator::Subtract, false) => self.emit("FSB", &right),
+                    (Operator::Multiply, true) => {
+                        // the product of two decrements is 2 to the 17th too small in the AC
+                        self.emit("XCA", "");
+                        self.emit("MPY", &right);
+                        self.emit("ALS", "17");
+                    }
+                    (Operator::Multiply, false) => {
+                        self.emit("XCA", "");
+                        self.emit("FMP", &right);
+                    }
+                    (Operator::Divide, true) => {
+                        // the dividend goes in the MQ with the AC cleared but keeping its sign
+                        self.emit("XCA", "");
+                        self.emit("PXD", "0,0");
+                        self.emit("LLS", "0");
+                        self.emit("DVP", &right);
+                        self.emit("XCA", "");
+                        self.emit("ALS", "18");
+                    }
+                    (Operator::Divide, false) => {
+                        self.emit("FDP", &right);
+                        self.emit("XCA", "");
+                    }
+                    (Operator::Power, _) => {
+                        self.emit("LDQ", &right);
+                        let routine = match (fixed, right_is_fixed(expr)) {
+                            (true, _) => "EXP1",
+                            (false, true) => "EXP2",
+                            (false, false) => "EXP3",
+                        };
+                        self.call(routine);
+                    }
+                }
+                if stored {
+                    self.release();
+                }
+            }
+        }
+    }
+
+    /// The first argument goes in the AC, the second in the MQ and the rest
+    /// in the words after the call.
+    fn library(&mut self, name: &str, args: &[Expr]) {
+        let mut rest = vec![];
+        for arg in args.iter().skip(1) {
+            self.expression(arg);
+            let temporary = self.temporary();
+            self.emit("STO", &temporary);
+            rest.push(temporary);
+        }
+        if let Some(first) = args.first() {
+            self.expression(first);
+        }
+        if let Some(second) = rest.first() {
+            self.emit("LDQ", second);
+        }
+        self.call(name);
+        for arg in rest.iter().skip(1) {
+            self.emit("PZE", arg);
+        }
+        for _ in &rest {
+            self.release();
+        }
+    }
+
+    /// Variables, constants, arrays and temporaries, after the code.
+    fn data(&mut self) {
+        let word = |location: String, operation: &str, address: String| Line {
+            location,
+            operation: operation.to_string(),
+            address,
+            card: None,
+        };
+        let mut lines = vec![];
+        for (value, symbol) in &self.constants {
+            lines.push(word(symbol.clone(), "OCT", word::octal(*value)));
+        }
+        for name in &self.variables {
+            if !self.program.arrays.contains_key(name) {
+                lines.push(word(name.clone(), "BSS", "1".to_string()));
+            }
+        }
+        let mut arrays = self.program.arrays.iter().collect::<Vec<_>>();
+        arrays.sort();
+        for (name, sizes) in arrays {
+            // the symbol is the first element, the rest are below it
+            let size = array_words(sizes).unwrap_or(1);
+            if size > 1 {
+                lines.push(word("".to_string(), "BSS", (size - 1).to_string()));
+            }
+            lines.push(word(name.clone(), "BSS", "1".to_string()));
+        }
+        for symbol in &self.loop_words {
+            lines.push(word(symbol.clone(), "BSS", "1".to_string()));
+        }
+        for i in 1..=self.temporaries {
+            lines.push(word(format!("{}T", i), "BSS", "1".to_string()));
+        }
+        for i in 1..=self.common_words {
+            lines.push(word(format!("{}E", i), "BSS", "1".to_string()));
+        }
+        for i in 1..=self.invariant_words {
+            lines.push(word(format!("{}V", i), "BSS", "1".to_string()));
+        }
+        self.lines.extend(lines);
+    }
+}
+
+/// How many words an element is below the first, as an expression.
+pub fn offset(program: &Program, name: &str, subscripts: &[Expr]) -> Expr {
+    let sizes = program.arrays.get(name).cloned().unwrap_or_default();
+    let mut offset: Option<Expr> = None;
+    let mut stride = 1;
+    for (i, subscript) in subscripts.iter().enumerate() {
+        let term = match subscript {
+            Expr::Int(a) => Expr::Int((a - 1) * stride),
+            _ => {
+                let term = Expr::Binary(Operator::Subtract, Box::new(subscript.clone()), Box::new(Expr::Int(1)));
+                if stride == 1 {
+                    term
+                } else {
+                    Expr::Binary(Operator::Multiply, Box::new(term), Box::new(Expr::Int(stride)))
+                }
+            }
+        };
+        offset = Some(match (offset, term) {
+            (None, term) => term,
+            (Some(Expr::Int(a)), Expr::Int(b)) => Expr::Int(a + b),
+            (Some(a), Expr::Int(0)) => a,
+            (Some(Expr::Int(0)), b) => b,
+            (Some(a), b) => Expr::Binary(Operator::Add, Box::new(a), Box::new(b)),
+        });
+        stride *= sizes.get(i).copied().unwrap_or(1);
+    }
+    offset.unwrap_or(Expr::Int(0))
+}
+
+/// An element at a constant distance below the first.
+fn displaced(name: &str, offset: i32) -> String {
+    if offset == 0 {
+        name.to_string()
+    } else {
+        format!("{}-{}", name, offset)
+    }
+}
+
+fn right_is_fixed(expr: &Expr) -> bool {
+    match expr {
+        Expr::Binary(_, _, right) => right.is_fixed(),
+        _ => false,
+    }
+}
+
+/// A statement function's body with its arguments in place of its parameters.
+pub fn substitute(body: &Expr, params: &[String], args: &[Expr]) -> Expr {
+    let apply = |x: &Expr| Box::new(substitute(x, params, args));
+    match body {
+        Expr::Variable(name) => match params.iter().position(|x| x == name) {
+            Some(i) => args.get(i).cloned().unwrap_or(Expr::Int(0)),
+            None => body.clone(),
+        },
+        Expr::Element(name, subscripts) => Expr::Element(
+            name.clone(),
+            subscripts.iter().map(|x| substitute(x, params, args)).collect(),
+        ),
+        Expr::Call(name, call_args) => Expr::Call(
+            name.clone(),
+            call_args.iter().map(|x| substitute(x, params, args)).collect(),
+        ),
+        Expr::Negate(a) => Expr::Negate(apply(a)),
+        Expr::Binary(operator, left, right) => Expr::Binary(*operator, apply(left), apply(right)),
+        Expr::Int(_) | Expr::Float(_) => body.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{link, unsupported};
+    use crate::card::import_cards;
+    use crate::compile::compile;
+    use crate::compute::process;
+    use crate::cpu::CoreSize;
+    use crate::storage::allocate;
+
+    fn program(deck: &str) -> Program {
+        let (text, line_data, _) = import_cards(deck);
+        let (statements, _) = process(text, line_data);
+        compile(&statements).unwrap()
+    }
+
+    const DECK: &str = "      DIMENSION A(5)
+      I = J + 1
+      IF (I - 2) 10, 20, 10
+   10 STOP 1
+   20 A(I) = SQRTF(X) * 2.0
+      GO TO (10, 20), I
+";
+
+    #[test]
+    fn a_program_is_lowered_as_the_fortran_compiler_lowered_it() {
+        let listing = generate(&program(DECK));
+        assert_eq!(
+            listing.to_string(),
+            "       ORG 100
+0ENTRY CLA J
+       ADD 1C
+       STO I
+       CLA I
+       SUB 2C
+       TZE 20S
+       TPL 10S
+       TRA 10S
+10S    HPR 1
+       TRA *-1
+20S    CLA I
+       SUB 1C
+       STO 1T
+       CLA X
+       TSX SQRTF,4
+       XCA
+       FMP 3C
+       LXD 1T,1
+       STO A,1
+       LXD I,1
+       TRA 1J,1
+       TRA 20S
+       TRA 10S
+1J     HPR 0
+       TRA *-1
+1C     OCT 000001000000
+2C     OCT 000002000000
+3C     OCT 202400000000
+I      BSS 1
+J      BSS 1
+X      BSS 1
+       BSS 4
+A      BSS 1
+1T     BSS 1
+       END 0ENTRY
+"
+        );
+        assert_eq!(listing.externals, BTreeSet::from(["SQRTF".to_string()]));
+        assert_eq!(listing.lines[listing.data].location, "1C");
+        // each instruction knows the card it was generated for
+        let cards: Vec<Option<usize>> = listing.lines.iter().map(|x| x.card).collect();
+        assert_eq!(cards[1..4], [Some(1); 3]);
+        assert_eq!(cards[11..20], [Some(4); 9]);
+        assert_eq!(cards[listing.data..], [None; 10]);
+    }
+
+    #[test]
+    fn a_line_reads_back_from_its_columns() {
+        for line in generate(&program(DECK)).lines {
+            assert_eq!(Line::parse(&line.to_string()), Line { card: None, ..line });
+        }
+    }
+
+    #[test]
+    fn listings_are_compared_card_by_card() {
+        let program = program(DECK);
+        let groups = compare(&generate(&program), &generate_with(&program, Passes::all()));
+        let cards: Vec<Option<usize>> = groups.iter().map(|x| x.0).collect();
+        // the ORG, the five executable cards, then the data
+        assert_eq!(
+            cards,
+            [None, Some(1), Some(2), Some(3), Some(4), Some(5), None]
+        );
+        assert_eq!(groups[1].1, "0ENTRY CLA J\n       ADD 1C\n       STO I\n");
+    }
+
+    #[test]
+    fn a_function_the_library_does_not_have_is_reported_missing() {
+        // checking rejects the deck, but a program can still be lowered
+        let program = program(
+            "      X = 1.0
+      Y = ROOTF(X, 2.0) + 1.0
+      STOP
+",
+        );
+        let listing = generate(&program);
+        let call = listing
+            .lines
+            .iter()
+            .position(|x| x.operation == "TSX")
+            .unwrap();
+        assert_eq!(listing.lines[call].address, "ROOTF,4");
+        assert_eq!(listing.lines[call - 1].operation, "LDQ");
+        assert!(listing.externals.contains("ROOTF"));
+
+        let diagnostics = unsupported(&link(&listing));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span.card, 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "ROOTF is not supported in a punched deck, the library has no ROOTF"
+        );
+        let (_, _, map) = allocate(&program, CoreSize::K4, Passes::default()).unwrap();
+        assert_eq!(map.missing, vec!["ROOTF".to_string()]);
+    }
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::compile::Program;
+use crate::compute::Value;
+use crate::machine::DoStatement;
+use crate::{LineData, IO704};
+
+/// Bumped whenever a field changes, old snapshots are refused rather than misread.
+pub const SNAPSHOT_VERSION: u32 = 4;
+
+/// A paused run, everything needed to carry on from where it stopped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    /// the deck as the editor holds it, so the cards can be shown again
+    pub text: String,
+    pub line_data: Vec<LineData>,
+    pub program: Program,
+    #[serde(with = "bits")]
+    pub variables: BTreeMap<String, Vec<Value>>,
+    /// the console and the printer output so far
+    pub io: IO704,
+    pub pc: usize,
+    pub do_statements: Vec<DoStatement>,
+    pub halted: bool,
+    pub executed: u64,
+    pub cycles: u64,
+    pub statement_cycles: Vec<u64>,
+    pub statement_counts: Vec<u64>,
+    pub breakpoints: BTreeSet<usize>,
+    /// the breakpoint the run was paused at, which continuing leaves
+    pub break_at: Option<usize>,
+}
+
+impl Snapshot {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    pub fn from_json(text: &str) -> Result<Snapshot, String> {
+        let value: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        match value.get("version").and_then(|x| x.as_u64()) {
+            Some(version) if version == SNAPSHOT_VERSION as u64 => {
+                serde_json::from_value(value).map_err(|e| e.to_string())
+            }
+            Some(version) => Err(format!(
+                "snapshot is version {}, this machine reads version {}",
+                version, SNAPSHOT_VERSION
+            )),
+            None => Err("not a snapshot, it has no version".to_string()),
+        }
+    }
+}
+
+/// Variables with floating point values kept as their bits, so an overflow
+/// to infinity or a NaN comes back as it was. JSON has no numbers for them.
+mod bits {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    use crate::compute::Value;
+
+    #[derive(Serialize, Deserialize)]
+    enum Word {
+        Fixed(i32),
+        Floating(u32),
+    }
+
+    pub fn serialize<S: Serializer>(
+        variables: &BTreeMap<String, Vec<Value>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let words: BTreeMap<&String, Vec<Word>> = variables
+            .iter()
+            .map(|(name, values)| {
+                let words = values.iter().map(|x| match *x {
+                    Value::Fixed(a) => Word::Fixed(a),
+                    Value::Floating(a) => Word::Floating(a.to_bits()),
+                });
+                (name, words.collect())
+            })
+            .collect();
+        words.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<String, Vec<Value>>, D::Error> {
+        let words = BTreeMap::<String, Vec<Word>>::deserialize(deserializer)?;
+        Ok(words
+            .into_iter()
+            .map(|(name, words)| {
+                let values = words.into_iter().map(|x| match x {
+                    Word::Fixed(a) => Value::Fixed(a),
+                    Word::Floating(a) => Value::Floating(f32::from_bits(a)),
+                });
+                (name, values.collect())
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::import_cards;
+    use crate::compile::compile;
+    use crate::compute::process;
+    use crate::machine::Machine;
+
+    /// The variables as words, so a NaN compares equal to itself.
+    fn words(machine: &Machine) -> BTreeMap<String, Vec<u64>> {
+        machine
+            .variables
+            .iter()
+            .map(|(name, values)| (name.clone(), values.iter().map(|x| x.word()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn a_resumed_run_ends_as_an_uninterrupted_one_does() {
+        let deck = "      DIMENSION A(4)
+      X = 1.0E30
+      DO 10 I = 1, 4
+      X = X * 1.0E10
+   10 A(I) = X - X
+      STOP
+";
+        let (text, line_data, _) = import_cards(deck);
+        let (statements, _) = process(text.clone(), line_data.clone());
+        let program = compile(&statements).unwrap();
+
+        let mut uninterrupted = Machine::new(program.clone());
+        uninterrupted.run_until_halt().unwrap();
+        // overflowed to infinity, and infinity less itself is not a number
+        assert_eq!(uninterrupted.variables["X"], vec![Value::Floating(f32::INFINITY)]);
+        assert!(uninterrupted.variables["A"][3].as_floating().is_nan());
+
+        let mut first = Machine::new(program);
+        first.run_for(8).unwrap();
+        let json = first.snapshot(text, line_data).to_json();
+        let mut resumed = Machine::restore(Snapshot::from_json(&json).unwrap());
+        resumed.run_until_halt().unwrap();
+        assert_eq!(words(&resumed), words(&uninterrupted));
+        assert_eq!(resumed.executed, uninterrupted.executed);
+        assert_eq!(resumed.cycles, uninterrupted.cycles);
+        assert_eq!(resumed.statement_counts, uninterrupted.statement_counts);
+    }
+}
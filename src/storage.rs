@@ -0,0 +1,324 @@
+//! Gives every statement, variable, constant and array of a compiled program
+//! a place in core, as the FORTRAN compiler did before punching its deck.
+//!
+//! The instructions and the library routines they call run up from the
+//! origin, and the data is put at the top of core, so a program fits if the
+//! two do not meet. The storage map lists where everything went, in octal as
+//! the compiler printed it.
+
+use std::fmt;
+
+use crate::assembler::{assemble, link, Assembly};
+use crate::compile::{array_words, Program};
+use crate::cpu::CoreSize;
+use crate::optimize::Passes;
+use crate::sap::{generate_with, Line, Listing};
+
+/// Where a compiled program was put in core.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageMap {
+    pub core_size: CoreSize,
+    /// the first word of the program and the word after its library routines
+    pub program: (usize, usize),
+    /// the first word of the data and the word after it
+    pub data: (usize, usize),
+    /// the card, label and first instruction of every executable statement
+    pub statements: Vec<(usize, i32, usize)>,
+    pub variables: Vec<(String, usize)>,
+    /// name, first element and the number of elements, stored down from the first
+    pub arrays: Vec<(String, usize, usize)>,
+    pub constants: Vec<(String, usize, u64)>,
+    /// temporaries, DO limits and increments, and computed GO TO tables
+    pub erasable: Vec<(String, usize)>,
+    pub library: Vec<(String, usize)>,
+    /// routines the library does not have, which the program cannot run without
+    pub missing: Vec<String>,
+}
+
+/// Assembles a program with its data at the top of core, and the map of where
+/// everything went, with the optimizations in `passes` made. The listing is
+/// the linked one the words came from.
+pub fn allocate(
+    program: &Program,
+    core_size: CoreSize,
+    passes: Passes,
+) -> Result<(Listing, Assembly, StorageMap), String> {
+    let generated = generate_with(program, passes);
+    let mut listing = link(&generated);
+    // assembled once where it stands to measure it
+    let measured = assemble(&listing)?;
+    let origin = measured.locations.first().copied().unwrap_or(0);
+    let code_end = measured.locations[listing.data];
+    let data_words = measured.locations.last().copied().unwrap_or(code_end) - code_end;
+    let core = core_size.words();
+    if code_end + data_words > core {
+        return Err(format!(
+            "program too large: {} words of instructions and {} of data do not fit in {} core",
+            code_end - origin,
+            data_words,
+            core_size
+        ));
+    }
+    let data_start = core - data_words;
+    listing.lines.insert(
+        listing.data,
+        Line {
+            location: "".to_string(),
+            operation: "ORG".to_string(),
+            address: data_start.to_string(),
+            card: None,
+        },
+    );
+    listing.data += 1;
+    let assembly = assemble(&listing)?;
+
+    let mut map = StorageMap {
+        core_size,
+        program: (origin, code_end),
+        data: (data_start, core),
+        statements: vec![],
+        variables: vec![],
+        arrays: vec![],
+        constants: vec![],
+        erasable: vec![],
+        library: vec![],
+        missing: listing.externals.iter().cloned().collect(),
+    };
+    for instruction in program.instructions.iter().filter(|x| x.executable) {
+        let first = listing.lines[..listing.data]
+            .iter()
+            .position(|x| x.card == Some(instruction.span.card));
+        if let Some(line) = first {
+            map.statements.push((
+                instruction.span.card,
+                instruction.label,
+                assembly.locations[line],
+            ));
+        }
+    }
+    for (symbol, &location) in &assembly.symbols {
+        let generated_symbol = symbol.starts_with(|x: char| x.is_ascii_digit());
+        if generated.externals.contains(symbol) {
+            if !listing.externals.contains(symbol) {
+                map.library.push((symbol.clone(), location));
+            }
+        } else if location < data_start {
+            continue;
+        } else if let Some(sizes) = program.arrays.get(symbol) {
+            let size = array_words(sizes).unwrap_or(1);
+            map.arrays.push((symbol.clone(), location, size));
+        } else if !generated_symbol {
+            map.variables.push((symbol.clone(), location));
+        } else if symbol.ends_with('C') {
+            let word = assembly.words.get(&location).copied().unwrap_or(0);
+            map.constants.push((symbol.clone(), location, word));
+        } else {
+            map.erasable.push((symbol.clone(), location));
+        }
+    }
+    // computed GO TO tables are among the instructions
+    for (symbol, &location) in &assembly.symbols {
+        if symbol.starts_with(|x: char| x.is_ascii_digit()) && symbol.ends_with('J') {
+            map.erasable.push((symbol.clone(), location));
+        }
+    }
+    for list in [&mut map.variables, &mut map.erasable, &mut map.library] {
+        list.sort_by_key(|x| x.1);
+    }
+    map.arrays.sort_by_key(|x| x.1);
+    map.constants.sort_by_key(|x| x.1);
+    Ok((listing, assembly, map))
+}
+
+impl fmt::Display for StorageMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "STORAGE MAP, {} CORE", self.core_size)?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "PROGRAM   {:05o} TO {:05o}",
+            self.program.0,
+            self.program.1.max(self.program.0 + 1) - 1
+        )?;
+        if self.data.1 > self.data.0 {
+            writeln!(
+                f,
+                "DATA      {:05o} TO {:05o}",
+                self.data.0,
+                self.data.1 - 1
+            )?;
+        }
+        writeln!(
+            f,
+            "UNUSED    {} WORDS",
+            self.data.0.saturating_sub(self.program.1)
+        )?;
+        writeln!(f)?;
+        writeln!(f, "STATEMENTS")?;
+        writeln!(f, " CARD  LABEL  LOCATION")?;
+        for (card, label, location) in &self.statements {
+            let label = match label {
+                0 => "".to_string(),
+                a => a.to_string(),
+            };
+            writeln!(f, "{:>5}  {:>5}  {:05o}", card + 1, label, location)?;
+        }
+        if !self.variables.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "VARIABLES")?;
+            for (name, location) in &self.variables {
+                writeln!(f, "{:<6} {:05o}", name, location)?;
+            }
+        }
+        if !self.arrays.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "ARRAYS")?;
+            writeln!(f, "NAME   FIRST   LAST  WORDS")?;
+            for (name, first, size) in &self.arrays {
+                writeln!(
+                    f,
+                    "{:<6} {:05o}  {:05o}  {:>5}",
+                    name,
+                    first,
+                    first + 1 - size,
+                    size
+                )?;
+            }
+        }
+        if !self.constants.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "CONSTANTS")?;
+            for (symbol, location, word) in &self.constants {
+                writeln!(
+                    f,
+                    "{:<6} {:05o}  {}",
+                    symbol,
+                    location,
+                    crate::word::octal(*word)
+                )?;
+            }
+        }
+        if !self.erasable.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "ERASABLE STORAGE AND TABLES")?;
+            for (symbol, location) in &self.erasable {
+                writeln!(f, "{:<6} {:05o}", symbol, location)?;
+            }
+        }
+        if !self.library.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "LIBRARY ROUTINES")?;
+            for (name, location) in &self.library {
+                writeln!(f, "{:<6} {:05o}", name, location)?;
+            }
+        }
+        if !self.missing.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "ENTRY POINTS TO SUBROUTINES NOT IN THE LIBRARY")?;
+            for name in &self.missing {
+                writeln!(f, "{}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::import_cards;
+    use crate::compile::compile;
+    use crate::compute::process;
+    use crate::sap::ORIGIN;
+
+    fn program(deck: &str) -> Program {
+        let (text, line_data, _) = import_cards(deck);
+        let (statements, _) = process(text, line_data);
+        compile(&statements).unwrap()
+    }
+
+    #[test]
+    fn the_data_goes_at_the_top_of_core() {
+        let program = program(
+            "      DIMENSION A(3)
+      DO 10 I = 1, 3
+   10 A(I) = SQRTF(FLOATF(I))
+      X = A(2) + 0.5
+      STOP
+",
+        );
+        let (_, assembly, map) = allocate(&program, CoreSize::K4, Passes::default()).unwrap();
+        assert_eq!(map.program.0, ORIGIN);
+        assert_eq!(map.data.1, CoreSize::K4.words());
+        assert_eq!(assembly.entry, ORIGIN);
+        // the array is stored down from its first element
+        assert_eq!(map.arrays, vec![("A".to_string(), 0o7776, 3)]);
+        assert_eq!(
+            map.to_string(),
+            "STORAGE MAP, 4K CORE
+
+PROGRAM   00144 TO 00200
+DATA      07767 TO 07777
+UNUSED    3958 WORDS
+
+STATEMENTS
+ CARD  LABEL  LOCATION
+    2         00144
+    3     10  00146
+    4         00164
+    5         00167
+
+VARIABLES
+I      07772
+X      07773
+
+ARRAYS
+NAME   FIRST   LAST  WORDS
+A      07776  07774      3
+
+CONSTANTS
+1C     07767  000001000000
+2C     07770  000003000000
+3C     07771  200400000000
+
+ERASABLE STORAGE AND TABLES
+1T     07777
+
+LIBRARY ROUTINES
+FLOATF 00173
+
+ENTRY POINTS TO SUBROUTINES NOT IN THE LIBRARY
+SQRTF
+"
+        );
+    }
+
+    fn array_of(size: usize) -> Program {
+        program(&format!(
+            "      DIMENSION A({})\n      A(1) = 1.0\n      STOP\n",
+            size
+        ))
+    }
+
+    #[test]
+    fn a_program_that_does_not_fit_is_too_large() {
+        let (_, _, map) = allocate(&array_of(10), CoreSize::K4, Passes::default()).unwrap();
+        let unused = map.data.0 - map.program.1;
+        // an array as big as the unused words fills core exactly
+        let (_, _, map) =
+            allocate(&array_of(10 + unused), CoreSize::K4, Passes::default()).unwrap();
+        assert_eq!(map.data.0, map.program.1);
+        assert!(map.to_string().contains("UNUSED    0 WORDS"));
+
+        let error = allocate(&array_of(11 + unused), CoreSize::K4, Passes::default()).unwrap_err();
+        assert_eq!(
+            error,
+            format!(
+                "program too large: {} words of instructions and {} of data do not fit in 4K core",
+                map.program.1 - map.program.0,
+                map.data.1 - map.data.0 + 1
+            )
+        );
+        assert!(allocate(&array_of(11 + unused), CoreSize::K8, Passes::default()).is_ok());
+    }
+}
@@ -0,0 +1,219 @@
+//! How long statements would have taken on a 704.
+//!
+//! Times are counted in machine cycles of 12 microseconds, from the
+//! instruction times in the 704 manual. A statement is costed as the
+//! instructions the FORTRAN compiler would have generated for it, so the
+//! figures are estimates of the right size rather than exact.
+
+use crate::compile::Op;
+use crate::compute::is_fixed_name;
+use crate::parse::{Expr, Operator};
+
+/// One machine cycle, in microseconds.
+pub const CYCLE_MICROSECONDS: u64 = 12;
+
+// instruction times, in cycles
+const CLA: u64 = 2;
+const STO: u64 = 2;
+const ADD: u64 = 2;
+const CHS: u64 = 1;
+const TRA: u64 = 1;
+/// a test such as TZE, TMI, SWT or SLT, and the transfer after it
+const TEST: u64 = 2;
+const SLN: u64 = 1;
+const HPR: u64 = 1;
+/// MPY and DVP take up to 20 and 22 cycles depending on the operands
+const MPY: u64 = 20;
+const DVP: u64 = 22;
+/// FAD varies with how far the operands have to be shifted to line up
+const FAD: u64 = 7;
+const FMP: u64 = 17;
+const FDP: u64 = 18;
+/// loading an index register and adding it to the address, for each subscript
+const SUBSCRIPT: u64 = 6;
+/// the TSX into a library routine and the return
+const CALL: u64 = 4;
+/// TIX at the end of a DO range
+pub const TIX: u64 = 2;
+
+/// Library routines, as a number of cycles for the whole routine.
+const POWER: u64 = 120;
+const ROOT: u64 = 150;
+const TRANSCENDENTAL: u64 = 300;
+const SIMPLE_FUNCTION: u64 = 10;
+/// converting one number to decimal for the printer
+const CONVERSION: u64 = 250;
+
+/// The 716 printer does 150 lines a minute, 400 ms a line.
+pub const PRINTER_LINE: u64 = 400_000 / CYCLE_MICROSECONDS;
+/// The 711 card reader reads 250 cards a minute, 240 ms a card.
+pub const CARD_READER_CARD: u64 = 240_000 / CYCLE_MICROSECONDS;
+/// A 727 tape unit moves 75 inches a second at 200 characters an inch, so a
+/// 120 character record and the gap it stops and starts in take about 18 ms.
+pub const TAPE_RECORD: u64 = 18_000 / CYCLE_MICROSECONDS;
+
+/// The cycles to run a statement once.
+pub fn cycles(op: &Op) -> u64 {
+    match op {
+        Op::Assign {
+            variable,
+            subscripts,
+            value,
+        } => {
+            let subscripts = subscripts.iter().map(|x| expression(x) + SUBSCRIPT).sum::<u64>();
+            // storing floating point into a fixed variable, or the other way, is a call
+            let conversion = if value.is_fixed() != is_fixed_name(variable) {
+                CALL + SIMPLE_FUNCTION
+            } else {
+                0
+            };
+            expression(value) + subscripts + conversion + STO
+        }
+        Op::GoTo(_) => TRA,
+        Op::ComputedGoTo { .. } => CLA + TRA + TRA,
+        Op::If { value, .. } => expression(value) + TEST + TEST,
+        Op::IfSenseLight { .. } | Op::IfSenseSwitch { .. } => TEST,
+        Op::SenseLight(_) => SLN,
+        Op::Do {
+            start, limit, step, ..
+        } => expression(start) + expression(limit) + expression(step) + STO * 3,
+        Op::Print(items) => {
+            items
+                .iter()
+                .map(|x| expression(x) + CALL + CONVERSION)
+                .sum::<u64>()
+                + PRINTER_LINE
+        }
+        Op::Pause(_) | Op::Stop(_) => HPR,
+        Op::Continue => 0,
+    }
+}
+
+/// The cycles to leave an expression in the accumulator.
+fn expression(expr: &Expr) -> u64 {
+    match expr {
+        Expr::Int(_) | Expr::Float(_) | Expr::Variable(_) => CLA,
+        Expr::Element(_, subscripts) => {
+            CLA + subscripts.iter().map(|x| expression(x) + SUBSCRIPT).sum::<u64>()
+        }
+        Expr::Call(name, args) => {
+            let args = args.iter().map(|x| expression(x) + STO).sum::<u64>();
+            let routine = match name.as_str() {
+                "SQRTF" => ROOT,
+                "SINF" | "COSF" | "ATANF" | "EXPF" | "LOGF" | "TANHF" => TRANSCENDENTAL,
+                _ => SIMPLE_FUNCTION,
+            };
+            args + CALL + routine
+        }
+        Expr::Negate(a) => expression(a) + CHS,
+        Expr::Binary(operator, left, right) => {
+            let operation = match (operator, left.is_fixed()) {
+                (Operator::Add | Operator::Subtract, true) => ADD,
+                (Operator::Add | Operator::Subtract, false) => FAD,
+                (Operator::Multiply, true) => MPY,
+                (Operator::Multiply, false) => FMP,
+                (Operator::Divide, true) => DVP,
+                (Operator::Divide, false) => FDP,
+                (Operator::Power, _) => CALL + POWER,
+            };
+            // the right operand is worked out and stored before the left is loaded
+            expression(left) + expression(right) + STO + operation
+        }
+    }
+}
+
+/// The reader and tape times, which no statement of the language uses: a
+/// program has no READ or tape statements, and is taken as already loaded.
+pub fn not_counted() -> String {
+    let milliseconds = |cycles: u64| cycles * CYCLE_MICROSECONDS / 1000;
+    format!(
+        "not counted: card reader {} ms a card, tape {} ms a record, no statement reads or writes them",
+        milliseconds(CARD_READER_CARD),
+        milliseconds(TAPE_RECORD)
+    )
+}
+
+/// Simulated time as the console clock shows it, hours to milliseconds.
+pub fn format_time(cycles: u64) -> String {
+    let milliseconds = cycles * CYCLE_MICROSECONDS / 1000;
+    format!(
+        "{}:{:02}:{:02}.{:03}",
+        milliseconds / 3_600_000,
+        milliseconds / 60_000 % 60,
+        milliseconds / 1000 % 60,
+        milliseconds % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::import_cards;
+    use crate::compile::{compile, Program};
+    use crate::compute::process;
+    use crate::machine::Machine;
+
+    fn program(deck: &str) -> Program {
+        let (text, line_data, _) = import_cards(deck);
+        let (statements, _) = process(text, line_data);
+        compile(&statements).unwrap()
+    }
+
+    #[test]
+    fn a_statement_costs_the_instructions_compiled_for_it() {
+        let program = program(
+            "      DIMENSION A(2)
+      I = J + 1
+      X = Y * Z
+      X = I
+      A(I) = 1.0
+      Y = SQRTF(X)
+      GO TO 10
+   10 STOP
+",
+        );
+        let cycles: Vec<u64> = program.instructions.iter().map(|x| x.cycles).collect();
+        assert_eq!(
+            cycles,
+            vec![
+                0,
+                CLA + CLA + STO + ADD + STO,
+                CLA + CLA + STO + FMP + STO,
+                CLA + CALL + SIMPLE_FUNCTION + STO,
+                CLA + CLA + SUBSCRIPT + STO,
+                CLA + STO + CALL + ROOT + STO,
+                TRA,
+                HPR,
+            ]
+        );
+    }
+
+    #[test]
+    fn a_run_adds_up_its_statements_and_loop_ends() {
+        let program = program(
+            "      DO 10 I = 1, 3
+   10 X = X + 1.0
+      STOP
+",
+        );
+        let per_statement: Vec<u64> = program.instructions.iter().map(|x| x.cycles).collect();
+        let mut machine = Machine::new(program);
+        machine.run_until_halt().unwrap();
+        let body = per_statement[1] + TIX;
+        assert_eq!(
+            machine.statement_cycles,
+            vec![per_statement[0], body * 3, HPR]
+        );
+        assert_eq!(machine.cycles, per_statement[0] + body * 3 + HPR);
+    }
+
+    #[test]
+    fn the_clock_shows_whole_milliseconds() {
+        assert_eq!(format_time(0), "0:00:00.000");
+        assert_eq!(format_time(83), "0:00:00.000");
+        assert_eq!(format_time(84), "0:00:00.001");
+        assert_eq!(format_time(83_334), "0:00:01.000");
+        assert_eq!(format_time(300_000_000 - 1), "0:59:59.999");
+        assert_eq!(format_time(300_000_000), "1:00:00.000");
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::compute::Value;
+use crate::machine::DoStatement;
+use crate::IO704;
+
+/// Statements kept for stepping back, older ones are forgotten.
+pub const TRACE_LIMIT: usize = 100_000;
+
+/// A value a statement stored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Write {
+    pub name: String,
+    pub index: usize,
+    /// None if the variable had never been stored
+    pub old: Option<Value>,
+    pub value: Value,
+}
+
+/// Something a statement did on the console or a device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Event {
+    Print(String),
+    SenseLights(Vec<bool>),
+    Display(i32),
+    StopLight(bool),
+}
+
+/// The parts of the machine a statement can change besides its variables.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Before {
+    pub do_statements: Vec<DoStatement>,
+    pub sense_lights: Vec<bool>,
+    pub display: i32,
+    pub stop_light: bool,
+    /// how much the printer had printed
+    pub printed: usize,
+    pub halted: bool,
+    pub cycles: u64,
+}
+
+impl Before {
+    pub fn events(&self, io: &IO704) -> Vec<Event> {
+        let mut events = vec![];
+        for line in io.print[self.printed..].lines() {
+            events.push(Event::Print(line.to_string()));
+        }
+        if io.sense_lights != self.sense_lights {
+            events.push(Event::SenseLights(io.sense_lights.clone()));
+        }
+        if io.display != self.display {
+            events.push(Event::Display(io.display));
+        }
+        if io.stop_light != self.stop_light {
+            events.push(Event::StopLight(io.stop_light));
+        }
+        events
+    }
+}
+
+/// One statement as it ran.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// the position of the statement in the program
+    pub statement: usize,
+    pub card: usize,
+    pub label: i32,
+    pub writes: Vec<Write>,
+    pub events: Vec<Event>,
+    /// what stepping back puts back, not exported
+    #[serde(skip)]
+    pub before: Before,
+}
+
+/// The trace as JSON, oldest statement first.
+pub fn export(trace: &VecDeque<Record>) -> String {
+    serde_json::to_string_pretty(trace).unwrap_or_default()
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::compute::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    /// Longest symbols first, so `<=` is not read as `<`.
+    const SYMBOLS: [(&'static str, Comparison); 6] = [
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("/=", Comparison::NotEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+        ("=", Comparison::Equal),
+    ];
+
+    fn symbol(&self) -> &'static str {
+        Comparison::SYMBOLS
+            .iter()
+            .find(|x| x.1 == *self)
+            .map_or("", |x| x.0)
+    }
+
+    fn holds(&self, a: f32, b: f32) -> bool {
+        match self {
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Equal => a == b,
+            Comparison::NotEqual => a != b,
+            Comparison::GreaterOrEqual => a >= b,
+            Comparison::Greater => a > b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Watch {
+    /// every store, even of the same value
+    Write,
+    Change,
+    /// a store that leaves the value meeting the condition
+    Condition(Comparison, f32),
+}
+
+/// A variable or array element to pause on, written `X`, `A(2,3) CHANGES`
+/// or `X > 100.0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub name: String,
+    /// as written, empty for a scalar
+    pub subscripts: Vec<i32>,
+    /// the element in storage order
+    pub index: usize,
+    pub watch: Watch,
+}
+
+impl Watchpoint {
+    pub fn parse(text: &str, arrays: &HashMap<String, Vec<i32>>) -> Result<Watchpoint, String> {
+        let text = text.trim().to_uppercase();
+        let end = text
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(text.len());
+        let name = text[..end].to_string();
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Err(format!("{} does not start with a variable", text));
+        }
+        let mut rest = text[end..].trim_start();
+
+        let mut subscripts = vec![];
+        if let Some(inside) = rest.strip_prefix('(') {
+            let Some(close) = inside.find(')') else {
+                return Err(format!("{} has no closing parenthesis", text));
+            };
+            for subscript in inside[..close].split(',') {
+                match subscript.trim().parse::<i32>() {
+                    Ok(a) => subscripts.push(a),
+                    Err(_) => return Err(format!("{} is not a constant subscript", subscript.trim())),
+                }
+            }
+            rest = inside[close + 1..].trim_start();
+        }
+        let index = element(&name, &subscripts, arrays)?;
+
+        let watch = if rest.is_empty() {
+            Watch::Write
+        } else if rest == "CHANGES" {
+            Watch::Change
+        } else {
+            let Some((symbol, comparison)) = Comparison::SYMBOLS
+                .iter()
+                .find(|x| rest.starts_with(x.0))
+            else {
+                return Err(format!("{} is not CHANGES or a comparison", rest));
+            };
+            let value = rest[symbol.len()..].trim();
+            match value.parse::<f32>() {
+                Ok(a) => Watch::Condition(*comparison, a),
+                Err(_) => return Err(format!("{} is not a number", value)),
+            }
+        };
+
+        Ok(Watchpoint {
+            name,
+            subscripts,
+            index,
+            watch,
+        })
+    }
+
+    /// The variable or element, as it was written.
+    pub fn target(&self) -> String {
+        if self.subscripts.is_empty() {
+            self.name.clone()
+        } else {
+            let subscripts = self
+                .subscripts
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            format!("{}({})", self.name, subscripts.join(","))
+        }
+    }
+
+    /// Whether storing `new` over `old` pauses the program.
+    pub fn hits(&self, old: Value, new: Value) -> bool {
+        match self.watch {
+            Watch::Write => true,
+            Watch::Change => old != new,
+            Watch::Condition(comparison, value) => comparison.holds(new.as_floating(), value),
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.watch {
+            Watch::Write => write!(f, "{}", self.target()),
+            Watch::Change => write!(f, "{} CHANGES", self.target()),
+            Watch::Condition(comparison, value) => {
+                write!(f, "{} {} {:?}", self.target(), comparison.symbol(), value)
+            }
+        }
+    }
+}
+
+/// Where an element sits in its array, which is stored by columns.
+fn element(name: &str, subscripts: &[i32], arrays: &HashMap<String, Vec<i32>>) -> Result<usize, String> {
+    let sizes = arrays.get(name).map_or(&[][..], |x| &x[..]);
+    if subscripts.len() != sizes.len() {
+        return Err(if sizes.is_empty() {
+            format!("{} is not dimensioned", name)
+        } else {
+            format!("{} has {} subscripts", name, sizes.len())
+        });
+    }
+    let mut index = 0;
+    let mut stride = 1;
+    for (subscript, size) in subscripts.iter().zip(sizes) {
+        if *subscript < 1 || subscript > size {
+            return Err(format!("subscript {} of {} is outside 1 to {}", subscript, name, size));
+        }
+        index += (subscript - 1) as usize * stride;
+        stride *= *size as usize;
+    }
+    Ok(index)
+}
+
+/// A watchpoint the last statement set off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub watchpoint: Watchpoint,
+    /// the card of the statement that stored the value
+    pub card: usize,
+    pub old: Value,
+    pub value: Value,
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "watchpoint {}: {} = {} (was {})",
+            self.watchpoint,
+            self.watchpoint.target(),
+            self.value,
+            self.old
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::import_cards;
+    use crate::compile::compile;
+    use crate::compute::process;
+    use crate::machine::{Machine, Step};
+
+    fn arrays() -> HashMap<String, Vec<i32>> {
+        HashMap::from([("A".to_string(), vec![2, 3])])
+    }
+
+    #[test]
+    fn parses_a_variable_an_element_and_what_to_wait_for() {
+        let x = Watchpoint::parse("x", &arrays()).unwrap();
+        assert_eq!((x.name.as_str(), x.index, &x.watch), ("X", 0, &Watch::Write));
+        // stored by columns, so A(2,3) is the sixth element
+        let a = Watchpoint::parse("A(2, 3) CHANGES", &arrays()).unwrap();
+        assert_eq!((a.index, &a.watch), (5, &Watch::Change));
+        assert_eq!(a.to_string(), "A(2,3) CHANGES");
+        let y = Watchpoint::parse("Y >= 1.5", &arrays()).unwrap();
+        assert_eq!(y.watch, Watch::Condition(Comparison::GreaterOrEqual, 1.5));
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_watchpoint() {
+        let arrays = arrays();
+        assert_eq!(
+            Watchpoint::parse("A(3,1)", &arrays),
+            Err("subscript 3 of A is outside 1 to 2".to_string())
+        );
+        assert_eq!(
+            Watchpoint::parse("A(1)", &arrays),
+            Err("A has 2 subscripts".to_string())
+        );
+        assert_eq!(
+            Watchpoint::parse("B(1)", &arrays),
+            Err("B is not dimensioned".to_string())
+        );
+        assert_eq!(
+            Watchpoint::parse("X GROWS", &arrays),
+            Err("GROWS is not CHANGES or a comparison".to_string())
+        );
+        assert_eq!(
+            Watchpoint::parse("X > Y", &arrays),
+            Err("Y is not a number".to_string())
+        );
+    }
+
+    #[test]
+    fn pauses_when_a_store_meets_the_condition() {
+        let deck = "      X = 0.0
+      DO 10 I = 1, 10
+   10 X = X + 1.0
+      STOP
+";
+        let (text, line_data, _) = import_cards(deck);
+        let (statements, _) = process(text, line_data);
+        let mut machine = Machine::new(compile(&statements).unwrap());
+        let arrays = machine.program.arrays.clone();
+        machine.watchpoints.push(Watchpoint::parse("X > 2.5", &arrays).unwrap());
+        assert_eq!(machine.run_for(1000).unwrap(), Step::Watch);
+        assert_eq!(machine.hits.len(), 1);
+        assert_eq!(machine.hits[0].card, 2);
+        assert_eq!(machine.hits[0].old, Value::Floating(2.0));
+        assert_eq!(machine.hits[0].value, Value::Floating(3.0));
+        assert_eq!(machine.run_for(1000).unwrap(), Step::Watch);
+        assert_eq!(machine.hits[0].value, Value::Floating(4.0));
+    }
+
+    #[test]
+    fn a_change_watch_ignores_storing_the_same_value() {
+        let deck = "      I = 1
+      I = 1
+      I = 2
+      STOP
+";
+        let (text, line_data, _) = import_cards(deck);
+        let (statements, _) = process(text, line_data);
+        let mut machine = Machine::new(compile(&statements).unwrap());
+        let arrays = machine.program.arrays.clone();
+        machine.watchpoints.push(Watchpoint::parse("I CHANGES", &arrays).unwrap());
+        assert_eq!(machine.run_for(1000).unwrap(), Step::Watch);
+        assert_eq!(machine.hits[0].card, 0);
+        assert_eq!(machine.run_for(1000).unwrap(), Step::Watch);
+        assert_eq!(machine.hits[0].card, 2);
+        assert_eq!(machine.run_for(1000).unwrap(), Step::Halted);
+    }
+}
@@ -0,0 +1,70 @@
+//! 36 bit words as the 704 holds them, in the low bits of a `u64`.
+//!
+//! Bits are numbered as IBM numbered them: S is the sign, then bits 1 to 35
+//! from most to least significant.
+
+/// The sign bit, S.
+pub const SIGN: u64 = 1 << 35;
+pub const MAGNITUDE: u64 = SIGN - 1;
+pub const WORD: u64 = SIGN | MAGNITUDE;
+
+/// Fixed point numbers are kept in the decrement, bits 3 to 17.
+const DECREMENT_SHIFT: u32 = 18;
+const DECREMENT: u64 = 0o77777 << DECREMENT_SHIFT;
+
+/// Floating point words have an excess 128 characteristic in bits 1 to 8 and
+/// a 27 bit fraction in bits 9 to 35.
+const FRACTION_BITS: u32 = 27;
+const FRACTION: u64 = (1 << FRACTION_BITS) - 1;
+const CHARACTERISTIC_BIAS: i32 = 128;
+
+pub fn fixed_word(value: i32) -> u64 {
+    let sign = if value < 0 { SIGN } else { 0 };
+    sign | (((value.unsigned_abs() as u64) << DECREMENT_SHIFT) & DECREMENT)
+}
+
+pub fn word_fixed(word: u64) -> i32 {
+    let magnitude = ((word & DECREMENT) >> DECREMENT_SHIFT) as i32;
+    if word & SIGN != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+pub fn floating_word(value: f32) -> u64 {
+    if value == 0.0 || !value.is_finite() {
+        return if value.is_sign_negative() { SIGN } else { 0 };
+    }
+    let sign = if value < 0.0 { SIGN } else { 0 };
+    // normalised so the fraction is at least a half
+    let mut exponent = value.abs().log2().floor() as i32 + 1;
+    let mut fraction = (value.abs() as f64 / 2f64.powi(exponent) * (1u64 << FRACTION_BITS) as f64)
+        .round() as u64;
+    if fraction > FRACTION {
+        fraction >>= 1;
+        exponent += 1;
+    }
+    let characteristic = (exponent + CHARACTERISTIC_BIAS).clamp(0, 255) as u64;
+    sign | (characteristic << FRACTION_BITS) | (fraction & FRACTION)
+}
+
+pub fn word_floating(word: u64) -> f32 {
+    let characteristic = ((word & MAGNITUDE) >> FRACTION_BITS) as i32;
+    let fraction = (word & FRACTION) as f64 / (1u64 << FRACTION_BITS) as f64;
+    let value = (fraction * 2f64.powi(characteristic - CHARACTERISTIC_BIAS)) as f32;
+    if word & SIGN != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+/// Twelve octal digits, as the console lights showed a word.
+pub fn octal(word: u64) -> String {
+    format!("{:012o}", word & WORD)
+}
+
+pub fn parse_octal(text: &str) -> Option<u64> {
+    u64::from_str_radix(text, 8).ok().filter(|x| *x <= WORD)
+}
@@ -0,0 +1,237 @@
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{DedicatedWorkerGlobalScope, MessageEvent};
+
+use crate::compile::compile;
+use crate::compute::process;
+use crate::diagnostics::has_errors;
+use crate::machine::{Machine, RuntimeError, Step};
+use crate::LineData;
+use crate::SLICE;
+
+/// Messages from the page to the worker.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ToWorker {
+    /// the deck to compile, as the editor holds it
+    Load {
+        text: String,
+        line_data: Vec<LineData>,
+    },
+    Start,
+    SenseSwitches(Vec<bool>),
+    Stop,
+}
+
+/// Messages from the worker to the page.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum FromWorker {
+    /// the worker is listening, anything sent before this would have been lost
+    Ready,
+    Console {
+        sense_lights: Vec<bool>,
+        display: i32,
+        stop_light: bool,
+        /// the simulated clock
+        cycles: u64,
+    },
+    /// a line off the printer
+    Print(String),
+    Halted {
+        executed: u64,
+    },
+    Interrupted {
+        card: usize,
+        executed: u64,
+    },
+    /// the error with the machine as it was, shown as a run in the page shows it
+    Error(RuntimeError),
+}
+
+#[wasm_bindgen]
+extern "C" {
+    /// Starts a worker running this same module, see `startWorker` in index.html.
+    #[wasm_bindgen(catch, js_name = startWorker)]
+    fn start_worker() -> Result<web_sys::Worker, JsValue>;
+}
+
+/// A worker started by `spawn`.
+pub struct Handle {
+    worker: web_sys::Worker,
+    /// what was sent before the worker was ready, `None` once it is
+    waiting: Rc<RefCell<Option<Vec<String>>>>,
+}
+
+/// Starts a worker and passes everything it sends to `receive`.
+///
+/// The worker only listens once its module has loaded, so messages sent
+/// before it says it is `Ready` are held back until then.
+pub fn spawn(mut receive: impl FnMut(FromWorker) + 'static) -> Option<Handle> {
+    let worker = start_worker().ok()?;
+    let waiting = Rc::new(RefCell::new(Some(Vec::<String>::new())));
+    let onmessage = Closure::<dyn FnMut(MessageEvent)>::new({
+        let worker = worker.clone();
+        let waiting = waiting.clone();
+        move |event: MessageEvent| {
+            let Some(message) = event.data().as_string().and_then(|x| serde_json::from_str(&x).ok())
+            else {
+                return;
+            };
+            if matches!(message, FromWorker::Ready) {
+                for text in waiting.borrow_mut().take().unwrap_or_default() {
+                    let _ = worker.post_message(&JsValue::from_str(&text));
+                }
+            } else {
+                receive(message);
+            }
+        }
+    });
+    worker.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    onmessage.forget();
+    Some(Handle { worker, waiting })
+}
+
+pub fn send(handle: &Handle, message: &ToWorker) {
+    let Ok(text) = serde_json::to_string(message) else {
+        return;
+    };
+    match handle.waiting.borrow_mut().as_mut() {
+        Some(waiting) => waiting.push(text),
+        None => {
+            let _ = handle.worker.post_message(&JsValue::from_str(&text));
+        }
+    }
+}
+
+/// The machine inside the worker.
+struct Runner {
+    machine: Machine,
+    running: bool,
+    /// counts the runs started and stopped, so a slice left over from an
+    /// earlier run does nothing
+    generation: u64,
+    /// how much of the printer output has been sent to the page
+    printed: usize,
+}
+
+fn scope() -> DedicatedWorkerGlobalScope {
+    js_sys::global().unchecked_into()
+}
+
+fn post(message: &FromWorker) {
+    if let Ok(text) = serde_json::to_string(message) {
+        let _ = scope().post_message(&JsValue::from_str(&text));
+    }
+}
+
+/// Runs in the worker, in place of the page.
+pub fn main() {
+    let runner = Rc::new(RefCell::new(Runner {
+        machine: Machine::new(Default::default()),
+        running: false,
+        generation: 0,
+        printed: 0,
+    }));
+    let onmessage = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+        if let Some(message) = event.data().as_string().and_then(|x| serde_json::from_str(&x).ok()) {
+            receive(&runner, message);
+        }
+    });
+    scope().set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    onmessage.forget();
+    post(&FromWorker::Ready);
+}
+
+fn receive(runner: &Rc<RefCell<Runner>>, message: ToWorker) {
+    let mut state = runner.borrow_mut();
+    match message {
+        ToWorker::Load { text, line_data } => {
+            let (statements, diagnostics) = process(text, line_data);
+            let program = if has_errors(&diagnostics) {
+                None
+            } else {
+                compile(&statements).ok()
+            };
+            state.machine.program = program.unwrap_or_default();
+            state.machine.reset();
+            state.running = false;
+            state.generation += 1;
+        }
+        ToWorker::Start => {
+            state.machine.reset();
+            state.printed = 0;
+            state.running = true;
+            state.generation += 1;
+            let generation = state.generation;
+            drop(state);
+            schedule(runner.clone(), generation);
+        }
+        ToWorker::SenseSwitches(a) => state.machine.io.sense_switches = a,
+        ToWorker::Stop => {
+            if state.running {
+                state.running = false;
+                state.generation += 1;
+                state.machine.interrupt();
+                post(&FromWorker::Interrupted {
+                    card: state.machine.card().unwrap_or(0),
+                    executed: state.machine.executed,
+                });
+            }
+        }
+    }
+}
+
+/// Runs the next slice of run `generation` once the worker has looked at its messages.
+fn schedule(runner: Rc<RefCell<Runner>>, generation: u64) {
+    let callback = Closure::once_into_js(move || slice(runner, generation));
+    let _ = scope()
+        .set_timeout_with_callback_and_timeout_and_arguments_0(callback.unchecked_ref(), 0);
+}
+
+fn slice(runner: Rc<RefCell<Runner>>, generation: u64) {
+    let mut state = runner.borrow_mut();
+    if !state.running || state.generation != generation {
+        return;
+    }
+    let result = state.machine.run_for(SLICE);
+
+    // printer lines go to the page as they are printed
+    let printed = state.printed;
+    state.printed = state.machine.io.print.len();
+    for line in state.machine.io.print[printed..].lines() {
+        post(&FromWorker::Print(line.to_string()));
+    }
+
+    let console = |machine: &Machine| FromWorker::Console {
+        sense_lights: machine.io.sense_lights.clone(),
+        display: machine.io.display,
+        stop_light: machine.io.stop_light,
+        cycles: machine.cycles,
+    };
+    match result {
+        // the worker has no breakpoints or watchpoints, the console is sent
+        // every slice to keep the clock going
+        Ok(Step::Ran) | Ok(Step::Break) | Ok(Step::Watch) | Ok(Step::Io) => {
+            post(&console(&state.machine))
+        }
+        Ok(Step::Halted) => {
+            state.running = false;
+            post(&console(&state.machine));
+            post(&FromWorker::Halted {
+                executed: state.machine.executed,
+            });
+        }
+        Err(error) => {
+            state.running = false;
+            post(&console(&state.machine));
+            post(&FromWorker::Error(error));
+        }
+    }
+
+    if state.running {
+        drop(state);
+        schedule(runner, generation);
+    }
+}